@@ -4,66 +4,72 @@
 //! It provides functionality to:
 //! - Submit queries to Athena
 //! - Poll for query completion
-//! - Retrieve query results
-//! - Extract database context from SQL comments
+//! - Print the query results (`--result-format`), see [`crate::result`]
+//! - Print the execution plan without calling AWS (`--dry-run`)
+//! - Stop or continue when a statement fails (`--on-error`), the exit code is non-zero
+//!   if any statement did not succeed, see [`crate::error`]
+//! - Report why a statement failed (`--error-format`), see [`crate::failure`]
+//! - Stop the queries in flight on Ctrl-C or SIGTERM, see [`crate::signal`]
+//! - Run up to `--concurrency` statements at the same time, see [`crate::schedule`]
+//! - Retry the statements that fail with a transient error, see [`crate::retry`]
+//! - Execute a saved plan file, see [`crate::plan`]
+//! - Skip the statements that were already applied (`--force` runs them), see [`crate::state`]
+//! - Resume a run that did not complete (`--resume`), see [`crate::journal`]
+//! - Submit the statements without waiting for them (`--no-wait`), see [`crate::execution`]
 //!
-//! # Database Context
-//!
-//! You can specify the target database using SQL comments:
-//!
-//! ```sql
-//! -- Database: my_database
-//! CREATE TABLE example (id INT);
-//! ```
-//!
-//! or
-//!
-//! ```sql
-//! /* Database: my_database */
-//! CREATE TABLE example (id INT);
-//! ```
+//! The target database and the options of each statement (catalog, workgroup, output
+//! location, timeout) are set with SQL comments, see [`crate::directive`].
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, Context, Result};
 use aws_sdk_athena::{
     operation::get_query_execution::GetQueryExecutionOutput,
-    types::{QueryExecutionContext, QueryExecutionState, ResultConfiguration, ResultSet},
+    types::{QueryExecution, QueryExecutionContext, QueryExecutionState, ResultConfiguration},
     Client,
 };
+use chrono::Utc;
 use devtimer::DevTime;
-use log::{error, info};
-use once_cell::sync::Lazy;
-use regex::Regex;
-use std::{collections::HashMap, env, path::PathBuf};
-use tokio::time::{sleep, Duration};
-
-use crate::utils::pretty_print;
+use log::{error, info, warn};
+use std::{collections::HashMap, path::PathBuf};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::build::Build;
+use crate::config::{self, AwsArgs};
+use crate::error::ErrorKind;
+use crate::failure::{ErrorFormat, FailureReport};
+use crate::journal::{Journal, RunStatus};
+use crate::plan::{self, ExecutionContext, ExecutionPlan, PlannedStatement};
+use crate::poll::{Backoff, PollArgs};
+use crate::result::ResultArgs;
+use crate::retry::{client_request_token, is_transient, RetryArgs};
+use crate::schedule::{format_indexes, Scheduler};
+use crate::signal::Shutdown;
+use crate::state::{self, StateStore, Target};
+use crate::utils::{new_run_id, pretty_print};
+use crate::vars::VarArgs;
 
 // Constants
-const QUERY_POLL_INTERVAL_SECS: u64 = 5;
-const SQL_STATEMENT_SEPARATOR: char = ';';
-
-// Compile regex patterns once and reuse them for extracting database names from SQL
-static DATABASE_PATTERNS: Lazy<Vec<Regex>> = Lazy::new(|| {
-    vec![
-        // Matches: -- Database: db_name
-        Regex::new(r"(?i)--\s+Database:\s(.*)").expect("invalid regex pattern"),
-        // Matches: /* Database: db_name */
-        Regex::new(r"(?i)/*\s+Database:\s([^\s]+)\s\*/").expect("invalid regex pattern"),
-    ]
-});
+const REQUEST_MAX_ATTEMPTS: u32 = 10;
+const REQUEST_MIN_DELAY: Duration = Duration::from_secs(1);
+const REQUEST_MAX_DELAY: Duration = Duration::from_secs(30);
 
 #[derive(clap::Args, Debug, Clone)]
 pub struct Apply {
     /// Target path to render. If the target path is a directory,
-    /// the root folder must contains the index.sql file
-    pub file: PathBuf,
+    /// the root folder must contains the index.sql file.
+    /// Optional when the `--env` environment defines a `target`.
+    /// A plan file (`.json`) created by `athena plan` is executed as saved
+    #[arg(required_unless_present = "env")]
+    pub file: Option<PathBuf>,
 
     /// Change the context current working dir
     #[arg(long, short)]
     pub context: Option<PathBuf>,
 
-    /// Dry-run
-    #[arg(global = true, long, short)]
+    /// Dry-run: render and split the SQL, then print the execution plan
+    /// (kind, database, workgroup, output location and size of each statement).
+    /// Nothing is sent to AWS and no credentials are needed
+    #[arg(global = true, long, short, num_args = 0..=1, default_missing_value = "true")]
     pub dry_run: Option<bool>,
 
     /// AWS Profile
@@ -91,62 +97,407 @@ pub struct Apply {
     /// No pretty print for SQL
     #[arg(long)]
     pub no_pretty: Option<bool>,
+
+    /// Environment defined in athena.toml, such as `prd`.
+    /// The AWS settings, variables and target path of the environment are used
+    /// unless they are given on the command line
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    /// What to do when a statement fails, is cancelled or times out:
+    /// `stop` skips the remaining statements, `continue` runs them anyway.
+    /// The exit code is non-zero in both cases
+    #[arg(long, value_enum, default_value_t = OnError::Stop)]
+    pub on_error: OnError,
+
+    /// Maximum number of statements in flight at the same time.
+    /// Use `-- athena: barrier` to make a statement wait for the previous ones
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub concurrency: u16,
+
+    /// Format of the failure reports printed to stderr: `human` or `json` (one object per line)
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+
+    /// Stop a statement that runs longer than this duration, such as `30s` or `10m`.
+    /// A `-- athena: timeout=<duration>` directive overrides it for one statement
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    #[command(flatten)]
+    pub poll: PollArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+
+    #[command(flatten)]
+    pub result: ResultArgs,
+
+    /// Run the statements that were already applied, see `athena state list`
+    #[arg(long)]
+    pub force: bool,
+
+    /// Continue a run that did not complete, such as `20240101T120000Z-1a2b3c`:
+    /// the statements that succeeded are not run again, they must not have changed
+    #[arg(long, value_name = "RUN_ID")]
+    pub resume: Option<String>,
+
+    /// Submit up to `--concurrency` statements and exit without waiting for them. The statements
+    /// that depend on another one are not started. Wait for the run with `athena wait --run`
+    #[arg(long, conflicts_with = "timeout")]
+    pub no_wait: bool,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+/// How queries are submitted and polled, shared by the commands that run SQL
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Print the SQL without syntax highlighting
+    pub no_pretty: bool,
+    /// Do not print the SQL
+    pub quiet: bool,
+    pub poll: PollArgs,
+    pub retry: RetryArgs,
+}
+
+impl From<&Apply> for QueryOptions {
+    fn from(args: &Apply) -> Self {
+        Self {
+            no_pretty: args.no_pretty.unwrap_or_default(),
+            quiet: false,
+            poll: args.poll.clone(),
+            retry: args.retry.clone(),
+        }
+    }
+}
+
+/// Behavior when a statement does not succeed
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Skip the remaining statements
+    #[default]
+    Stop,
+    /// Run the remaining statements
+    Continue,
 }
 
 pub async fn call(args: Apply) -> Result<()> {
-    let build_args = crate::build::Build {
-        file: args.file.clone(),
-        out: None,
-        context: args.context.clone(),
-        no_pretty: None,
+    let aws_args = AwsArgs {
+        profile: args.profile.clone(),
+        region: args.region.clone(),
+        workgroup: args.workgroup.clone(),
+        output_location: args.output_location.clone(),
     };
 
-    let sql = crate::build::build(build_args)?;
-    if args.no_pretty.unwrap_or_default() {
-        print!("{}", sql);
-    } else {
-        pretty_print(sql.as_bytes());
+    let (settings, plan) = match args.file.as_deref().filter(|f| plan::is_plan_file(f)) {
+        Some(path) => {
+            info!("Applying the plan file {}", path.display());
+            let (settings, plan) = plan::load_plan_file(path, &aws_args)?;
+            let plan = plan.with_default_timeout(args.timeout);
+            print!("{}", plan);
+            (settings, plan)
+        }
+        None => {
+            let build_args = Build {
+                file: args.file.clone(),
+                out: None,
+                context: args.context.clone(),
+                no_pretty: None,
+                env: args.env.clone(),
+                vars: args.vars.clone(),
+            };
+
+            let (settings, sql, plan) = plan::render_plan(&build_args, &aws_args)?;
+            let plan = plan.with_default_timeout(args.timeout);
+            if args.no_pretty.unwrap_or_default() {
+                print!("{}", sql);
+            } else {
+                pretty_print(sql.as_bytes());
+            }
+
+            if args.dry_run.unwrap_or_default() {
+                println!("\n");
+                print!("{}", plan);
+            }
+
+            (settings, plan)
+        }
+    };
+
+    if args.dry_run.unwrap_or_default() {
+        return Ok(());
     }
 
-    // Set AWS_PROFILE
-    if let Some(ref profile) = args.profile {
-        std::env::set_var("AWS_PROFILE", profile);
+    let shared_config = config::load_aws_config(&settings).await;
+    let client = Client::new(&shared_config);
+    args.result.create_out()?;
+
+    // Ledger of the statements already applied
+    let store = StateStore::resolve(args.context.as_deref(), settings.env.as_deref())?;
+    let mut ledger = store.load(&shared_config).await?;
+
+    // Journal of the run, with the statements finished by the resumed run
+    let run_id = new_run_id();
+    let mut journal = Journal {
+        env: settings.env.clone(),
+        profile: settings.profile(),
+        region: shared_config.region().map(|r| r.to_string()),
+        ..Journal::new(&run_id, &plan)
+    };
+    if let Some(resume) = &args.resume {
+        journal.resume(&Journal::read(&store.dir, resume)?)?;
     }
+    // The ledger entries are specific to the AWS account and region
+    let target = Target::resolve(&shared_config).await;
+    if !args.force {
+        for s in &plan.statements {
+            if journal.status(s.index) == Some(RunStatus::Pending)
+                && state::is_recorded(s)
+                && ledger.contains(s, &target)
+            {
+                journal.set(s.index, RunStatus::AlreadyApplied, None);
+            }
+        }
+    }
+    let plan = pending_statements(plan, &journal);
+    journal.write(&store.dir)?;
+    info!("Run id: {}", run_id);
 
-    // Set AWS_DEFAULT_REGION
-    if let Some(ref region) = args.region {
-        std::env::set_var("AWS_DEFAULT_REGION", region);
+    if plan.statements.is_empty() {
+        info!("Nothing to apply, every statement was already applied");
+        return Ok(());
     }
 
-    let shared_config = aws_config::load_from_env().await;
-    let client = Client::new(&shared_config);
+    // Stop the queries in flight on Ctrl-C or SIGTERM
+    let shutdown = Shutdown::listen();
 
     // Healthcheck
-    submit_and_wait(client.clone(), Some("SELECT 1".to_string()), args.clone()).await?;
+    let health_check = PlannedStatement {
+        kind: "SELECT".to_string(),
+        sql: "SELECT 1".to_string(),
+        context: ExecutionContext {
+            timeout: args.timeout,
+            ..ExecutionContext::new(&settings)
+        },
+        ..Default::default()
+    };
+    let options = QueryOptions::from(&args);
+    let execution = submit_and_wait(
+        client.clone(),
+        &health_check,
+        &options,
+        &run_id,
+        shutdown.clone(),
+    )
+    .await
+    .context("health check failed")
+    .context(ErrorKind::Aws)?;
+    if shutdown.is_requested() {
+        return Err(anyhow!("no statement was started")).context(ErrorKind::Interrupted);
+    }
+    let state = execution_state(&execution);
+    if state != QueryExecutionState::Succeeded {
+        return Err(anyhow!(
+            "health check query finished with state {:?}",
+            state
+        ))
+        .context(ErrorKind::Aws);
+    }
+
+    if args.no_wait {
+        return submit_without_waiting(
+            &client,
+            &plan,
+            usize::from(args.concurrency),
+            &options,
+            &mut journal,
+            &store,
+            &shutdown,
+        )
+        .await;
+    }
 
     // Submit SQL
-    let sql = sql
-        .split(SQL_STATEMENT_SEPARATOR)
-        .into_iter()
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .collect::<Vec<_>>();
-    info!("Submitting {} queries to Athena", sql.len());
+    info!("Submitting {} queries to Athena", plan.statements.len());
 
+    let total = plan.statements.len();
+    let concurrency = usize::from(args.concurrency);
     let mut stats: HashMap<QueryExecutionState, i32> = HashMap::new();
+    let mut failures: Vec<(usize, ErrorKind)> = Vec::new();
+    let mut scheduler = Scheduler::new(&plan);
+    let mut running = JoinSet::new();
+    // First error with `--on-error stop`, or an AWS error: no more statements are started
+    let mut stop_error: Option<anyhow::Error> = None;
 
     // Timer
     let mut timer = DevTime::new_simple();
     timer.start();
 
-    for s in sql {
-        let state = submit_and_wait(client.clone(), Some(s.to_string()), args.clone()).await?;
+    loop {
+        // Start the statements that are ready, up to the concurrency
+        while running.len() < concurrency && stop_error.is_none() && !shutdown.is_requested() {
+            let Some(position) = scheduler.next_ready() else {
+                break;
+            };
+            let statement = plan.statements[position].clone();
+            update_journal(
+                &mut journal,
+                &store,
+                statement.index,
+                RunStatus::Running,
+                None,
+            );
+            let (client, options, shutdown) = (client.clone(), options.clone(), shutdown.clone());
+            let run_id = run_id.clone();
+
+            running.spawn(async move {
+                let result = submit_and_wait(client, &statement, &options, &run_id, shutdown).await;
+                (position, result)
+            });
+        }
+
+        // Wait for the next statement to finish
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (position, result) = joined.context("statement task failed")?;
+        let s = &plan.statements[position];
+
+        let err = match result {
+            Ok(execution) => {
+                let state = execution_state(&execution);
+                update_journal(
+                    &mut journal,
+                    &store,
+                    s.index,
+                    run_status(&state),
+                    execution.query_execution_id().map(str::to_string),
+                );
+
+                // Update stats
+                stats
+                    .entry(state.clone())
+                    .and_modify(|c| *c += 1)
+                    .or_insert(1);
+
+                if let (QueryExecutionState::Succeeded, Some(id)) =
+                    (&state, execution.query_execution_id())
+                {
+                    if let Err(e) = args.result.print(&client, id).await {
+                        error!("{:#}", e);
+                    }
+                }
+
+                if state == QueryExecutionState::Succeeded && state::is_recorded(s) {
+                    ledger.record(
+                        s,
+                        &target,
+                        execution.query_execution_id().map(str::to_string),
+                        Utc::now(),
+                    );
+                    if let Err(e) = store.save(&ledger) {
+                        warn!("{:#}", e);
+                    }
+                }
+
+                // Cancelled on Ctrl-C or SIGTERM, reported below
+                if state == QueryExecutionState::Succeeded
+                    || (state == QueryExecutionState::Cancelled && shutdown.is_requested())
+                {
+                    scheduler.finish(position, true);
+                    continue;
+                }
+
+                let report = FailureReport::new(s, &execution);
+                report.print(args.error_format);
+                anyhow!(report.summary()).context(ErrorKind::QueryFailed)
+            }
+            Err(e) if ErrorKind::of(&e) == Some(ErrorKind::Timeout) => {
+                update_journal(&mut journal, &store, s.index, RunStatus::TimedOut, None);
+                e.context(statement_name(s))
+            }
+            // AWS errors, nothing else can run
+            Err(e) => {
+                error!("{:#}", e);
+                update_journal(&mut journal, &store, s.index, RunStatus::Failed, None);
+                stop_error.get_or_insert(e.context(statement_name(s)));
+                continue;
+            }
+        };
+
+        let skipped = scheduler.finish(position, false);
 
-        // Update stats
-        stats
-            .entry(state.clone())
-            .and_modify(|c| *c += 1)
-            .or_insert(0);
+        match args.on_error {
+            OnError::Stop => {
+                if stop_error.is_none() && !running.is_empty() {
+                    error!(
+                        "Stopping, waiting for {} running statement(s) to finish",
+                        running.len()
+                    );
+                }
+                stop_error.get_or_insert(err);
+            }
+            OnError::Continue => {
+                error!("{:#}", err);
+                let kind = ErrorKind::of(&err).unwrap_or(ErrorKind::QueryFailed);
+                failures.push((s.index, kind));
+
+                for skipped in skipped.into_iter().map(|p| &plan.statements[p]) {
+                    warn!(
+                        "Skipping {}, it depends on statement #{}",
+                        statement_name(skipped),
+                        s.index
+                    );
+                    update_journal(
+                        &mut journal,
+                        &store,
+                        skipped.index,
+                        RunStatus::Skipped,
+                        None,
+                    );
+                    failures.push((skipped.index, kind));
+                }
+            }
+        }
+    }
+
+    // The local ledger is up to date, the S3 copy may not be
+    if let Err(e) = store.upload(&ledger, &shared_config).await {
+        error!("{:#}", e);
+    }
+
+    let count = |status: RunStatus| {
+        plan.statements
+            .iter()
+            .filter(|s| journal.status(s.index) == Some(status))
+            .count()
+    };
+
+    if shutdown.is_requested() {
+        let cancelled = count(RunStatus::Cancelled);
+        let not_started = count(RunStatus::Pending);
+
+        if cancelled > 0 || not_started > 0 {
+            print_interrupted(&plan, &journal);
+            print_resume_hint(&run_id);
+            return Err(anyhow!(
+                "{} statement(s) cancelled, {} not started",
+                cancelled,
+                not_started
+            ))
+            .context(ErrorKind::Interrupted);
+        }
+    }
+
+    if let Some(err) = stop_error {
+        let skipped = count(RunStatus::Pending);
+        if skipped > 0 {
+            error!("Stopped, {} remaining statement(s) skipped", skipped);
+        }
+        print_resume_hint(&run_id);
+        return Err(err);
     }
 
     timer.stop();
@@ -158,49 +509,265 @@ pub async fn call(args: Apply) -> Result<()> {
         info!("  ==> Took: {:?} seconds", secs);
     }
 
+    if !failures.is_empty() {
+        print_resume_hint(&run_id);
+
+        // A timeout exit code only if every failure is a timeout
+        let kind = if failures.iter().all(|(_, k)| *k == ErrorKind::Timeout) {
+            ErrorKind::Timeout
+        } else {
+            ErrorKind::QueryFailed
+        };
+        let indexes = failures
+            .iter()
+            .map(|(i, _)| format!("#{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return Err(anyhow!(
+            "{} of {} statement(s) did not succeed: {}",
+            failures.len(),
+            total,
+            indexes
+        ))
+        .context(kind);
+    }
+
+    Ok(())
+}
+
+/// The statements of the plan that are not done in the journal
+fn pending_statements(mut plan: ExecutionPlan, journal: &Journal) -> ExecutionPlan {
+    let done = |status: RunStatus| {
+        plan.statements
+            .iter()
+            .filter(|s| journal.status(s.index) == Some(status))
+            .map(|s| s.index)
+            .collect::<Vec<_>>()
+    };
+
+    let succeeded = done(RunStatus::Succeeded);
+    if let (false, Some(resumed)) = (succeeded.is_empty(), &journal.resumed_from) {
+        info!(
+            "Skipping {} statement(s) that succeeded in run {}: {}",
+            succeeded.len(),
+            resumed,
+            format_indexes(&succeeded)
+        );
+    }
+    let applied = done(RunStatus::AlreadyApplied);
+    if !applied.is_empty() {
+        info!(
+            "Skipping {} statement(s) already applied: {}, use --force to run them again",
+            applied.len(),
+            format_indexes(&applied)
+        );
+    }
+
+    plan.statements
+        .retain(|s| !journal.status(s.index).is_some_and(RunStatus::is_done));
+    plan
+}
+
+/// `--no-wait`: start up to `concurrency` statements that do not depend on another one,
+/// and exit. Their query execution ids are saved in the journal, see `athena wait --run`
+async fn submit_without_waiting(
+    client: &Client,
+    plan: &ExecutionPlan,
+    concurrency: usize,
+    options: &QueryOptions,
+    journal: &mut Journal,
+    store: &StateStore,
+    shutdown: &Shutdown,
+) -> Result<()> {
+    let run_file = Journal::path(&store.dir, &journal.run_id)?;
+    let mut submitted = 0;
+
+    // Nothing finishes, so only the statements without dependencies are ready
+    for position in Scheduler::new(plan).take_ready(concurrency) {
+        if shutdown.is_requested() {
+            break;
+        }
+
+        let s = &plan.statements[position];
+        let token = client_request_token(&journal.run_id, s, 1);
+        let query_execution_id = start_query(client, s, &token, options, shutdown)
+            .await
+            .with_context(|| statement_name(s));
+        match query_execution_id {
+            Ok(id) => update_journal(journal, store, s.index, RunStatus::Running, Some(id)),
+            Err(e) => {
+                if submitted > 0 {
+                    error!(
+                        "{} statement(s) were submitted, see {}",
+                        submitted,
+                        run_file.display()
+                    );
+                }
+                return Err(e);
+            }
+        }
+        submitted += 1;
+    }
+
+    info!(
+        "Submitted {} statement(s), wait for them with: athena wait --run {}",
+        submitted,
+        run_file.display()
+    );
+
+    let not_started = plan.statements.len() - submitted;
+    if shutdown.is_requested() {
+        return Err(anyhow!("{} statement(s) not submitted", not_started))
+            .context(ErrorKind::Interrupted);
+    }
+    if not_started > 0 {
+        info!(
+            "{} statement(s) not started, they depend on the submitted ones or exceed \
+             --concurrency: continue the run with `athena apply --resume {}` once they succeeded",
+            not_started, journal.run_id
+        );
+    }
+
     Ok(())
 }
 
-fn get_result_configuration(args: Apply) -> ResultConfiguration {
-    let output_location = args
-        .output_location
-        .or_else(|| env::var("AWS_OUTPUT_LOCATION").ok());
+/// Set the status of a statement and write the journal
+fn update_journal(
+    journal: &mut Journal,
+    store: &StateStore,
+    index: usize,
+    status: RunStatus,
+    query_execution_id: Option<String>,
+) {
+    journal.set(index, status, query_execution_id);
+    if let Err(e) = journal.write(&store.dir) {
+        warn!("{:#}", e);
+    }
+}
+
+pub fn run_status(state: &QueryExecutionState) -> RunStatus {
+    match state {
+        QueryExecutionState::Succeeded => RunStatus::Succeeded,
+        QueryExecutionState::Cancelled => RunStatus::Cancelled,
+        _ => RunStatus::Failed,
+    }
+}
+
+/// Print which statements ran, which were cancelled and which were not started
+fn print_interrupted(plan: &ExecutionPlan, journal: &Journal) {
+    let entries = plan
+        .statements
+        .iter()
+        .filter_map(|s| journal.statements.iter().find(|e| e.index == s.index))
+        .collect::<Vec<_>>();
+    let started = entries
+        .iter()
+        .filter(|e| e.status != RunStatus::Pending)
+        .count();
+
+    eprintln!();
+    eprintln!(
+        "Interrupted, {} of {} statement(s) started:",
+        started,
+        entries.len()
+    );
+    for entry in entries {
+        eprintln!(
+            "  #{:<4} {:<12} {}",
+            entry.index,
+            entry.status.to_string(),
+            entry.query_execution_id.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+pub fn print_resume_hint(run_id: &str) {
+    warn!(
+        "Run {} did not complete, continue it with: athena apply --resume {}",
+        run_id, run_id
+    );
+}
+
+/// Name of a statement for error messages, such as `statement #3 (SELECT) from base/a.sql`
+fn statement_name(statement: &PlannedStatement) -> String {
+    match &statement.source {
+        Some(source) => format!(
+            "statement #{} ({}) from {}",
+            statement.index, statement.kind, source
+        ),
+        None => format!("statement #{} ({})", statement.index, statement.kind),
+    }
+}
 
+fn get_result_configuration(context: &ExecutionContext) -> ResultConfiguration {
     ResultConfiguration::builder()
-        .set_output_location(output_location)
+        .set_output_location(context.output_location.clone())
         .build()
 }
 
-fn get_query_execution_context(query: Option<String>) -> Option<QueryExecutionContext> {
-    let query = query.as_ref()?;
-
-    let database = get_database_from_sql(query);
-    database.as_ref()?;
+fn get_query_execution_context(context: &ExecutionContext) -> Option<QueryExecutionContext> {
+    if context.catalog.is_none() && context.database.is_none() {
+        return None;
+    }
 
     let ctx = QueryExecutionContext::builder()
-        .set_database(database)
+        .set_catalog(context.catalog.clone())
+        .set_database(context.database.clone())
         .build();
 
     Some(ctx)
 }
 
-async fn submit_and_wait(
+/// Submit a statement and wait for its final state.
+/// A statement that fails with a transient error is submitted again
+pub async fn submit_and_wait(
     client: Client,
-    query: Option<String>,
-    args: Apply,
-) -> Result<QueryExecutionState> {
-    if query.clone().is_none() {
-        bail!("Empty query");
-    }
+    statement: &PlannedStatement,
+    options: &QueryOptions,
+    run_id: &str,
+    mut shutdown: Shutdown,
+) -> Result<QueryExecution> {
+    let mut backoff = options.retry.backoff();
+    let mut attempt = 1;
 
-    // Timer
-    let mut timer = DevTime::new_simple();
-    timer.start();
+    loop {
+        let token = client_request_token(run_id, statement, attempt);
+        let execution = run_query(&client, statement, &token, options, &mut shutdown).await?;
+        if shutdown.is_requested() || !options.retry.should_retry(&execution, attempt) {
+            return Ok(execution);
+        }
 
-    let workgroup = args.workgroup.clone();
-    let result_configuration = get_result_configuration(args.clone());
-    let query_execution_context = get_query_execution_context(query.clone());
-    let query = query.unwrap();
+        let delay = backoff.next_delay(None);
+        warn!(
+            "{} (attempt {}/{}), retrying in {:?} ...",
+            FailureReport::new(statement, &execution).summary(),
+            attempt,
+            options.retry.retry_max_attempts,
+            delay
+        );
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = shutdown.requested() => return Ok(execution),
+        }
+        attempt += 1;
+    }
+}
+
+/// Submit a query with a `ClientRequestToken`, its query execution id is returned
+async fn start_query(
+    client: &Client,
+    statement: &PlannedStatement,
+    token: &str,
+    options: &QueryOptions,
+    shutdown: &Shutdown,
+) -> Result<String> {
+    let query = &statement.sql;
+    let context = &statement.context;
+
+    let workgroup = context.workgroup.clone();
+    let result_configuration = get_result_configuration(context);
+    let query_execution_context = get_query_execution_context(context);
 
     match &query_execution_context {
         Some(ctx) => match ctx.database() {
@@ -210,56 +777,159 @@ async fn submit_and_wait(
         _ => info!("\nSubmitting ..."),
     }
 
-    if args.no_pretty.unwrap_or_default() {
+    if options.quiet {
+        // The SQL is not printed
+    } else if options.no_pretty {
         print!("{}", query);
     } else {
         pretty_print(query.as_bytes());
     }
 
-    let resp = client
-        .start_query_execution()
-        .set_query_string(Some(query))
-        .set_work_group(workgroup)
-        .set_result_configuration(Some(result_configuration.clone()))
-        .set_query_execution_context(query_execution_context)
-        .send()
-        .await?;
+    // Athena limits the number of queries in flight, wait and retry when it is reached.
+    // The same token is sent again, so a query whose response was lost is not started twice
+    let mut throttle = Backoff::with_bounds(REQUEST_MIN_DELAY, REQUEST_MAX_DELAY);
+    let mut attempt = 1;
+    let resp = loop {
+        let result = client
+            .start_query_execution()
+            .set_query_string(Some(query.clone()))
+            .set_client_request_token(Some(token.to_string()))
+            .set_work_group(workgroup.clone())
+            .set_result_configuration(Some(result_configuration.clone()))
+            .set_query_execution_context(query_execution_context.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(resp) => break resp,
+            Err(e)
+                if attempt < REQUEST_MAX_ATTEMPTS
+                    && !shutdown.is_requested()
+                    && is_transient(&e) =>
+            {
+                let delay = throttle.next_delay(None);
+                warn!(
+                    "Could not start the query: {} (attempt {}/{}), retrying in {:?} ...",
+                    e, attempt, REQUEST_MAX_ATTEMPTS, delay
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(e)
+                    .context("could not start the query execution")
+                    .context(ErrorKind::Aws)
+            }
+        }
+    };
 
     let query_execution_id = resp
         .query_execution_id()
         .ok_or_else(|| anyhow!("query execution id not found in response"))?;
     info!("Query execution id: {}", &query_execution_id);
 
-    let mut state: QueryExecutionState;
+    Ok(query_execution_id.to_string())
+}
+
+/// Submit a query with a `ClientRequestToken` and wait for its final state
+async fn run_query(
+    client: &Client,
+    statement: &PlannedStatement,
+    token: &str,
+    options: &QueryOptions,
+    shutdown: &mut Shutdown,
+) -> Result<QueryExecution> {
+    let context = &statement.context;
 
-    loop {
-        let resp = client
+    // Timer
+    let mut timer = DevTime::new_simple();
+    timer.start();
+
+    let query_execution_id = &start_query(client, statement, token, options, shutdown).await?;
+
+    let started_at = Instant::now();
+    let mut timed_out: Option<Duration> = None;
+    let mut backoff = Backoff::new(&options.poll);
+    // Stopped on Ctrl-C or SIGTERM
+    let mut stopping = false;
+
+    // Consecutive transient errors of `GetQueryExecution`
+    let mut errors = 0;
+
+    let resp = loop {
+        let resp = match client
             .get_query_execution()
             .set_query_execution_id(Some(query_execution_id.to_string()))
             .send()
-            .await?;
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) if errors + 1 < REQUEST_MAX_ATTEMPTS && is_transient(&e) => {
+                errors += 1;
+                let delay = backoff.next_delay(None);
+                warn!(
+                    "Could not get the query state: {}, retrying in {:?} ...",
+                    e, delay
+                );
+                sleep(delay).await;
+                continue;
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| {
+                        format!("could not get query execution {}", query_execution_id)
+                    })
+                    .context(ErrorKind::Aws)
+            }
+        };
+        errors = 0;
 
-        state = status(&resp)
+        let state = status(&resp)
             .ok_or_else(|| anyhow!("could not get query execution status from response"))?
             .clone();
 
         match state {
             QueryExecutionState::Queued | QueryExecutionState::Running => {
-                sleep(Duration::from_secs(QUERY_POLL_INTERVAL_SECS)).await;
-                info!(
-                    "State: {:?}, sleeping {} secs ...",
-                    state, QUERY_POLL_INTERVAL_SECS
-                );
+                let timeout = context.timeout.filter(|_| timed_out.is_none() && !stopping);
+                if let Some(timeout) = timeout.filter(|t| started_at.elapsed() >= *t) {
+                    error!(
+                        "Timed out after {}, stopping ...",
+                        humantime::format_duration(timeout)
+                    );
+                    stop_query(client, query_execution_id).await?;
+                    timed_out = Some(timeout);
+                } else if shutdown.is_requested() && !stopping && timed_out.is_none() {
+                    error!("Interrupted, stopping query {} ...", query_execution_id);
+                    stop_query(client, query_execution_id).await?;
+                    stopping = true;
+                }
+
+                // Wake up at the timeout rather than a full interval later
+                let mut interval = backoff.next_delay(engine_execution_time(&resp));
+                if let Some(timeout) = context.timeout.filter(|_| timed_out.is_none()) {
+                    let remaining = timeout.saturating_sub(started_at.elapsed());
+                    interval = interval.min(remaining.max(Duration::from_millis(100)));
+                }
+
+                info!("State: {:?}, sleeping {:?} ...", state, interval);
+                tokio::select! {
+                    _ = sleep(interval) => {}
+                    _ = shutdown.requested(), if !stopping => {}
+                }
             }
             QueryExecutionState::Cancelled | QueryExecutionState::Failed => {
                 error!("State: {:?}", state);
 
-                match get_query_result(&client, query_execution_id.to_string()).await {
-                    Ok(result) => info!("Result: {:?}", result),
-                    Err(e) => error!("Result error: {:?}", e),
+                if let Some(timeout) = timed_out {
+                    return Err(anyhow!(
+                        "query {} was stopped after {}",
+                        query_execution_id,
+                        humantime::format_duration(timeout)
+                    ))
+                    .context(ErrorKind::Timeout);
                 }
 
-                break;
+                break resp;
             }
             _ => {
                 info!("State: {:?}", state);
@@ -267,22 +937,39 @@ async fn submit_and_wait(
                     info!("Total execution time: {} millis", millis);
                 }
 
-                match get_query_result(&client, query_execution_id.to_string()).await {
-                    Ok(result) => info!("Result: {:?}", result),
-                    Err(e) => error!("Result error: {:?}", e),
-                }
-
-                break;
+                break resp;
             }
         }
-    }
+    };
 
     timer.stop();
     if let Some(secs) = timer.time_in_secs() {
         info!("Took: {} secs", secs);
     }
 
-    Ok(state.clone())
+    resp.query_execution()
+        .cloned()
+        .ok_or_else(|| anyhow!("query execution not found in response"))
+}
+
+pub async fn stop_query(client: &Client, query_execution_id: &str) -> Result<()> {
+    client
+        .stop_query_execution()
+        .set_query_execution_id(Some(query_execution_id.to_string()))
+        .send()
+        .await
+        .with_context(|| format!("could not stop query execution {}", query_execution_id))
+        .context(ErrorKind::Aws)?;
+
+    Ok(())
+}
+
+pub fn execution_state(execution: &QueryExecution) -> QueryExecutionState {
+    execution
+        .status()
+        .and_then(|s| s.state())
+        .cloned()
+        .unwrap_or_else(|| QueryExecutionState::from("UNKNOWN"))
 }
 
 fn status(resp: &GetQueryExecutionOutput) -> Option<&QueryExecutionState> {
@@ -291,94 +978,16 @@ fn status(resp: &GetQueryExecutionOutput) -> Option<&QueryExecutionState> {
         .and_then(|s| s.state())
 }
 
-fn total_execution_time(resp: &GetQueryExecutionOutput) -> Option<i64> {
+fn engine_execution_time(resp: &GetQueryExecutionOutput) -> Option<Duration> {
     resp.query_execution()
         .and_then(|qe| qe.statistics())
-        .and_then(|s| s.total_execution_time_in_millis())
-}
-
-async fn get_query_result(client: &Client, query_execution_id: String) -> Result<ResultSet> {
-    let resp = client
-        .get_query_results()
-        .set_query_execution_id(Some(query_execution_id.clone()))
-        .send()
-        .await
-        .with_context(|| {
-            format!(
-                "could not get query results for query id {}",
-                query_execution_id
-            )
-        })?;
-
-    Ok(resp
-        .result_set()
-        .ok_or_else(|| anyhow!("could not get query result"))?
-        .clone())
+        .and_then(|s| s.engine_execution_time_in_millis())
+        .and_then(|millis| u64::try_from(millis).ok())
+        .map(Duration::from_millis)
 }
 
-fn get_database_from_sql<S: AsRef<str>>(sql: S) -> Option<String> {
-    for r in DATABASE_PATTERNS.iter() {
-        if let Some(caps) = r.captures(sql.as_ref()) {
-            let name = caps.get(1).map_or("", |m| m.as_str());
-            return Some(name.trim().to_string());
-        }
-    }
-
-    None
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_get_database_from_sql() {
-        let sql = "-- database: db0";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
-
-        let sql = "-- database: db1\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db1");
-
-        let sql = "-- Database: db2\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db2");
-
-        let sql = "-- Database: db3 \nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db3");
-
-        let sql = "-- Database: db4    \nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db4");
-
-        let sql = "--   Database: db4    \nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db4");
-
-        let sql = "/* Database: db5 */\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db5");
-
-        let sql = "/* database: db6 */\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db6");
-
-        let sql = "/*        database: db7 */\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db7");
-
-        let sql = "SELECT * FROM ...;";
-        assert!(get_database_from_sql(sql).is_none());
-
-        let sql = "-- database: db0 \n-- database: db1";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
-
-        let sql = "/* database: db0 */\n/* database: db1 */";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
-    }
-
-    #[test]
-    fn test_get_database_from_sql_with_comment() {
-        let sql = "-- database: db0\n-- comment\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
-
-        let sql = "-- database: db0\n-- comment\n-- comment\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
-
-        let sql = "-- database: db0\n-- comment\n-- comment\n-- comment\nSELECT * FROM ...;";
-        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
-    }
+fn total_execution_time(resp: &GetQueryExecutionOutput) -> Option<i64> {
+    resp.query_execution()
+        .and_then(|qe| qe.statistics())
+        .and_then(|s| s.total_execution_time_in_millis())
 }
@@ -7,23 +7,38 @@
 //! 1. Loads all `.sql` files from the working directory as templates
 //! 2. Renders the target template (or `index.sql` if a directory is provided)
 //! 3. Outputs the rendered SQL to stdout or a file
+//!
+//! Variables from `athena.toml`, the `_vars.*` files along the target path,
+//! `--vars-file` and `--var` are available in every template,
+//! see [`crate::config`] and [`crate::vars`].
 
 use anyhow::{bail, Context, Result};
 use log::debug;
-use std::{fs::File, io::Write, path::PathBuf};
-
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::config::{self, AwsArgs, Settings};
+use crate::error::ErrorKind;
+use crate::source::SourceMap;
 use crate::tera::get_tera;
 use crate::utils::{get_current_working_dir, get_full_path_str, is_dir, pretty_print};
+use crate::vars::{self, VarArgs};
 
 // Constants
 const INDEX_SQL_FILENAME: &str = "index.sql";
 const SQL_FILE_EXTENSION: &str = "sql";
+const INLINE_TEMPLATE_NAME: &str = "(query)";
 
 #[derive(clap::Args, Debug, Clone)]
 pub struct Build {
     /// Target path to render. If the target path is a directory,
-    /// the root folder must contains the index.sql file
-    pub file: PathBuf,
+    /// the root folder must contains the index.sql file.
+    /// Optional when the `--env` environment defines a `target`
+    #[arg(required_unless_present = "env")]
+    pub file: Option<PathBuf>,
 
     /// Output path. The file will be overwritten if is already exists
     #[arg(long, short)]
@@ -36,11 +51,18 @@ pub struct Build {
     /// No pretty print for SQL
     #[arg(long, short)]
     pub no_pretty: Option<bool>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
 }
 
 pub async fn call(args: Build) -> Result<()> {
     // Render SQL
-    let sql = build(args.clone())?;
+    let sql = build(&args)?;
 
     // Print to stdout or write to file?
     match args.out {
@@ -67,17 +89,41 @@ pub async fn call(args: Build) -> Result<()> {
     Ok(())
 }
 
-pub fn build(args: Build) -> Result<String> {
-    let path = &args.file;
+pub fn build(args: &Build) -> Result<String> {
+    let settings = config::resolve(
+        args.context.as_deref(),
+        args.env.as_deref(),
+        &AwsArgs::default(),
+    )?;
+
+    render(args, &settings).context(ErrorKind::Render)
+}
+
+/// Render the target template with the already resolved settings
+pub fn render(args: &Build, settings: &Settings) -> Result<String> {
+    render_template(args, settings, false).map(|(sql, _)| sql)
+}
+
+/// Render the target template and track the source template of the rendered SQL
+pub fn render_with_sources(args: &Build, settings: &Settings) -> Result<(String, SourceMap)> {
+    render_template(args, settings, true)
+}
+
+fn render_template(
+    args: &Build,
+    settings: &Settings,
+    trace_sources: bool,
+) -> Result<(String, SourceMap)> {
+    let path = &config::target_path(args.file.as_deref(), settings)?;
 
     let is_dir = is_dir(path);
 
     // If input path is empty folder, just return empty
     if is_dir && path.read_dir()?.next().is_none() {
-        return Ok("".to_string());
+        return Ok(("".to_string(), SourceMap::default()));
     }
 
-    let (working_dir, path_str) = get_dirs(args.clone())?;
+    let (working_dir, path_str) = get_dirs(path, args.context.clone())?;
 
     // If input path contains no *.sql files, error
     if is_dir
@@ -88,9 +134,7 @@ pub fn build(args: Build) -> Result<String> {
     {
         let files = path
             .read_dir()?
-            .map(Result::ok)
-            .into_iter()
-            .flatten()
+            .filter_map(Result::ok)
             .map(|f| f.path())
             .collect::<Vec<_>>();
 
@@ -98,14 +142,13 @@ pub fn build(args: Build) -> Result<String> {
     }
 
     // Init Tera template
-    let tera = get_tera(args.file.clone(), working_dir)?;
+    let tera = get_tera(path, &working_dir, trace_sources)?;
 
     // For debug
     let loaded_template: Vec<_> = tera.get_template_names().collect();
     debug!("loaded templates: {:?}", loaded_template);
 
-    // TODO: Tera context
-    let context = tera::Context::new();
+    let context = template_context(settings, &working_dir, path, &args.vars)?;
 
     // Render the index.sql file if the target path is a folder
     let endpoint = if is_dir {
@@ -119,15 +162,57 @@ pub fn build(args: Build) -> Result<String> {
     let out = tera
         .render(&endpoint, &context)
         .with_context(|| format!("failed to render from {}", path_str))?;
+    let (out, sources) = SourceMap::extract(&out);
+
+    let trimmed = out.trim_start();
+    let sources = sources.trim_start(out.len() - trimmed.len());
+
+    Ok((trimmed.trim_end().to_string(), sources))
+}
+
+/// Render an inline SQL template, such as the SQL of `athena query`.
+/// The templates of the context dir can be included and imported
+pub fn render_inline(
+    sql: &str,
+    context: Option<PathBuf>,
+    vars: &VarArgs,
+    settings: &Settings,
+) -> Result<String> {
+    let working_dir = get_current_working_dir(context)?;
+
+    let mut tera = get_tera(&working_dir, &working_dir, false)?;
+    tera.add_raw_template(INLINE_TEMPLATE_NAME, sql)
+        .context("could not parse the SQL template")?;
+
+    let context = template_context(settings, &working_dir, &working_dir, vars)?;
+    let out = tera
+        .render(INLINE_TEMPLATE_NAME, &context)
+        .context("failed to render the SQL")?;
 
     Ok(out.trim().to_string())
 }
 
-fn get_dirs(args: Build) -> Result<(PathBuf, String)> {
-    let path = &args.file;
+/// Tera context from athena.toml, _vars.* files, --vars-file and --var
+fn template_context(
+    settings: &Settings,
+    working_dir: &Path,
+    target: &Path,
+    args: &VarArgs,
+) -> Result<tera::Context> {
+    // From the most generic to the most specific: the project defaults, the `_vars` files
+    // from shallow to deep, the selected environment, then the command line
+    let mut vars = settings.default_vars.clone();
+    vars::merge(&mut vars, vars::load_dir_vars(working_dir, target)?);
+    vars::merge(&mut vars, settings.env_vars.clone());
+    vars::merge(&mut vars, vars::load(args)?);
+    debug!("template variables: {:?}", vars);
+
+    tera::Context::from_value(vars.into()).context("could not build the template context")
+}
 
+fn get_dirs(path: &Path, context: Option<PathBuf>) -> Result<(PathBuf, String)> {
     // Working directory (context directory)
-    let working_dir = get_current_working_dir(args.context)?;
+    let working_dir = get_current_working_dir(context)?;
     debug!("Working dir: {}", &working_dir.display());
 
     // Get path_str (without context directory prefix)
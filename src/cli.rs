@@ -1,11 +1,23 @@
 //! Command-line interface definitions and argument parsing
 //!
 //! This module defines the CLI structure using `clap` with derive macros.
-//! It provides the main CLI entry point and command definitions for `build` and `apply`.
+//! It provides the main CLI entry point and command definitions for `build`, `plan`, `apply`, `migrate`, `rollback`, `query`, `shell`,
+//! `status`, `wait`, `cancel`, `history`, `config` and `state`.
 
 use clap::Parser;
 
-use crate::{apply::Apply, build::Build};
+use crate::{
+    apply::Apply,
+    build::Build,
+    config::Config,
+    execution::{Cancel, History, Status, Wait},
+    migrate::Migrate,
+    plan::Plan,
+    query::Query,
+    rollback::Rollback,
+    shell::Shell,
+    state::State,
+};
 
 /// Managing AWS Athena Schemas
 #[derive(Parser, Debug)]
@@ -21,8 +33,33 @@ pub struct Cli {
 pub enum Command {
     /// Build SQL from template path
     Build(Build),
-    /// Build and execute SQL to Athena
+    /// Build SQL and save the execution plan to a file, to be applied later
+    Plan(Plan),
+    /// Build and execute SQL to Athena, or execute a saved plan file
     Apply(Apply),
+    /// Apply the versioned migrations and inspect their history
+    #[command(subcommand)]
+    Migrate(Migrate),
+    /// Revert the applied migrations newer than a version
+    Rollback(Rollback),
+    /// Run one SQL statement and print its results
+    Query(Query),
+    /// Run SQL interactively, with line editing, history and completion
+    Shell(Shell),
+    /// Show the state, timings, data scanned and error of queries
+    Status(Status),
+    /// Wait until queries finish
+    Wait(Wait),
+    /// Stop running queries
+    Cancel(Cancel),
+    /// List the recent queries of a workgroup
+    History(History),
+    /// Inspect the project configuration (athena.toml)
+    #[command(subcommand)]
+    Config(Config),
+    /// Manage the ledger of the applied statements
+    #[command(subcommand)]
+    State(State),
 }
 
 // Parse the command line arguments
@@ -0,0 +1,641 @@
+//! Project configuration file (`athena.toml`)
+//!
+//! The configuration file is searched from the context directory (`--context`, or the
+//! current working dir) up to the filesystem root, the first `athena.toml` found is used.
+//!
+//! Top-level keys are the project defaults, named environments under `[env.<name>]`
+//! override them and are selected with `--env <name>`:
+//!
+//! ```toml
+//! region = "us-east-1"
+//! workgroup = "primary"
+//!
+//! [vars]
+//! team = "data"
+//!
+//! [env.prd]
+//! profile = "prd"
+//! output_location = "s3://athena-output-prd/"
+//! target = "prd"
+//!
+//! [env.prd.vars]
+//! s3_bucket = "s3://prd"
+//! ```
+//!
+//! Settings are resolved in this order, the first one wins:
+//! 1. Command line flags (`--profile`, `--region`, ...)
+//! 2. The selected `[env.<name>]` section
+//! 3. Environment variables (`AWS_PROFILE`, `AWS_REGION`, ...)
+//! 4. The top-level defaults
+//!
+//! An environment variable overridden by the selected section is reported with a warning,
+//! so an exported `AWS_PROFILE=dev` does not silently apply to `--env prd`, nor the reverse.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::get_current_working_dir;
+use crate::vars::{self, Vars};
+
+// Constants
+pub const CONFIG_FILENAME: &str = "athena.toml";
+
+/// Settings of the project, or of one environment
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Environment {
+    /// AWS profile
+    pub profile: Option<String>,
+    /// AWS region
+    pub region: Option<String>,
+    /// Athena workgroup
+    pub workgroup: Option<String>,
+    /// S3 location of the query results
+    pub output_location: Option<String>,
+    /// Target path to render, relative to the configuration file
+    pub target: Option<PathBuf>,
+    /// S3 location of the apply ledger, such as `s3://bucket/athena/state.json`
+    pub state_location: Option<String>,
+    /// Iceberg table of the migration history, such as `admin.athena_migrations`
+    pub migrations_table: Option<String>,
+    /// S3 location of the migration history table, such as `s3://bucket/athena/migrations/`
+    pub migrations_location: Option<String>,
+    /// Template variables
+    #[serde(default)]
+    pub vars: Vars,
+}
+
+/// The `athena.toml` file, top-level keys are the project defaults
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub profile: Option<String>,
+    pub region: Option<String>,
+    pub workgroup: Option<String>,
+    pub output_location: Option<String>,
+    pub target: Option<PathBuf>,
+    pub state_location: Option<String>,
+    pub migrations_table: Option<String>,
+    pub migrations_location: Option<String>,
+    #[serde(default)]
+    pub vars: Vars,
+    /// Named environments
+    #[serde(default)]
+    pub env: BTreeMap<String, Environment>,
+}
+
+impl ConfigFile {
+    /// Project defaults, as an environment
+    pub fn defaults(&self) -> Environment {
+        Environment {
+            profile: self.profile.clone(),
+            region: self.region.clone(),
+            workgroup: self.workgroup.clone(),
+            output_location: self.output_location.clone(),
+            target: self.target.clone(),
+            state_location: self.state_location.clone(),
+            migrations_table: self.migrations_table.clone(),
+            migrations_location: self.migrations_location.clone(),
+            vars: self.vars.clone(),
+        }
+    }
+}
+
+/// Where a setting value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// Command line flag
+    Cli,
+    /// Environment variable
+    EnvVar(&'static str),
+    /// Configuration file, `section` is `None` for the top-level defaults
+    Config {
+        path: PathBuf,
+        section: Option<String>,
+    },
+    /// Saved plan file
+    Plan(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Cli => write!(f, "command line"),
+            Source::EnvVar(name) => write!(f, "environment variable {}", name),
+            Source::Config {
+                path,
+                section: Some(section),
+            } => write!(f, "{} [env.{}]", path.display(), section),
+            Source::Config {
+                path,
+                section: None,
+            } => write!(f, "{}", path.display()),
+            Source::Plan(path) => write!(f, "plan file {}", path.display()),
+        }
+    }
+}
+
+/// A setting value and its source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sourced<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// AWS settings given on the command line
+#[derive(Debug, Default, Clone)]
+pub struct AwsArgs {
+    pub profile: Option<String>,
+    pub region: Option<String>,
+    pub workgroup: Option<String>,
+    pub output_location: Option<String>,
+}
+
+/// The resolved settings
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    /// Path of the configuration file, if any
+    pub config_path: Option<PathBuf>,
+    /// Selected environment
+    pub env: Option<String>,
+    pub profile: Option<Sourced<String>>,
+    pub region: Option<Sourced<String>>,
+    pub workgroup: Option<Sourced<String>>,
+    pub output_location: Option<Sourced<String>>,
+    /// Target path to render, absolute
+    pub target: Option<Sourced<PathBuf>>,
+    /// S3 location of the apply ledger
+    pub state_location: Option<Sourced<String>>,
+    /// Iceberg table of the migration history
+    pub migrations_table: Option<Sourced<String>>,
+    /// S3 location of the migration history table
+    pub migrations_location: Option<Sourced<String>>,
+    /// Template variables of the top-level `[vars]`, the `_vars` files override them
+    pub default_vars: Vars,
+    /// Template variables of the selected `[env.<name>.vars]`, they override the `_vars` files
+    pub env_vars: Vars,
+    /// Source of each top-level template variable
+    pub var_sources: BTreeMap<String, Source>,
+}
+
+impl Settings {
+    pub fn profile(&self) -> Option<String> {
+        self.profile.as_ref().map(|s| s.value.clone())
+    }
+
+    pub fn region(&self) -> Option<String> {
+        self.region.as_ref().map(|s| s.value.clone())
+    }
+
+    pub fn workgroup(&self) -> Option<String> {
+        self.workgroup.as_ref().map(|s| s.value.clone())
+    }
+
+    pub fn output_location(&self) -> Option<String> {
+        self.output_location.as_ref().map(|s| s.value.clone())
+    }
+
+    /// Template variables of the configuration file, the environment overrides the defaults
+    pub fn vars(&self) -> Vars {
+        let mut vars = self.default_vars.clone();
+        vars::merge(&mut vars, self.env_vars.clone());
+        vars
+    }
+}
+
+/// Load the AWS configuration with the profile and region of the settings
+pub async fn load_aws_config(settings: &Settings) -> aws_config::SdkConfig {
+    // Set AWS_PROFILE
+    if let Some(profile) = settings.profile() {
+        std::env::set_var("AWS_PROFILE", profile);
+    }
+
+    // Set AWS_REGION and AWS_DEFAULT_REGION, the SDK prefers an exported AWS_REGION
+    if let Some(region) = settings.region() {
+        std::env::set_var("AWS_REGION", &region);
+        std::env::set_var("AWS_DEFAULT_REGION", region);
+    }
+
+    aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Config {
+    /// Print the resolved settings and where each value comes from
+    Show(Show),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Show {
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+}
+
+pub async fn call(args: Config) -> Result<()> {
+    match args {
+        Config::Show(args) => show(&args),
+    }
+}
+
+fn show(args: &Show) -> Result<()> {
+    let settings = resolve(
+        args.context.as_deref(),
+        args.env.as_deref(),
+        &AwsArgs::default(),
+    )?;
+
+    match &settings.config_path {
+        Some(path) => println!("config: {}", path.display()),
+        None => println!("config: (no {} found)", CONFIG_FILENAME),
+    }
+    println!("env: {}", settings.env.as_deref().unwrap_or("(none)"));
+    println!();
+
+    print_setting("profile", &settings.profile);
+    print_setting("region", &settings.region);
+    print_setting("workgroup", &settings.workgroup);
+    print_setting("output_location", &settings.output_location);
+    let target = settings.target.as_ref().map(|t| Sourced {
+        value: t.value.display().to_string(),
+        source: t.source.clone(),
+    });
+    print_setting("target", &target);
+    print_setting("state_location", &settings.state_location);
+    print_setting("migrations_table", &settings.migrations_table);
+    print_setting("migrations_location", &settings.migrations_location);
+
+    let vars = settings.vars();
+    if !vars.is_empty() {
+        println!();
+        println!("vars:");
+        for (key, value) in &vars {
+            match settings.var_sources.get(key) {
+                Some(source) => println!("  {} = {}  # {}", key, value, source),
+                None => println!("  {} = {}", key, value),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_setting(name: &str, setting: &Option<Sourced<String>>) {
+    match setting {
+        Some(s) => println!("{:<19} = {}  # {}", name, s.value, s.source),
+        None => println!("{:<19} = (not set)", name),
+    }
+}
+
+/// Find `athena.toml` from `start` up to the root
+pub fn find_config_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILENAME))
+        .find(|path| path.is_file())
+}
+
+/// Read and parse a configuration file
+pub fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("could not read config file {}", path.display()))?;
+
+    toml::from_str(&content)
+        .with_context(|| format!("could not parse config file {}", path.display()))
+}
+
+/// Find the configuration file from the context directory and resolve the settings
+pub fn resolve(context: Option<&Path>, env: Option<&str>, cli: &AwsArgs) -> Result<Settings> {
+    let working_dir = get_current_working_dir(context.map(Path::to_path_buf))?;
+
+    let config = match find_config_file(&working_dir) {
+        Some(path) => {
+            let config = read_config_file(&path)?;
+            Some((path, config))
+        }
+        None => None,
+    };
+
+    resolve_with(config, env, cli, |name| std::env::var(name).ok())
+}
+
+/// Resolve the settings from the given configuration, `getenv` looks up environment variables
+pub fn resolve_with(
+    config: Option<(PathBuf, ConfigFile)>,
+    env: Option<&str>,
+    cli: &AwsArgs,
+    getenv: impl Fn(&str) -> Option<String>,
+) -> Result<Settings> {
+    let (config_path, config) = match config {
+        Some((path, config)) => (Some(path), config),
+        None => (None, ConfigFile::default()),
+    };
+
+    let defaults = config.defaults();
+    let selected = match env {
+        Some(name) => {
+            let path = config_path
+                .as_ref()
+                .ok_or_else(|| anyhow!("environment `{}` requires a {}", name, CONFIG_FILENAME))?;
+            let environment = config.env.get(name).ok_or_else(|| {
+                let available = config.env.keys().cloned().collect::<Vec<_>>();
+                anyhow!(
+                    "environment `{}` not found in {}, available: {:?}",
+                    name,
+                    path.display(),
+                    available
+                )
+            })?;
+            Some((name, environment))
+        }
+        None => None,
+    };
+
+    // Value of the selected environment section
+    let from_section = |field: fn(&Environment) -> Option<String>| -> Option<Sourced<String>> {
+        let path = config_path.clone()?;
+        let (name, environment) = selected?;
+        field(environment).map(|value| Sourced {
+            value,
+            source: Source::Config {
+                path,
+                section: Some(name.to_string()),
+            },
+        })
+    };
+
+    let from_defaults = |field: fn(&Environment) -> Option<String>| -> Option<Sourced<String>> {
+        let path = config_path.clone()?;
+        field(&defaults).map(|value| Sourced {
+            value,
+            source: Source::Config {
+                path,
+                section: None,
+            },
+        })
+    };
+
+    // Config values, the selected environment overrides the defaults
+    let from_config = |field: fn(&Environment) -> Option<String>| {
+        from_section(field).or_else(|| from_defaults(field))
+    };
+
+    let from_cli = |value: &Option<String>| {
+        value.clone().map(|value| Sourced {
+            value,
+            source: Source::Cli,
+        })
+    };
+
+    let from_env_var = |names: &[&'static str]| {
+        names.iter().find_map(|name| {
+            getenv(name).filter(|v| !v.is_empty()).map(|value| Sourced {
+                value,
+                source: Source::EnvVar(name),
+            })
+        })
+    };
+
+    // Command line, then the selected environment, then environment variables, then defaults
+    let resolve_setting = |name: &str,
+                           cli: &Option<String>,
+                           env_vars: &[&'static str],
+                           field: fn(&Environment) -> Option<String>| {
+        if let Some(value) = from_cli(cli) {
+            return Some(value);
+        }
+
+        let env_var = from_env_var(env_vars);
+        if let Some(value) = from_section(field) {
+            if let Some(ignored) = env_var.filter(|v| v.value != value.value) {
+                warn!(
+                    "{} = {} from {} is ignored, {} = {} from {}",
+                    name, ignored.value, ignored.source, name, value.value, value.source
+                );
+            }
+            return Some(value);
+        }
+
+        env_var.or_else(|| from_defaults(field))
+    };
+
+    let profile = resolve_setting("profile", &cli.profile, &["AWS_PROFILE"], |e| {
+        e.profile.clone()
+    });
+    let region = resolve_setting(
+        "region",
+        &cli.region,
+        &["AWS_REGION", "AWS_DEFAULT_REGION"],
+        |e| e.region.clone(),
+    );
+    let workgroup = resolve_setting("workgroup", &cli.workgroup, &["AWS_WORKGROUP"], |e| {
+        e.workgroup.clone()
+    });
+    let output_location = resolve_setting(
+        "output_location",
+        &cli.output_location,
+        &["AWS_OUTPUT_LOCATION"],
+        |e| e.output_location.clone(),
+    );
+
+    // Target path is relative to the configuration file
+    let target = from_config(|e| e.target.as_ref().map(|t| t.display().to_string())).map(|t| {
+        let base = config_path
+            .as_ref()
+            .and_then(|p| p.parent())
+            .unwrap_or_else(|| Path::new("."));
+        Sourced {
+            value: base.join(t.value),
+            source: t.source,
+        }
+    });
+
+    let state_location = from_config(|e| e.state_location.clone());
+    let migrations_table = from_config(|e| e.migrations_table.clone());
+    let migrations_location = from_config(|e| e.migrations_location.clone());
+
+    // Template variables, two layers around the `_vars` files, see `crate::build`
+    let default_vars = defaults.vars.clone();
+    let env_vars = selected
+        .map(|(_, environment)| environment.vars.clone())
+        .unwrap_or_default();
+    let mut var_sources = BTreeMap::new();
+    if let Some(path) = &config_path {
+        let section = selected.map(|(name, _)| name.to_string());
+        let layers = [(None, &default_vars), (section, &env_vars)];
+        for (section, layer) in layers {
+            for key in layer.keys() {
+                var_sources.insert(
+                    key.clone(),
+                    Source::Config {
+                        path: path.clone(),
+                        section: section.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(Settings {
+        config_path,
+        env: env.map(str::to_string),
+        profile,
+        region,
+        workgroup,
+        output_location,
+        target,
+        state_location,
+        migrations_table,
+        migrations_location,
+        default_vars,
+        env_vars,
+        var_sources,
+    })
+}
+
+/// Resolve the target path to render from the command line or the selected environment
+pub fn target_path(file: Option<&Path>, settings: &Settings) -> Result<PathBuf> {
+    if let Some(file) = file {
+        return Ok(file.to_path_buf());
+    }
+
+    match &settings.target {
+        Some(target) => Ok(target.value.clone()),
+        None => match &settings.env {
+            Some(env) => bail!(
+                "no target path given and environment `{}` has no `target`",
+                env
+            ),
+            None => bail!("no target path given"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> (PathBuf, ConfigFile) {
+        let content = r#"
+            region = "us-east-1"
+            workgroup = "primary"
+
+            [vars]
+            team = "data"
+            s3_bucket = "s3://default"
+
+            [env.prd]
+            profile = "prd"
+            target = "prd"
+
+            [env.prd.vars]
+            s3_bucket = "s3://prd"
+        "#;
+
+        (
+            PathBuf::from("/project/athena.toml"),
+            toml::from_str(content).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_resolve_env_overrides_defaults() {
+        let settings =
+            resolve_with(Some(config()), Some("prd"), &AwsArgs::default(), |_| None).unwrap();
+
+        assert_eq!(settings.profile().unwrap(), "prd");
+        assert_eq!(settings.region().unwrap(), "us-east-1");
+        assert_eq!(settings.workgroup().unwrap(), "primary");
+        assert!(settings.output_location.is_none());
+        assert_eq!(
+            settings.target.as_ref().unwrap().value,
+            PathBuf::from("/project/prd")
+        );
+        assert_eq!(settings.vars()["team"], json!("data"));
+        assert_eq!(settings.vars()["s3_bucket"], json!("s3://prd"));
+        assert_eq!(settings.default_vars["s3_bucket"], json!("s3://default"));
+        assert_eq!(settings.env_vars["s3_bucket"], json!("s3://prd"));
+        assert_eq!(
+            settings.var_sources["s3_bucket"],
+            Source::Config {
+                path: PathBuf::from("/project/athena.toml"),
+                section: Some("prd".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_precedence() {
+        let cli = AwsArgs {
+            profile: Some("cli".to_string()),
+            ..Default::default()
+        };
+        let getenv = |name: &str| match name {
+            "AWS_PROFILE" => Some("env_profile".to_string()),
+            "AWS_DEFAULT_REGION" => Some("eu-west-1".to_string()),
+            _ => None,
+        };
+        let settings = resolve_with(Some(config()), Some("prd"), &cli, getenv).unwrap();
+
+        assert_eq!(settings.profile.unwrap().source, Source::Cli);
+        let region = settings.region.unwrap();
+        assert_eq!(region.value, "eu-west-1");
+        assert_eq!(region.source, Source::EnvVar("AWS_DEFAULT_REGION"));
+
+        // The selected environment wins over environment variables, not over the command line
+        let getenv = |name: &str| match name {
+            "AWS_PROFILE" => Some("dev".to_string()),
+            "AWS_WORKGROUP" => Some("adhoc".to_string()),
+            _ => None,
+        };
+        let settings =
+            resolve_with(Some(config()), Some("prd"), &AwsArgs::default(), getenv).unwrap();
+        let profile = settings.profile.unwrap();
+        assert_eq!(profile.value, "prd");
+        assert_eq!(
+            profile.source,
+            Source::Config {
+                path: PathBuf::from("/project/athena.toml"),
+                section: Some("prd".to_string())
+            }
+        );
+        // Not set by the section, the variable wins over the top-level defaults
+        let workgroup = settings.workgroup.unwrap();
+        assert_eq!(workgroup.value, "adhoc");
+        assert_eq!(workgroup.source, Source::EnvVar("AWS_WORKGROUP"));
+
+        // Without --env, the variable wins over the top-level defaults
+        let settings = resolve_with(Some(config()), None, &AwsArgs::default(), getenv).unwrap();
+        assert_eq!(settings.profile().unwrap(), "dev");
+    }
+
+    #[test]
+    fn test_resolve_unknown_env() {
+        let err =
+            resolve_with(Some(config()), Some("dev"), &AwsArgs::default(), |_| None).unwrap_err();
+        assert!(err.to_string().contains("environment `dev` not found"));
+
+        let err = resolve_with(None, Some("prd"), &AwsArgs::default(), |_| None).unwrap_err();
+        assert!(err.to_string().contains("requires a athena.toml"));
+    }
+
+    #[test]
+    fn test_unknown_config_key() {
+        let res = toml::from_str::<ConfigFile>("[env.prd]\nregoin = \"us-east-1\"");
+        assert!(res.is_err());
+
+        let res = toml::from_str::<ConfigFile>("regoin = \"us-east-1\"");
+        assert!(res.is_err());
+    }
+}
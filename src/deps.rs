@@ -0,0 +1,640 @@
+//! Dependencies between statements, inferred from the SQL
+//!
+//! Each statement is parsed to find the databases, tables and views it creates,
+//! drops and references:
+//!
+//! - `CREATE DATABASE db` creates `db`
+//! - `CREATE [EXTERNAL] TABLE t`, `CREATE [OR REPLACE] VIEW v` create `t` and `v`
+//! - `DROP TABLE t`, `DROP VIEW v`, `DROP DATABASE db` drop them
+//! - `ALTER TABLE t RENAME TO u` drops `t` and creates `u`
+//! - `ALTER TABLE t`, `MSCK REPAIR TABLE t`, `FROM t`, `JOIN t`, `INTO t` reference `t`
+//! - A table in a database, or a statement executed on a database, references the database
+//!
+//! Unqualified table names belong to the database of the statement, see [`crate::directive`].
+//! The names of a `WITH` clause are not tables, nor the operands of `EXTRACT(... FROM x)`.
+//!
+//! A statement that references an object runs after the last statement before it that
+//! creates the object, or after the first one after it when the object is not dropped in
+//! between, so a view included before its tables runs after them. A statement with the
+//! `-- athena: keep_order` directive only waits for the statements before it.
+//! A `CREATE` or a `DROP` keeps its place among the other statements that use the same
+//! object. The plan is sorted in topological order, statements without dependencies
+//! between them keep the order of the templates. A dependency cycle is an error.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+
+use crate::sql::{tokenize, Token, TokenKind};
+
+/// A database, or a table or view in a database
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Object {
+    Database(String),
+    /// `database` is `None` for the default database of the workgroup
+    Table {
+        database: Option<String>,
+        name: String,
+    },
+}
+
+/// Objects created, dropped and referenced by a statement
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Objects {
+    pub creates: BTreeSet<Object>,
+    pub drops: BTreeSet<Object>,
+    pub references: BTreeSet<Object>,
+}
+
+impl Objects {
+    /// Parse a statement executed on `database`.
+    /// Invalid SQL has no objects, Athena reports the error when the statement runs
+    pub fn parse(sql: &str, database: Option<&str>) -> Self {
+        let Ok(tokens) = tokenize(sql) else {
+            return Self::default();
+        };
+        let tokens = tokens
+            .into_iter()
+            .filter(|t| !t.is_trivia())
+            .collect::<Vec<_>>();
+
+        let mut parser = Parser {
+            tokens: &tokens,
+            database: database.map(str::to_lowercase),
+            objects: Self::default(),
+        };
+        parser.parse();
+
+        let mut objects = parser.objects;
+        if let Some(database) = database {
+            objects
+                .references
+                .insert(Object::Database(database.to_lowercase()));
+        }
+
+        // The database of every table
+        let databases = objects
+            .creates
+            .iter()
+            .chain(&objects.drops)
+            .chain(&objects.references)
+            .filter_map(|o| match o {
+                Object::Table {
+                    database: Some(database),
+                    ..
+                } => Some(Object::Database(database.clone())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        objects.references.extend(databases);
+
+        // Not a reference to itself
+        let own = objects
+            .creates
+            .iter()
+            .chain(&objects.drops)
+            .cloned()
+            .collect::<Vec<_>>();
+        for object in own {
+            objects.references.remove(&object);
+        }
+
+        objects
+    }
+
+    fn touches(&self, object: &Object) -> bool {
+        self.creates.contains(object)
+            || self.drops.contains(object)
+            || self.references.contains(object)
+    }
+}
+
+struct Parser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    database: Option<String>,
+    objects: Objects,
+}
+
+impl Parser<'_, '_> {
+    fn parse(&mut self) {
+        self.parse_head();
+        let names = self.with_names();
+
+        // References anywhere in the statement. For each open parenthesis, whether it
+        // starts a subquery rather than the arguments of a function such as `EXTRACT`
+        let mut parentheses = vec![];
+        for i in 0..self.tokens.len() {
+            if self.is_symbol(i, "(") {
+                parentheses.push(
+                    self.keyword(i + 1)
+                        .is_some_and(|k| matches!(k.as_str(), "SELECT" | "WITH")),
+                );
+                continue;
+            }
+            if self.is_symbol(i, ")") {
+                parentheses.pop();
+                continue;
+            }
+
+            let Some(keyword) = self.keyword(i) else {
+                continue;
+            };
+            let name = match keyword.as_str() {
+                // Not `EXTRACT(YEAR FROM ts)`, `TRIM(BOTH FROM s)`, `SUBSTRING(s FROM 2)`
+                "FROM" if parentheses.last() == Some(&false) => None,
+                // Not a function call such as `FROM UNNEST(...)`
+                "FROM" | "JOIN" => self
+                    .name(i + 1)
+                    .filter(|(_, next)| !self.is_symbol(*next, "(")),
+                // `INSERT INTO t (a, b)`, `MERGE INTO t`
+                "INTO" => self.name(i + 1),
+                _ => None,
+            };
+            if let Some((parts, _)) = name {
+                if let [name] = parts.as_slice() {
+                    if names.contains(name) {
+                        continue;
+                    }
+                }
+                let table = self.table(parts);
+                self.objects.references.insert(table);
+            }
+        }
+    }
+
+    /// Names defined by `WITH` clauses: `WITH a AS (...), b (x, y) AS (...)`
+    fn with_names(&self) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+
+        for i in 0..self.tokens.len() {
+            let starts = self
+                .keyword(i)
+                .is_some_and(|k| matches!(k.as_str(), "WITH" | "RECURSIVE"))
+                || (self.is_symbol(i, ",") && !names.is_empty());
+            if !starts {
+                continue;
+            }
+            let Some(name) = self.identifier(i + 1) else {
+                continue;
+            };
+
+            // Optional column list
+            let mut next = i + 2;
+            if self.is_symbol(next, "(") {
+                let Some(close) = (next..self.tokens.len()).find(|&j| self.is_symbol(j, ")"))
+                else {
+                    continue;
+                };
+                next = close + 1;
+            }
+            if self.keyword(next).as_deref() == Some("AS") && self.is_symbol(next + 1, "(") {
+                names.insert(name);
+            }
+        }
+
+        names
+    }
+
+    /// The object created, dropped or altered by the statement
+    fn parse_head(&mut self) {
+        let mut i = 0;
+        let Some(first) = self.keyword(i) else {
+            return;
+        };
+        i += 1;
+
+        match first.as_str() {
+            "CREATE" | "DROP" => {
+                // Modifiers such as `OR REPLACE`, `EXTERNAL`, `PROTECTED MULTI DIALECT`
+                while self.keyword(i).is_some_and(|k| {
+                    matches!(
+                        k.as_str(),
+                        "OR" | "REPLACE"
+                            | "EXTERNAL"
+                            | "TEMPORARY"
+                            | "PROTECTED"
+                            | "MULTI"
+                            | "DIALECT"
+                    )
+                }) {
+                    i += 1;
+                }
+                let Some(kind) = self.keyword(i) else {
+                    return;
+                };
+                i += 1;
+                // `IF NOT EXISTS`, `IF EXISTS`
+                while self
+                    .keyword(i)
+                    .is_some_and(|k| matches!(k.as_str(), "IF" | "NOT" | "EXISTS"))
+                {
+                    i += 1;
+                }
+                let Some((parts, _)) = self.name(i) else {
+                    return;
+                };
+
+                let object = match kind.as_str() {
+                    "TABLE" | "VIEW" => self.table(parts),
+                    "DATABASE" | "SCHEMA" => match parts.last() {
+                        Some(name) => Object::Database(name.clone()),
+                        None => return,
+                    },
+                    _ => return,
+                };
+                if first == "CREATE" {
+                    self.objects.creates.insert(object);
+                } else {
+                    self.objects.drops.insert(object);
+                }
+            }
+            "ALTER" | "MSCK" | "OPTIMIZE" | "VACUUM" => {
+                // `ALTER TABLE t`, `MSCK REPAIR TABLE t`, `OPTIMIZE t REWRITE DATA`
+                while self
+                    .keyword(i)
+                    .is_some_and(|k| matches!(k.as_str(), "REPAIR" | "TABLE" | "VIEW"))
+                {
+                    i += 1;
+                }
+                let Some((parts, next)) = self.name(i) else {
+                    return;
+                };
+                let table = self.table(parts);
+
+                // `ALTER TABLE t RENAME TO u`, the new name is in the same database
+                if self.keyword(next).as_deref() == Some("RENAME")
+                    && self.keyword(next + 1).as_deref() == Some("TO")
+                {
+                    if let Some((parts, _)) = self.name(next + 2) {
+                        let renamed = match (&table, parts.as_slice()) {
+                            (Object::Table { database, .. }, [name]) => Object::Table {
+                                database: database.clone(),
+                                name: name.clone(),
+                            },
+                            _ => self.table(parts),
+                        };
+                        self.objects.creates.insert(renamed);
+                        self.objects.drops.insert(table);
+                        return;
+                    }
+                }
+                self.objects.references.insert(table);
+            }
+            _ => {}
+        }
+    }
+
+    /// Uppercase word at `i`
+    fn keyword(&self, i: usize) -> Option<String> {
+        self.tokens
+            .get(i)
+            .filter(|t| t.kind == TokenKind::Word)
+            .map(|t| t.text.to_uppercase())
+    }
+
+    fn is_symbol(&self, i: usize, symbol: &str) -> bool {
+        self.tokens
+            .get(i)
+            .is_some_and(|t| t.kind == TokenKind::Symbol && t.text == symbol)
+    }
+
+    /// Lowercase parts of a qualified name at `i`, such as `db.t`,
+    /// and the position after the name
+    fn name(&self, i: usize) -> Option<(Vec<String>, usize)> {
+        let mut parts = vec![self.identifier(i)?];
+        let mut next = i + 1;
+
+        while self.is_symbol(next, ".") {
+            let Some(part) = self.identifier(next + 1) else {
+                break;
+            };
+            parts.push(part);
+            next += 2;
+        }
+
+        Some((parts, next))
+    }
+
+    fn identifier(&self, i: usize) -> Option<String> {
+        let token = self.tokens.get(i)?;
+        match token.kind {
+            TokenKind::Word => Some(token.text.to_lowercase()),
+            TokenKind::Quoted(quote @ ('"' | '`')) => {
+                let inner = &token.text[1..token.text.len() - 1];
+                let doubled = format!("{}{}", quote, quote);
+                Some(inner.replace(&doubled, &quote.to_string()).to_lowercase())
+            }
+            _ => None,
+        }
+    }
+
+    /// A table from the parts of its name: `t`, `db.t` or `catalog.db.t`
+    fn table(&self, mut parts: Vec<String>) -> Object {
+        let name = parts.pop().unwrap_or_default();
+        let database = parts.pop().or_else(|| self.database.clone());
+        Object::Table { database, name }
+    }
+}
+
+/// Dependencies of each statement, as positions in `statements`.
+/// `keep_order[position]` prevents a statement from waiting for a creator after it
+pub fn infer(statements: &[Objects], keep_order: &[bool]) -> Vec<BTreeSet<usize>> {
+    let mut dependencies = vec![BTreeSet::new(); statements.len()];
+    // Statements waiting for a creator after them, as (position, creator)
+    let mut forward = BTreeSet::new();
+
+    for (position, objects) in statements.iter().enumerate() {
+        for object in &objects.references {
+            // The last creator before
+            let before = (0..position)
+                .rev()
+                .find(|&p| statements[p].creates.contains(object));
+            if before.is_some() || keep_order.get(position).copied().unwrap_or(false) {
+                dependencies[position].extend(before);
+                continue;
+            }
+
+            // The first creator after, when the object is not dropped in between
+            let after = (position + 1..statements.len())
+                .take_while(|&p| !statements[p].drops.contains(object))
+                .find(|&p| statements[p].creates.contains(object));
+            if let Some(after) = after {
+                dependencies[position].insert(after);
+                forward.insert((position, after));
+            }
+        }
+    }
+
+    // A create or a drop keeps its place among the statements using the object
+    for (position, objects) in statements.iter().enumerate() {
+        for object in objects.creates.iter().chain(&objects.drops) {
+            for (p, other) in statements.iter().enumerate() {
+                if p == position || !other.touches(object) {
+                    continue;
+                }
+                let (from, to) = if p < position {
+                    (position, p)
+                } else {
+                    (p, position)
+                };
+                if !forward.contains(&(to, from)) {
+                    dependencies[from].insert(to);
+                }
+            }
+        }
+    }
+
+    dependencies
+}
+
+/// Positions sorted so that every statement comes after its dependencies.
+/// Statements without dependencies between them keep their order
+pub fn topological_order(dependencies: &[BTreeSet<usize>]) -> Result<Vec<usize>> {
+    let count = dependencies.len();
+    let mut remaining = dependencies.to_vec();
+    let mut done = vec![false; count];
+    let mut order = Vec::with_capacity(count);
+
+    while order.len() < count {
+        let Some(next) = (0..count).find(|&p| !done[p] && remaining[p].is_empty()) else {
+            let cycle = find_cycle(&remaining, &done);
+            bail!(
+                "dependency cycle between statements {}",
+                cycle
+                    .iter()
+                    .map(|p| format!("#{}", p + 1))
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            );
+        };
+
+        done[next] = true;
+        order.push(next);
+        for deps in &mut remaining {
+            deps.remove(&next);
+        }
+    }
+
+    Ok(order)
+}
+
+/// A cycle among the statements not done yet, every one of them has a remaining dependency
+fn find_cycle(remaining: &[BTreeSet<usize>], done: &[bool]) -> Vec<usize> {
+    let Some(start) = (0..remaining.len()).find(|&p| !done[p]) else {
+        return vec![];
+    };
+
+    let mut path = vec![start];
+    let mut current = start;
+    while let Some(&next) = remaining[current].iter().next() {
+        if let Some(i) = path.iter().position(|&p| p == next) {
+            let mut cycle = path[i..].to_vec();
+            cycle.push(next);
+            return cycle;
+        }
+        path.push(next);
+        current = next;
+    }
+
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(database: Option<&str>, name: &str) -> Object {
+        Object::Table {
+            database: database.map(str::to_string),
+            name: name.to_string(),
+        }
+    }
+
+    fn database(name: &str) -> Object {
+        Object::Database(name.to_string())
+    }
+
+    #[test]
+    fn test_parse_objects() {
+        let objects = Objects::parse("CREATE DATABASE IF NOT EXISTS db", None);
+        assert_eq!(objects.creates, BTreeSet::from([database("db")]));
+        assert!(objects.references.is_empty());
+
+        let objects = Objects::parse(
+            "-- comment\nCREATE EXTERNAL TABLE IF NOT EXISTS `t1` (id string) LOCATION 's3://b/t1'",
+            Some("db"),
+        );
+        assert_eq!(objects.creates, BTreeSet::from([table(Some("db"), "t1")]));
+        assert_eq!(objects.references, BTreeSet::from([database("db")]));
+
+        let objects = Objects::parse(
+            r#"CREATE OR REPLACE VIEW "v" AS SELECT * FROM db2.t1 a JOIN "T2" b ON a.id = b.id"#,
+            None,
+        );
+        assert_eq!(objects.creates, BTreeSet::from([table(None, "v")]));
+        assert_eq!(
+            objects.references,
+            BTreeSet::from([table(Some("db2"), "t1"), table(None, "t2"), database("db2")])
+        );
+
+        let objects = Objects::parse(
+            "INSERT INTO t (a, b) SELECT x, y FROM s CROSS JOIN UNNEST(arr) AS u(x)",
+            Some("db"),
+        );
+        assert_eq!(
+            objects.references,
+            BTreeSet::from([
+                table(Some("db"), "t"),
+                table(Some("db"), "s"),
+                database("db")
+            ])
+        );
+
+        let objects = Objects::parse("MSCK REPAIR TABLE awsdatacatalog.db.t", None);
+        assert_eq!(
+            objects.references,
+            BTreeSet::from([table(Some("db"), "t"), database("db")])
+        );
+
+        let objects = Objects::parse("DROP TABLE IF EXISTS t", None);
+        assert_eq!(objects.drops, BTreeSet::from([table(None, "t")]));
+
+        // A string is not a name
+        let objects = Objects::parse("SELECT 'FROM x' FROM (SELECT 1)", None);
+        assert!(objects.references.is_empty());
+    }
+
+    #[test]
+    fn test_parse_with_and_functions() {
+        // The names of the `WITH` clause are not tables
+        let objects = Objects::parse(
+            "WITH recent AS (SELECT * FROM events), totals (id, n) AS (SELECT id, count(*) FROM recent GROUP BY id) SELECT * FROM totals JOIN users u ON totals.id = u.id",
+            None,
+        );
+        assert_eq!(
+            objects.references,
+            BTreeSet::from([table(None, "events"), table(None, "users")])
+        );
+
+        // `FROM` in the arguments of a function is not a table
+        let objects = Objects::parse(
+            "SELECT EXTRACT(YEAR FROM ts), TRIM(BOTH ' ' FROM name), SUBSTRING(s FROM 2 FOR 3) FROM t WHERE id IN (SELECT id FROM u)",
+            None,
+        );
+        assert_eq!(
+            objects.references,
+            BTreeSet::from([table(None, "t"), table(None, "u")])
+        );
+
+        let objects = Objects::parse("ALTER TABLE db.events RENAME TO events_v1", None);
+        assert_eq!(objects.drops, BTreeSet::from([table(Some("db"), "events")]));
+        assert_eq!(
+            objects.creates,
+            BTreeSet::from([table(Some("db"), "events_v1")])
+        );
+    }
+
+    #[test]
+    fn test_infer_forward_reference() {
+        // The view is included before the table it selects from
+        let statements = vec![
+            Objects::parse("CREATE DATABASE db", None),
+            Objects::parse("CREATE VIEW v AS SELECT * FROM t", Some("db")),
+            Objects::parse("CREATE TABLE t (id int)", Some("db")),
+        ];
+
+        let dependencies = infer(&statements, &[]);
+        assert_eq!(
+            dependencies,
+            vec![BTreeSet::new(), BTreeSet::from([0, 2]), BTreeSet::from([0])]
+        );
+        assert_eq!(topological_order(&dependencies).unwrap(), vec![0, 2, 1]);
+
+        // Unless the view keeps its order
+        let dependencies = infer(&statements, &[false, true, false]);
+        assert_eq!(
+            dependencies,
+            vec![BTreeSet::new(), BTreeSet::from([0]), BTreeSet::from([0, 1])]
+        );
+        assert_eq!(topological_order(&dependencies).unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_infer_rename() {
+        let statements = vec![
+            Objects::parse("ALTER TABLE events RENAME TO events_v1", None),
+            Objects::parse("CREATE TABLE events (id int)", None),
+            Objects::parse("INSERT INTO events SELECT id FROM events_v1", None),
+        ];
+
+        // The insert waits for the rename, with or without `keep_order`
+        for keep_order in [[false; 3], [true; 3]] {
+            let dependencies = infer(&statements, &keep_order);
+            assert_eq!(
+                dependencies,
+                vec![BTreeSet::new(), BTreeSet::from([0]), BTreeSet::from([0, 1])]
+            );
+            assert_eq!(topological_order(&dependencies).unwrap(), vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_infer_with() {
+        // A `WITH` name does not bind to the table created later
+        let statements = vec![
+            Objects::parse(
+                "INSERT INTO t SELECT * FROM (WITH s AS (SELECT 1 AS id) SELECT * FROM s)",
+                None,
+            ),
+            Objects::parse("CREATE TABLE s (id int)", None),
+        ];
+        let dependencies = infer(&statements, &[]);
+        assert_eq!(dependencies, vec![BTreeSet::new(), BTreeSet::new()]);
+        assert_eq!(topological_order(&dependencies).unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_infer_drop() {
+        let statements = vec![
+            Objects::parse("SELECT * FROM t", None),
+            Objects::parse("DROP TABLE t", None),
+            Objects::parse("CREATE TABLE t (id int)", None),
+            Objects::parse("SELECT * FROM t", None),
+        ];
+        let dependencies = infer(&statements, &[]);
+        assert_eq!(
+            dependencies,
+            vec![
+                BTreeSet::new(),
+                BTreeSet::from([0]),
+                BTreeSet::from([0, 1]),
+                BTreeSet::from([1, 2])
+            ]
+        );
+        assert_eq!(topological_order(&dependencies).unwrap(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_topological_order_stable() {
+        let dependencies = vec![
+            BTreeSet::new(),
+            BTreeSet::new(),
+            BTreeSet::from([3]),
+            BTreeSet::new(),
+        ];
+        assert_eq!(topological_order(&dependencies).unwrap(), vec![0, 1, 3, 2]);
+    }
+
+    #[test]
+    fn test_topological_order_cycle() {
+        let statements = vec![
+            Objects::parse("SELECT 1", None),
+            Objects::parse("CREATE VIEW a AS SELECT * FROM b", None),
+            Objects::parse("CREATE VIEW b AS SELECT * FROM a", None),
+        ];
+        let err = topological_order(&infer(&statements, &[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dependency cycle between statements #2 -> #3 -> #2"
+        );
+    }
+}
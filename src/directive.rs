@@ -0,0 +1,423 @@
+//! Statement directives in SQL comments
+//!
+//! Directives are SQL comments placed before a statement. They are read from the
+//! leading comments of each statement, see [`crate::sql::Statement`].
+//!
+//! # Database
+//!
+//! The target database works like a `USE`: once set, it applies to the statement
+//! and every following statement until another directive changes it.
+//!
+//! ```sql
+//! -- Database: db1
+//! CREATE EXTERNAL TABLE a (id INT) LOCATION 's3://bucket/a/';
+//! -- runs on db1 too
+//! CREATE EXTERNAL TABLE b (id INT) LOCATION 's3://bucket/b/';
+//!
+//! /* Database: db2 */
+//! CREATE VIEW v AS SELECT * FROM b;
+//! ```
+//!
+//! - `-- Database once: <name>` sets the database of the next statement only
+//! - `-- Database reset` goes back to the default database of the workgroup
+//!
+//! Both forms also work as block comments: `/* Database once: db1 */`, `/* Database reset */`.
+//!
+//! # Statement options
+//!
+//! `-- athena:` directives set options of the next statement only, as `key=value` pairs:
+//!
+//! ```sql
+//! -- athena: catalog=AwsDataCatalog workgroup=etl timeout=10m
+//! -- athena: output_location=s3://bucket/results/
+//! INSERT INTO t SELECT * FROM s;
+//! ```
+//!
+//! | Key               | Maps to                                  |
+//! |-------------------|------------------------------------------|
+//! | `catalog`         | `QueryExecutionContext.Catalog`          |
+//! | `database`        | `QueryExecutionContext.Database`         |
+//! | `output_location` | `ResultConfiguration.OutputLocation`     |
+//! | `workgroup`       | `StartQueryExecution.WorkGroup`          |
+//! | `timeout`         | Stop the query after this duration, such as `30s` or `10m` |
+//! | `barrier`         | Wait for the previous statements, see [`crate::schedule`] |
+//! | `once`            | Record the statement in the ledger, later applies skip it, see [`crate::state`] |
+//! | `keep_order`      | Do not wait for a later statement that creates what it uses, see [`crate::deps`] |
+//!
+//! Unknown keys are an error. The `database` key overrides the `Database` directives
+//! for this statement only, like `-- Database once:`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::time::Duration;
+
+// Compile regex patterns once and reuse them for extracting database names from SQL
+#[allow(clippy::expect_used)]
+static DATABASE_PATTERNS: Lazy<Vec<Regex>> = Lazy::new(|| {
+    vec![
+        // Matches: -- Database: db_name
+        Regex::new(r"(?i)--\s+Database:\s(.*)").expect("invalid regex pattern"),
+        // Matches: /* Database: db_name */
+        Regex::new(r"(?i)/*\s+Database:\s([^\s]+)\s\*/").expect("invalid regex pattern"),
+    ]
+});
+
+#[allow(clippy::expect_used)]
+static DATABASE_ONCE_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: -- Database once: db_name or /* Database once: db_name */
+    Regex::new(r"(?i)^(?:--|/\*)\s*Database\s+once:\s*([^\s*]+)").expect("invalid regex pattern")
+});
+
+#[allow(clippy::expect_used)]
+static DATABASE_RESET_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: -- Database reset or /* Database reset */
+    Regex::new(r"(?i)^(?:--|/\*)\s*Database\s+reset\b").expect("invalid regex pattern")
+});
+
+#[allow(clippy::expect_used)]
+static ATHENA_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: -- athena: key=value ... or /* athena: key=value ... */
+    Regex::new(r"(?is)^(?:--|/\*)\s*athena:(.*?)(?:\*/)?$").expect("invalid regex pattern")
+});
+
+const STATEMENT_OPTION_KEYS: [&str; 8] = [
+    "catalog",
+    "database",
+    "workgroup",
+    "output_location",
+    "timeout",
+    "barrier",
+    "once",
+    "keep_order",
+];
+
+/// A database directive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatabaseDirective {
+    /// Database of this and the following statements
+    Set(String),
+    /// Database of this statement only
+    Once(String),
+    /// Back to the default database of the workgroup
+    Reset,
+}
+
+/// Tracks the current database along the statements
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseResolver {
+    current: Option<String>,
+}
+
+impl DatabaseResolver {
+    /// Apply the directives in the leading comments of a statement
+    /// and return the database of the statement
+    pub fn resolve<S: AsRef<str>>(&mut self, comments: &[S]) -> Option<String> {
+        let mut once = None;
+
+        for comment in comments {
+            match parse_database_directive(comment.as_ref()) {
+                Some(DatabaseDirective::Set(database)) => self.current = Some(database),
+                Some(DatabaseDirective::Once(database)) => once = Some(database),
+                Some(DatabaseDirective::Reset) => self.current = None,
+                None => {}
+            }
+        }
+
+        once.or_else(|| self.current.clone())
+    }
+}
+
+/// Parse a database directive from a comment
+pub fn parse_database_directive(comment: &str) -> Option<DatabaseDirective> {
+    let comment = comment.trim();
+
+    if let Some(caps) = DATABASE_ONCE_PATTERN.captures(comment) {
+        let name = caps.get(1).map_or("", |m| m.as_str());
+        return Some(DatabaseDirective::Once(name.to_string()));
+    }
+
+    if DATABASE_RESET_PATTERN.is_match(comment) {
+        return Some(DatabaseDirective::Reset);
+    }
+
+    get_database_from_sql(comment).map(DatabaseDirective::Set)
+}
+
+/// Options of a single statement, set with `-- athena:` directives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatementOptions {
+    pub catalog: Option<String>,
+    pub database: Option<String>,
+    pub workgroup: Option<String>,
+    pub output_location: Option<String>,
+    pub timeout: Option<Duration>,
+    /// Start after every previous statement, the following ones start after it
+    pub barrier: bool,
+    /// Record in the ledger once applied, even if it is not idempotent
+    pub once: bool,
+    /// Do not start after a later statement that creates an object it references
+    pub keep_order: bool,
+}
+
+impl StatementOptions {
+    /// Read the `-- athena:` directives in the leading comments of a statement.
+    /// Later directives override earlier ones
+    pub fn from_comments<S: AsRef<str>>(comments: &[S]) -> Result<Self> {
+        let mut options = Self::default();
+
+        for comment in comments {
+            if let Some(pairs) = parse_athena_directive(comment.as_ref()) {
+                for (key, value) in pairs {
+                    options
+                        .set(key, value)
+                        .with_context(|| format!("invalid directive `{}`", comment.as_ref()))?;
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let slot = match key.to_lowercase().as_str() {
+            "catalog" => &mut self.catalog,
+            "database" => &mut self.database,
+            "workgroup" => &mut self.workgroup,
+            "output_location" => &mut self.output_location,
+            "timeout" => {
+                let timeout = humantime::parse_duration(value)
+                    .with_context(|| format!("invalid timeout `{}`", value))?;
+                self.timeout = Some(timeout);
+                return Ok(());
+            }
+            // Flags, `barrier` is the same as `barrier=true`
+            "barrier" => {
+                self.barrier = parse_flag(key, value)?;
+                return Ok(());
+            }
+            "once" => {
+                self.once = parse_flag(key, value)?;
+                return Ok(());
+            }
+            "keep_order" => {
+                self.keep_order = parse_flag(key, value)?;
+                return Ok(());
+            }
+            _ => bail!(
+                "unknown key `{}`, expected one of: {}",
+                key,
+                STATEMENT_OPTION_KEYS.join(", ")
+            ),
+        };
+
+        if value.is_empty() {
+            return Err(anyhow!("missing value, expected `{}=<value>`", key));
+        }
+        *slot = Some(value.to_string());
+
+        Ok(())
+    }
+}
+
+/// Value of a flag, an empty value is `true`
+fn parse_flag(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "" | "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!("invalid {} `{}`, expected true or false", key, value),
+    }
+}
+
+/// Parse the `key=value` pairs of an `-- athena:` directive,
+/// `None` if the comment is not a directive
+fn parse_athena_directive(comment: &str) -> Option<Vec<(&str, &str)>> {
+    let caps = ATHENA_PATTERN.captures(comment.trim())?;
+    let body = caps.get(1).map_or("", |m| m.as_str());
+
+    Some(
+        body.split_whitespace()
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .collect(),
+    )
+}
+
+pub fn get_database_from_sql<S: AsRef<str>>(sql: S) -> Option<String> {
+    for r in DATABASE_PATTERNS.iter() {
+        if let Some(caps) = r.captures(sql.as_ref()) {
+            let name = caps.get(1).map_or("", |m| m.as_str());
+            return Some(name.trim().to_string());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_database_from_sql() {
+        let sql = "-- database: db0";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
+
+        let sql = "-- database: db1\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db1");
+
+        let sql = "-- Database: db2\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db2");
+
+        let sql = "-- Database: db3 \nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db3");
+
+        let sql = "-- Database: db4    \nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db4");
+
+        let sql = "--   Database: db4    \nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db4");
+
+        let sql = "/* Database: db5 */\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db5");
+
+        let sql = "/* database: db6 */\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db6");
+
+        let sql = "/*        database: db7 */\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db7");
+
+        let sql = "SELECT * FROM ...;";
+        assert!(get_database_from_sql(sql).is_none());
+
+        let sql = "-- database: db0 \n-- database: db1";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
+
+        let sql = "/* database: db0 */\n/* database: db1 */";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
+    }
+
+    #[test]
+    fn test_parse_database_directive() {
+        assert_eq!(
+            parse_database_directive("-- Database: db1"),
+            Some(DatabaseDirective::Set("db1".to_string()))
+        );
+        assert_eq!(
+            parse_database_directive("/* Database: db1 */"),
+            Some(DatabaseDirective::Set("db1".to_string()))
+        );
+        assert_eq!(
+            parse_database_directive("-- Database once: db2 "),
+            Some(DatabaseDirective::Once("db2".to_string()))
+        );
+        assert_eq!(
+            parse_database_directive("/* database once: db2 */"),
+            Some(DatabaseDirective::Once("db2".to_string()))
+        );
+        assert_eq!(
+            parse_database_directive("-- Database reset"),
+            Some(DatabaseDirective::Reset)
+        );
+        assert_eq!(
+            parse_database_directive("/* DATABASE RESET */"),
+            Some(DatabaseDirective::Reset)
+        );
+        assert_eq!(parse_database_directive("-- a comment"), None);
+        assert_eq!(parse_database_directive("-- Databases are great"), None);
+    }
+
+    #[test]
+    fn test_database_resolver() {
+        let mut resolver = DatabaseResolver::default();
+
+        assert_eq!(resolver.resolve::<&str>(&[]), None);
+        assert_eq!(
+            resolver.resolve(&["-- Database: db1"]).as_deref(),
+            Some("db1")
+        );
+        // Carried over
+        assert_eq!(resolver.resolve::<&str>(&[]).as_deref(), Some("db1"));
+        assert_eq!(resolver.resolve(&["-- comment"]).as_deref(), Some("db1"));
+        // Override for one statement
+        assert_eq!(
+            resolver.resolve(&["-- Database once: db2"]).as_deref(),
+            Some("db2")
+        );
+        assert_eq!(resolver.resolve::<&str>(&[]).as_deref(), Some("db1"));
+        // Last directive wins
+        assert_eq!(
+            resolver
+                .resolve(&["/* Database: db3 */", "-- Database: db4"])
+                .as_deref(),
+            Some("db4")
+        );
+        // Reset
+        assert_eq!(resolver.resolve(&["-- Database reset"]), None);
+        assert_eq!(resolver.resolve::<&str>(&[]), None);
+    }
+
+    #[test]
+    fn test_statement_options() {
+        let options = StatementOptions::from_comments(&[
+            "-- a comment",
+            "-- athena: catalog=AwsDataCatalog workgroup=etl timeout=10m",
+            "/* athena: output_location=s3://bucket/results/ database=db1 */",
+        ])
+        .unwrap();
+        assert_eq!(
+            options,
+            StatementOptions {
+                catalog: Some("AwsDataCatalog".to_string()),
+                database: Some("db1".to_string()),
+                workgroup: Some("etl".to_string()),
+                output_location: Some("s3://bucket/results/".to_string()),
+                timeout: Some(Duration::from_secs(600)),
+                barrier: false,
+                once: false,
+                keep_order: false,
+            }
+        );
+
+        let options = StatementOptions::from_comments(&["-- athena: barrier"]).unwrap();
+        assert!(options.barrier);
+        let options = StatementOptions::from_comments(&["-- athena: barrier=false"]).unwrap();
+        assert!(!options.barrier);
+        let options = StatementOptions::from_comments(&["-- athena: once"]).unwrap();
+        assert!(options.once);
+        let options = StatementOptions::from_comments(&["-- athena: keep_order"]).unwrap();
+        assert!(options.keep_order);
+
+        // Later directives override earlier ones
+        let options =
+            StatementOptions::from_comments(&["-- athena: workgroup=a", "-- Athena: workgroup=b"])
+                .unwrap();
+        assert_eq!(options.workgroup.as_deref(), Some("b"));
+
+        let options = StatementOptions::from_comments::<&str>(&[]).unwrap();
+        assert_eq!(options, StatementOptions::default());
+    }
+
+    #[test]
+    fn test_statement_options_invalid() {
+        let err = StatementOptions::from_comments(&["-- athena: workgroups=etl"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown key `workgroups`"));
+
+        let err = StatementOptions::from_comments(&["-- athena: timeout=soon"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid timeout `soon`"));
+
+        let err = StatementOptions::from_comments(&["-- athena: catalog"]).unwrap_err();
+        assert!(format!("{:#}", err).contains("missing value"));
+    }
+
+    #[test]
+    fn test_get_database_from_sql_with_comment() {
+        let sql = "-- database: db0\n-- comment\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
+
+        let sql = "-- database: db0\n-- comment\n-- comment\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
+
+        let sql = "-- database: db0\n-- comment\n-- comment\n-- comment\nSELECT * FROM ...;";
+        assert_eq!(get_database_from_sql(sql).unwrap(), "db0");
+    }
+}
@@ -0,0 +1,97 @@
+//! Error kinds and process exit codes
+//!
+//! Errors are plain [`anyhow::Error`]s. The ones that need a distinct exit code carry
+//! an [`ErrorKind`] in their context chain, which `main` maps to the exit code:
+//!
+//! | Exit code | Meaning                                           |
+//! |-----------|---------------------------------------------------|
+//! | 0         | Success                                           |
+//! | 1         | Any other error                                   |
+//! | 2         | Invalid command line usage, reported by clap      |
+//! | 3         | The templates could not be rendered               |
+//! | 4         | AWS error, such as missing credentials            |
+//! | 5         | A statement failed or was cancelled               |
+//! | 6         | A statement timed out                             |
+//! | 130       | Interrupted by Ctrl-C or SIGTERM                  |
+
+use std::fmt;
+
+use crate::signal::INTERRUPTED_EXIT_CODE;
+
+/// Kind of an error, to be attached with [`anyhow::Context::context`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The templates could not be rendered or split into statements
+    Render,
+    /// An AWS request failed
+    Aws,
+    /// A statement did not succeed
+    QueryFailed,
+    /// A statement ran longer than its timeout
+    Timeout,
+    /// Stopped by Ctrl-C or SIGTERM
+    Interrupted,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Render => 3,
+            Self::Aws => 4,
+            Self::QueryFailed => 5,
+            Self::Timeout => 6,
+            Self::Interrupted => INTERRUPTED_EXIT_CODE,
+        }
+    }
+
+    /// The kind of an error, the outermost one of the context chain
+    pub fn of(err: &anyhow::Error) -> Option<Self> {
+        err.downcast_ref::<Self>().copied()
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            Self::Render => "could not render the SQL",
+            Self::Aws => "AWS request failed",
+            Self::QueryFailed => "query failed",
+            Self::Timeout => "query timed out",
+            Self::Interrupted => "interrupted",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for ErrorKind {}
+
+/// Exit code of the process for an error
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    ErrorKind::of(err).map_or(1, ErrorKind::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&anyhow!("oops")), 1);
+
+        let err = anyhow!("template not found").context(ErrorKind::Render);
+        assert_eq!(exit_code(&err), 3);
+
+        // Found anywhere in the chain
+        let err = anyhow!("statement #2 was cancelled")
+            .context(ErrorKind::QueryFailed)
+            .context("could not apply");
+        assert_eq!(exit_code(&err), 5);
+
+        let err = Err::<(), _>(anyhow!("oops"))
+            .context(ErrorKind::Timeout)
+            .unwrap_err();
+        assert_eq!(format!("{:#}", err), "query timed out: oops");
+        assert_eq!(exit_code(&err), 6);
+    }
+}
@@ -0,0 +1,881 @@
+//! Query executions
+//!
+//! Follow up on the queries started by `athena apply`, `athena query` or any other
+//! client, by their query execution id:
+//!
+//! - `athena status <id>...`: state, timings, data scanned and error of the queries
+//! - `athena wait <id>...`: wait until the queries finish, the exit code is non-zero
+//!   if any of them did not succeed, see [`crate::error`]
+//! - `athena wait --run <journal>`: wait for the statements submitted by
+//!   `athena apply --no-wait`, then update the journal and the ledger of the run,
+//!   see [`crate::journal`] and [`crate::state`]
+//! - `athena cancel <id>...`: stop the queries with `StopQueryExecution`
+//! - `athena history`: the recent queries of a workgroup, filtered by state and
+//!   submission time, with `ListQueryExecutions` and `BatchGetQueryExecution`
+
+use anyhow::{anyhow, bail, Context, Result};
+use aws_config::SdkConfig;
+use aws_sdk_athena::{
+    primitives::DateTime as AwsDateTime,
+    types::{QueryExecution, QueryExecutionState},
+    Client,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{error, info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::time::{sleep, Instant};
+
+use crate::apply::{execution_state, print_resume_hint, run_status, stop_query};
+use crate::config::{self, AwsArgs, Settings};
+use crate::error::ErrorKind;
+use crate::failure::error_category_name;
+use crate::journal::{Journal, JournalEntry, RunStatus};
+use crate::poll::{Backoff, PollArgs};
+use crate::signal::Shutdown;
+use crate::state::{StateStore, Target};
+use crate::utils::format_table;
+
+// Constants
+/// Maximum number of ids of `BatchGetQueryExecution` and `ListQueryExecutions`
+const BATCH_SIZE: usize = 50;
+/// Maximum number of pages of `ListQueryExecutions` searched by `athena history`
+const HISTORY_MAX_PAGES: usize = 20;
+const DEFAULT_WORKGROUP: &str = "primary";
+const QUERY_PREVIEW_LENGTH: usize = 60;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ExecutionArgs {
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    /// AWS Profile
+    #[arg(long, short)]
+    pub profile: Option<String>,
+
+    /// AWS Region
+    #[arg(long, short)]
+    pub region: Option<String>,
+}
+
+impl ExecutionArgs {
+    /// The settings and the Athena client
+    async fn client(&self, workgroup: Option<String>) -> Result<(Settings, Client)> {
+        let (settings, shared_config) = self.aws_config(workgroup).await?;
+        Ok((settings, Client::new(&shared_config)))
+    }
+
+    async fn aws_config(&self, workgroup: Option<String>) -> Result<(Settings, SdkConfig)> {
+        let aws_args = AwsArgs {
+            profile: self.profile.clone(),
+            region: self.region.clone(),
+            workgroup,
+            output_location: None,
+        };
+        let settings = config::resolve(self.context.as_deref(), self.env.as_deref(), &aws_args)?;
+        let shared_config = config::load_aws_config(&settings).await;
+
+        Ok((settings, shared_config))
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Status {
+    /// Query execution ids, as printed by `athena apply`
+    #[arg(required = true, value_name = "QUERY_EXECUTION_ID")]
+    pub ids: Vec<String>,
+
+    #[command(flatten)]
+    pub common: ExecutionArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Wait {
+    /// Query execution ids, as printed by `athena apply`
+    #[arg(
+        required_unless_present = "run",
+        conflicts_with = "run",
+        value_name = "QUERY_EXECUTION_ID"
+    )]
+    pub ids: Vec<String>,
+
+    /// Journal of a run submitted by `athena apply --no-wait`, such as
+    /// `.athena/runs/20240101T120000Z-1a2b3c.json`: wait for its statements,
+    /// then update the journal and the ledger. The environment, profile and region
+    /// of the run are used by default
+    #[arg(long, value_name = "RUN_FILE")]
+    pub run: Option<PathBuf>,
+
+    /// Stop waiting after this duration, such as `30s` or `10m`. The queries keep running
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub timeout: Option<Duration>,
+
+    #[command(flatten)]
+    pub poll: PollArgs,
+
+    #[command(flatten)]
+    pub common: ExecutionArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Cancel {
+    /// Query execution ids, as printed by `athena apply`
+    #[arg(required = true, value_name = "QUERY_EXECUTION_ID")]
+    pub ids: Vec<String>,
+
+    #[command(flatten)]
+    pub common: ExecutionArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct History {
+    /// AWS Athena Workgroup, `primary` by default
+    #[arg(long, short)]
+    pub workgroup: Option<String>,
+
+    /// Only the queries in these states, such as `failed,cancelled`
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub state: Vec<ExecutionState>,
+
+    /// Only the queries submitted since this duration ago, such as `2h`,
+    /// or since this time, such as `2024-01-01T12:00:00Z`
+    #[arg(long, value_name = "DURATION|TIME", value_parser = parse_since)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Maximum number of queries listed
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u16).range(1..))]
+    pub limit: u16,
+
+    #[command(flatten)]
+    pub common: ExecutionArgs,
+}
+
+/// State of a query, to filter the history
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl ExecutionState {
+    fn matches(self, state: &QueryExecutionState) -> bool {
+        let expected = match self {
+            Self::Queued => QueryExecutionState::Queued,
+            Self::Running => QueryExecutionState::Running,
+            Self::Succeeded => QueryExecutionState::Succeeded,
+            Self::Failed => QueryExecutionState::Failed,
+            Self::Cancelled => QueryExecutionState::Cancelled,
+        };
+        *state == expected
+    }
+}
+
+pub async fn status(args: Status) -> Result<()> {
+    let (_, client) = args.common.client(None).await?;
+
+    let descriptions = get_executions(&client, &args.ids)
+        .await?
+        .iter()
+        .map(describe)
+        .collect::<Vec<_>>();
+    print!("{}", descriptions.join("\n"));
+
+    Ok(())
+}
+
+pub async fn wait(args: Wait) -> Result<()> {
+    if let Some(run_file) = &args.run {
+        return wait_run(&args, run_file).await;
+    }
+
+    let (_, client) = args.common.client(None).await?;
+
+    // Ctrl-C stops waiting, not the queries
+    let (shutdown, _) = Shutdown::listen_once();
+    let executions = wait_for(
+        &client,
+        &args.ids,
+        &args.poll,
+        args.timeout,
+        shutdown,
+        |execution| println!("{}", summary(execution)),
+    )
+    .await?;
+
+    let failed = executions
+        .iter()
+        .filter(|e| execution_state(e) != QueryExecutionState::Succeeded)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!(
+            "{} of {} query(ies) did not succeed",
+            failed,
+            executions.len()
+        ))
+        .context(ErrorKind::QueryFailed);
+    }
+
+    Ok(())
+}
+
+/// Wait for the `RUNNING` statements of a journal, updated as they finish.
+/// The statements that succeeded are recorded in the ledger, as `athena apply` does
+async fn wait_run(args: &Wait, run_file: &Path) -> Result<()> {
+    let mut journal = Journal::read_file(run_file)?;
+    if let (Some(env), Some(applied)) = (&args.common.env, &journal.env) {
+        if env != applied {
+            bail!(
+                "run {} was applied with --env {}, not {}",
+                journal.run_id,
+                applied,
+                env
+            );
+        }
+    }
+
+    // The project of the run file, with the environment, profile and region of the run
+    let state_dir = fs::canonicalize(run_file)
+        .ok()
+        .and_then(|path| Journal::state_dir_of(&path));
+    let common = ExecutionArgs {
+        context: args.common.context.clone().or_else(|| {
+            state_dir
+                .as_deref()
+                .and_then(Path::parent)
+                .map(Path::to_path_buf)
+        }),
+        env: args.common.env.clone().or_else(|| journal.env.clone()),
+        profile: args
+            .common
+            .profile
+            .clone()
+            .or_else(|| journal.profile.clone()),
+        region: args
+            .common
+            .region
+            .clone()
+            .or_else(|| journal.region.clone()),
+    };
+    let (settings, shared_config) = common.aws_config(None).await?;
+    let client = Client::new(&shared_config);
+
+    let ids = journal
+        .statements
+        .iter()
+        .filter(|e| e.status == RunStatus::Running)
+        .filter_map(|e| e.query_execution_id.clone())
+        .collect::<Vec<_>>();
+    info!(
+        "Waiting for {} statement(s) of run {}",
+        ids.len(),
+        journal.run_id
+    );
+
+    // Ctrl-C stops waiting, not the queries
+    let (shutdown, _) = Shutdown::listen_once();
+    let waited = wait_for(
+        &client,
+        &ids,
+        &args.poll,
+        args.timeout,
+        shutdown,
+        |execution| {
+            println!("{}", summary(execution));
+            let id = execution.query_execution_id();
+            if let Some(entry) = journal
+                .statements
+                .iter_mut()
+                .find(|e| e.query_execution_id.as_deref() == id)
+            {
+                entry.status = run_status(&execution_state(execution));
+            }
+            if let Err(e) = journal.write_file(run_file) {
+                warn!("{:#}", e);
+            }
+        },
+    )
+    .await;
+
+    let waited_for = |e: &&JournalEntry| {
+        e.query_execution_id
+            .as_ref()
+            .is_some_and(|id| ids.contains(id))
+    };
+    let applied = journal
+        .statements
+        .iter()
+        .filter(waited_for)
+        .filter(|e| e.status == RunStatus::Succeeded && e.recorded)
+        .collect::<Vec<_>>();
+    if !applied.is_empty() {
+        let mut store = StateStore::from_settings(&settings, common.context.as_deref())?;
+        if let Some(dir) = state_dir {
+            store = store.with_dir(dir);
+        }
+        let mut ledger = store.load(&shared_config).await?;
+        let target = Target::resolve(&shared_config).await;
+        for entry in applied {
+            ledger.insert(entry.ledger_entry(&target, Utc::now()));
+        }
+        store.save(&ledger)?;
+        // The local ledger is up to date, the S3 copy may not be
+        if let Err(e) = store.upload(&ledger, &shared_config).await {
+            error!("{:#}", e);
+        }
+    }
+
+    print!("{}", format_run(&journal));
+    if let Err(e) = waited {
+        print_resume_hint(&journal.run_id);
+        return Err(e);
+    }
+
+    let failed = journal
+        .statements
+        .iter()
+        .filter(waited_for)
+        .filter(|e| e.status != RunStatus::Succeeded)
+        .map(|e| format!("#{}", e.index))
+        .collect::<Vec<_>>();
+    let not_started = journal
+        .statements
+        .iter()
+        .filter(|e| e.status == RunStatus::Pending)
+        .count();
+    if !failed.is_empty() {
+        print_resume_hint(&journal.run_id);
+        return Err(anyhow!(
+            "{} of {} statement(s) did not succeed: {}",
+            failed.len(),
+            ids.len(),
+            failed.join(", ")
+        ))
+        .context(ErrorKind::QueryFailed);
+    }
+    if not_started > 0 {
+        warn!(
+            "{} statement(s) of run {} not started, run them with: athena apply --resume {}",
+            not_started, journal.run_id, journal.run_id
+        );
+    }
+
+    Ok(())
+}
+
+/// Wait until the queries finish, `on_finished` is called once for each of them
+async fn wait_for(
+    client: &Client,
+    ids: &[String],
+    poll: &PollArgs,
+    timeout: Option<Duration>,
+    mut shutdown: Shutdown,
+    mut on_finished: impl FnMut(&QueryExecution),
+) -> Result<Vec<QueryExecution>> {
+    let started_at = Instant::now();
+    let mut backoff = Backoff::new(poll);
+    let mut pending = ids.to_vec();
+    let mut finished = vec![];
+
+    loop {
+        for execution in get_executions(client, &pending).await? {
+            if is_finished(&execution) {
+                on_finished(&execution);
+                finished.push(execution);
+            }
+        }
+        pending.retain(|id| {
+            !finished
+                .iter()
+                .any(|e| e.query_execution_id() == Some(id.as_str()))
+        });
+        if pending.is_empty() {
+            break;
+        }
+
+        if let Some(timeout) = timeout.filter(|t| started_at.elapsed() >= *t) {
+            return Err(anyhow!(
+                "{} query(ies) still running after {}: {}",
+                pending.len(),
+                humantime::format_duration(timeout),
+                pending.join(", ")
+            ))
+            .context(ErrorKind::Timeout);
+        }
+
+        let delay = backoff.next_delay(None);
+        info!(
+            "Waiting for {} query(ies), sleeping {:?} ...",
+            pending.len(),
+            delay
+        );
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = shutdown.requested() => {
+                return Err(anyhow!(
+                    "stopped waiting, still running: {}",
+                    pending.join(", ")
+                ))
+                .context(ErrorKind::Interrupted);
+            }
+        }
+    }
+
+    sort_by_ids(&mut finished, ids);
+    Ok(finished)
+}
+
+pub async fn cancel(args: Cancel) -> Result<()> {
+    let (_, client) = args.common.client(None).await?;
+
+    for execution in get_executions(&client, &args.ids).await? {
+        let id = execution.query_execution_id().unwrap_or_default();
+        if is_finished(&execution) {
+            println!("{} already {}", id, execution_state(&execution).as_str());
+            continue;
+        }
+
+        stop_query(&client, id).await?;
+        println!("{} cancelled", id);
+    }
+
+    Ok(())
+}
+
+pub async fn history(args: History) -> Result<()> {
+    let (settings, client) = args.common.client(args.workgroup.clone()).await?;
+    let workgroup = settings
+        .workgroup()
+        .unwrap_or_else(|| DEFAULT_WORKGROUP.to_string());
+    let limit = usize::from(args.limit);
+
+    let mut executions = vec![];
+    let mut next_token = None;
+    for page in 1..=HISTORY_MAX_PAGES {
+        let resp = client
+            .list_query_executions()
+            .work_group(&workgroup)
+            .max_results(BATCH_SIZE as i32)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .with_context(|| format!("could not list the queries of workgroup {}", workgroup))
+            .context(ErrorKind::Aws)?;
+
+        let mut page_executions = get_executions(&client, resp.query_execution_ids()).await?;
+        sort_by_ids(&mut page_executions, resp.query_execution_ids());
+
+        // The newest first, the next pages are older
+        let all_older = page_executions.iter().all(|e| {
+            args.since
+                .is_some_and(|since| submitted_at(e) < Some(since))
+        });
+        executions.extend(page_executions.into_iter().filter(|e| {
+            args.since
+                .is_none_or(|since| submitted_at(e) >= Some(since))
+                && (args.state.is_empty()
+                    || args.state.iter().any(|s| s.matches(&execution_state(e))))
+        }));
+
+        next_token = resp.next_token().map(str::to_string);
+        if executions.len() >= limit || all_older || next_token.is_none() {
+            break;
+        }
+        if page == HISTORY_MAX_PAGES {
+            info!(
+                "Searched the last {} queries of workgroup {}",
+                page * BATCH_SIZE,
+                workgroup
+            );
+        }
+    }
+    executions.truncate(limit);
+
+    if executions.is_empty() {
+        info!("No query found in workgroup {}", workgroup);
+        return Ok(());
+    }
+
+    let rows = executions
+        .iter()
+        .map(|e| {
+            vec![
+                e.query_execution_id().unwrap_or_default().to_string(),
+                execution_state(e).as_str().to_string(),
+                submitted_at(e).map_or_else(|| "-".to_string(), format_time),
+                millis(
+                    e.statistics()
+                        .and_then(|s| s.total_execution_time_in_millis()),
+                )
+                .map_or_else(|| "-".to_string(), format_duration),
+                e.statistics()
+                    .and_then(|s| s.data_scanned_in_bytes())
+                    .map_or_else(|| "-".to_string(), |b| b.to_string()),
+                query_preview(e.query().unwrap_or_default()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = [
+        "QUERY EXECUTION ID",
+        "STATE",
+        "SUBMITTED",
+        "DURATION",
+        "SCANNED (B)",
+        "QUERY",
+    ];
+    print!("{}", format_table(&header, &rows));
+
+    Ok(())
+}
+
+/// Get the query executions with `BatchGetQueryExecution`, fails for an unknown id
+async fn get_executions(client: &Client, ids: &[String]) -> Result<Vec<QueryExecution>> {
+    let mut executions = vec![];
+
+    for chunk in ids.chunks(BATCH_SIZE) {
+        let resp = client
+            .batch_get_query_execution()
+            .set_query_execution_ids(Some(chunk.to_vec()))
+            .send()
+            .await
+            .context("could not get the query executions")
+            .context(ErrorKind::Aws)?;
+
+        if let Some(unprocessed) = resp.unprocessed_query_execution_ids().first() {
+            bail!(
+                "could not get query execution {}: {}",
+                unprocessed.query_execution_id().unwrap_or_default(),
+                unprocessed
+                    .error_message()
+                    .or(unprocessed.error_code())
+                    .unwrap_or("unknown error")
+            );
+        }
+        executions.extend(resp.query_executions().iter().cloned());
+    }
+
+    Ok(executions)
+}
+
+/// The statements of a run and their status, as a table
+fn format_run(journal: &Journal) -> String {
+    let rows = journal
+        .statements
+        .iter()
+        .map(|e| {
+            vec![
+                format!("#{}", e.index),
+                e.status.to_string(),
+                e.kind.clone(),
+                e.query_execution_id
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    format_table(&["#", "STATUS", "KIND", "QUERY EXECUTION ID"], &rows)
+}
+
+/// Sort the executions in the order of their ids
+fn sort_by_ids(executions: &mut [QueryExecution], ids: &[String]) {
+    executions.sort_by_key(|e| {
+        ids.iter()
+            .position(|id| e.query_execution_id() == Some(id.as_str()))
+    });
+}
+
+fn is_finished(execution: &QueryExecution) -> bool {
+    matches!(
+        execution_state(execution),
+        QueryExecutionState::Succeeded
+            | QueryExecutionState::Failed
+            | QueryExecutionState::Cancelled
+    )
+}
+
+/// One line with the id, the final state and the reason, such as `<id> FAILED: ...`
+fn summary(execution: &QueryExecution) -> String {
+    let mut summary = format!(
+        "{} {}",
+        execution.query_execution_id().unwrap_or_default(),
+        execution_state(execution).as_str()
+    );
+    if let Some(reason) = execution.status().and_then(|s| s.state_change_reason()) {
+        summary.push_str(&format!(": {}", reason));
+    }
+    summary
+}
+
+/// State, timings, data scanned and error of a query, on several lines
+fn describe(execution: &QueryExecution) -> String {
+    let status = execution.status();
+    let statistics = execution.statistics();
+    let error = status.and_then(|s| s.athena_error());
+    let context = execution.query_execution_context();
+
+    let fields = [
+        (
+            "State",
+            Some(execution_state(execution).as_str().to_string()),
+        ),
+        (
+            "Reason",
+            status
+                .and_then(|s| s.state_change_reason())
+                .map(str::to_string),
+        ),
+        (
+            "Statement type",
+            execution.statement_type().map(|t| t.as_str().to_string()),
+        ),
+        (
+            "Catalog",
+            context.and_then(|c| c.catalog()).map(str::to_string),
+        ),
+        (
+            "Database",
+            context.and_then(|c| c.database()).map(str::to_string),
+        ),
+        ("Workgroup", execution.work_group().map(str::to_string)),
+        (
+            "Submitted",
+            status
+                .and_then(|s| s.submission_date_time())
+                .and_then(to_chrono)
+                .map(format_time),
+        ),
+        (
+            "Completed",
+            status
+                .and_then(|s| s.completion_date_time())
+                .and_then(to_chrono)
+                .map(format_time),
+        ),
+        (
+            "Queue time",
+            millis(statistics.and_then(|s| s.query_queue_time_in_millis())).map(format_duration),
+        ),
+        (
+            "Planning time",
+            millis(statistics.and_then(|s| s.query_planning_time_in_millis())).map(format_duration),
+        ),
+        (
+            "Engine time",
+            millis(statistics.and_then(|s| s.engine_execution_time_in_millis()))
+                .map(format_duration),
+        ),
+        (
+            "Total time",
+            millis(statistics.and_then(|s| s.total_execution_time_in_millis()))
+                .map(format_duration),
+        ),
+        (
+            "Data scanned",
+            statistics
+                .and_then(|s| s.data_scanned_in_bytes())
+                .map(|b| format!("{} bytes", b)),
+        ),
+        (
+            "Output location",
+            execution
+                .result_configuration()
+                .and_then(|c| c.output_location())
+                .map(str::to_string),
+        ),
+        (
+            "Error category",
+            error
+                .and_then(|e| e.error_category())
+                .map(error_category_name),
+        ),
+        (
+            "Error type",
+            error.and_then(|e| e.error_type()).map(|t| t.to_string()),
+        ),
+        ("Retryable", error.map(|e| e.retryable().to_string())),
+        (
+            "Error message",
+            error.and_then(|e| e.error_message()).map(str::to_string),
+        ),
+    ];
+
+    let mut description = format!(
+        "Query execution id: {}\n",
+        execution.query_execution_id().unwrap_or_default()
+    );
+    for (name, value) in fields {
+        if let Some(value) = value {
+            description.push_str(&format!("  {:<17} {}\n", format!("{}:", name), value));
+        }
+    }
+    if let Some(query) = execution.query() {
+        description.push_str("  Query:\n");
+        for line in query.lines() {
+            description.push_str(&format!("    {}\n", line));
+        }
+    }
+
+    description
+}
+
+fn submitted_at(execution: &QueryExecution) -> Option<DateTime<Utc>> {
+    execution
+        .status()
+        .and_then(|s| s.submission_date_time())
+        .and_then(to_chrono)
+}
+
+fn to_chrono(time: &AwsDateTime) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(time.secs(), time.subsec_nanos())
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn millis(millis: Option<i64>) -> Option<Duration> {
+    millis
+        .and_then(|m| u64::try_from(m).ok())
+        .map(Duration::from_millis)
+}
+
+fn format_duration(duration: Duration) -> String {
+    humantime::format_duration(duration).to_string()
+}
+
+/// The query on one line, shortened
+fn query_preview(query: &str) -> String {
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    match query.char_indices().nth(QUERY_PREVIEW_LENGTH) {
+        Some((end, _)) => format!("{}...", &query[..end]),
+        None => query,
+    }
+}
+
+/// `--since`: a duration before now, or a time
+fn parse_since(input: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(duration) = humantime::parse_duration(input) {
+        let duration = chrono::Duration::from_std(duration).map_err(|e| e.to_string())?;
+        return Ok(Utc::now() - duration);
+    }
+
+    DateTime::parse_from_rfc3339(input)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| {
+            format!("invalid `{input}`, expected a duration such as `2h` or a time such as `2024-01-01T12:00:00Z`")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_athena::types::{
+        AthenaError, QueryExecutionContext, QueryExecutionStatistics, QueryExecutionStatus,
+    };
+
+    fn execution(id: &str, state: QueryExecutionState) -> QueryExecution {
+        QueryExecution::builder()
+            .query_execution_id(id)
+            .query("SELECT *\nFROM events")
+            .work_group("primary")
+            .query_execution_context(QueryExecutionContext::builder().database("db").build())
+            .status(
+                QueryExecutionStatus::builder()
+                    .state(state)
+                    .submission_date_time(AwsDateTime::from_secs(1_704_110_400))
+                    .build(),
+            )
+            .statistics(
+                QueryExecutionStatistics::builder()
+                    .total_execution_time_in_millis(1500)
+                    .data_scanned_in_bytes(2048)
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_describe() {
+        let description = describe(&execution("id-1", QueryExecutionState::Succeeded));
+        assert!(description.starts_with("Query execution id: id-1\n"));
+        assert!(description.contains("  State:            SUCCEEDED\n"));
+        assert!(description.contains("  Database:         db\n"));
+        assert!(description.contains("  Submitted:        2024-01-01T12:00:00Z\n"));
+        assert!(description.contains("  Total time:       1s 500ms\n"));
+        assert!(description.contains("  Data scanned:     2048 bytes\n"));
+        assert!(description.ends_with("  Query:\n    SELECT *\n    FROM events\n"));
+        assert!(!description.contains("Error"));
+
+        let failed = QueryExecution::builder()
+            .query_execution_id("id-2")
+            .status(
+                QueryExecutionStatus::builder()
+                    .state(QueryExecutionState::Failed)
+                    .state_change_reason("line 1:8: Column 'x' cannot be resolved")
+                    .athena_error(
+                        AthenaError::builder()
+                            .error_category(2)
+                            .error_type(1006)
+                            .retryable(false)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+        let description = describe(&failed);
+        assert!(
+            description.contains("  Reason:           line 1:8: Column 'x' cannot be resolved\n")
+        );
+        assert!(description.contains("  Error category:   USER\n"));
+        assert!(description.contains("  Error type:       1006\n"));
+        assert_eq!(
+            summary(&failed),
+            "id-2 FAILED: line 1:8: Column 'x' cannot be resolved"
+        );
+    }
+
+    #[test]
+    fn test_sort_by_ids() {
+        let mut executions = vec![
+            execution("b", QueryExecutionState::Running),
+            execution("a", QueryExecutionState::Queued),
+        ];
+        sort_by_ids(&mut executions, &["a".to_string(), "b".to_string()]);
+        assert_eq!(executions[0].query_execution_id(), Some("a"));
+        assert!(!is_finished(&executions[0]));
+        assert!(is_finished(&execution("c", QueryExecutionState::Cancelled)));
+    }
+
+    #[test]
+    fn test_query_preview() {
+        assert_eq!(query_preview("SELECT *\n  FROM t"), "SELECT * FROM t");
+        let long = format!("SELECT {} FROM t", "a, ".repeat(30));
+        let preview = query_preview(&long);
+        assert_eq!(preview.chars().count(), QUERY_PREVIEW_LENGTH + 3);
+        assert!(preview.ends_with("..."));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let since = parse_since("2h").unwrap();
+        let expected = Utc::now() - chrono::Duration::hours(2);
+        assert!((expected - since).num_seconds().abs() < 5);
+
+        assert_eq!(
+            format_time(parse_since("2024-01-01T14:00:00+02:00").unwrap()),
+            "2024-01-01T12:00:00Z"
+        );
+        assert!(parse_since("yesterday").is_err());
+    }
+}
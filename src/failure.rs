@@ -0,0 +1,226 @@
+//! Failure reports of the statements
+//!
+//! When a statement fails or is cancelled, the report tells where it comes from
+//! (statement number and source template) and why Athena stopped it: the
+//! `StateChangeReason` of the query status and its `AthenaError`.
+//!
+//! With `--error-format json`, each report is printed to stderr as a single line of JSON:
+//!
+//! ```json
+//! {"statement":3,"kind":"CREATE EXTERNAL TABLE","source":"base/table_1.sql","query_execution_id":"...","state":"FAILED","state_change_reason":"...","error_category":"USER","error_type":1301,"retryable":false,"error_message":"..."}
+//! ```
+
+use aws_sdk_athena::types::{QueryExecution, QueryExecutionState};
+use serde::Serialize;
+use std::fmt;
+
+use crate::plan::PlannedStatement;
+
+/// Output format of the failure reports
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Human readable, on several lines
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+/// Why a statement did not succeed
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FailureReport {
+    /// Position of the statement, starting from 1
+    pub statement: usize,
+    /// Kind of the statement, such as `CREATE EXTERNAL TABLE`
+    pub kind: String,
+    /// Template the statement was rendered from
+    pub source: Option<String>,
+    pub query_execution_id: Option<String>,
+    /// Final state of the query, such as `FAILED` or `CANCELLED`
+    pub state: String,
+    pub state_change_reason: Option<String>,
+    /// `SYSTEM`, `USER` or `OTHER`
+    pub error_category: Option<String>,
+    /// Athena error type, see the Athena error catalog
+    pub error_type: Option<i32>,
+    pub retryable: Option<bool>,
+    pub error_message: Option<String>,
+}
+
+impl FailureReport {
+    pub fn new(statement: &PlannedStatement, execution: &QueryExecution) -> Self {
+        let status = execution.status();
+        let error = status.and_then(|s| s.athena_error());
+
+        Self {
+            statement: statement.index,
+            kind: statement.kind.clone(),
+            source: statement.source.clone(),
+            query_execution_id: execution.query_execution_id().map(str::to_string),
+            state: status
+                .and_then(|s| s.state())
+                .map_or("UNKNOWN", QueryExecutionState::as_str)
+                .to_string(),
+            state_change_reason: status
+                .and_then(|s| s.state_change_reason())
+                .map(str::to_string),
+            error_category: error
+                .and_then(|e| e.error_category())
+                .map(error_category_name),
+            error_type: error.and_then(|e| e.error_type()),
+            retryable: error.map(|e| e.retryable()),
+            error_message: error.and_then(|e| e.error_message()).map(str::to_string),
+        }
+    }
+
+    /// One line summary, such as `statement #3 (CREATE TABLE) from base/a.sql FAILED: ...`
+    pub fn summary(&self) -> String {
+        let mut summary = format!("statement #{} ({})", self.statement, self.kind);
+        if let Some(source) = &self.source {
+            summary.push_str(&format!(" from {}", source));
+        }
+        summary.push_str(&format!(" {}", self.state));
+        if let Some(reason) = self
+            .state_change_reason
+            .as_ref()
+            .or(self.error_message.as_ref())
+        {
+            summary.push_str(&format!(": {}", reason));
+        }
+        summary
+    }
+
+    /// Print the report to stderr
+    pub fn print(&self, format: ErrorFormat) {
+        match format {
+            ErrorFormat::Human => eprint!("{}", self),
+            ErrorFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => eprintln!("{}", json),
+                Err(e) => eprintln!("could not serialize the failure report: {}", e),
+            },
+        }
+    }
+}
+
+impl fmt::Display for FailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "Statement #{} {}", self.statement, self.state)?;
+        writeln!(f, "  Kind:               {}", self.kind)?;
+        if let Some(source) = &self.source {
+            writeln!(f, "  Template:           {}", source)?;
+        }
+        if let Some(id) = &self.query_execution_id {
+            writeln!(f, "  Query execution id: {}", id)?;
+        }
+        if let Some(reason) = &self.state_change_reason {
+            writeln!(f, "  Reason:             {}", reason)?;
+        }
+        if let Some(category) = &self.error_category {
+            writeln!(f, "  Error category:     {}", category)?;
+        }
+        if let Some(error_type) = self.error_type {
+            writeln!(f, "  Error type:         {}", error_type)?;
+        }
+        if let Some(retryable) = self.retryable {
+            writeln!(f, "  Retryable:          {}", retryable)?;
+        }
+        if let Some(message) = &self.error_message {
+            writeln!(f, "  Message:            {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of an Athena error category
+pub fn error_category_name(category: i32) -> String {
+    match category {
+        1 => "SYSTEM".to_string(),
+        2 => "USER".to_string(),
+        3 => "OTHER".to_string(),
+        _ => category.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::ExecutionContext;
+    use aws_sdk_athena::types::{AthenaError, QueryExecutionStatus};
+
+    fn failed_execution() -> QueryExecution {
+        QueryExecution::builder()
+            .query_execution_id("abc-123")
+            .status(
+                QueryExecutionStatus::builder()
+                    .state(QueryExecutionState::Failed)
+                    .state_change_reason("line 1:8: Table 'db.t' does not exist")
+                    .athena_error(
+                        AthenaError::builder()
+                            .error_category(2)
+                            .error_type(1301)
+                            .retryable(false)
+                            .error_message("Table 'db.t' does not exist")
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build()
+    }
+
+    fn statement() -> PlannedStatement {
+        PlannedStatement {
+            index: 3,
+            kind: "SELECT".to_string(),
+            source: Some("base/query.sql".to_string()),
+            sql: "SELECT * FROM t".to_string(),
+            context: ExecutionContext::default(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_failure_report() {
+        let report = FailureReport::new(&statement(), &failed_execution());
+
+        assert_eq!(report.statement, 3);
+        assert_eq!(report.state, "FAILED");
+        assert_eq!(report.query_execution_id.as_deref(), Some("abc-123"));
+        assert_eq!(report.error_category.as_deref(), Some("USER"));
+        assert_eq!(report.error_type, Some(1301));
+        assert_eq!(report.retryable, Some(false));
+        assert_eq!(
+            report.summary(),
+            "statement #3 (SELECT) from base/query.sql FAILED: line 1:8: Table 'db.t' does not exist"
+        );
+
+        let human = report.to_string();
+        assert!(human.contains("Template:           base/query.sql"));
+        assert!(human.contains("Error category:     USER"));
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["source"], "base/query.sql");
+        assert_eq!(json["error_type"], 1301);
+        assert_eq!(json["retryable"], false);
+    }
+
+    #[test]
+    fn test_failure_report_without_error() {
+        let execution = QueryExecution::builder()
+            .status(
+                QueryExecutionStatus::builder()
+                    .state(QueryExecutionState::Cancelled)
+                    .build(),
+            )
+            .build();
+        let report = FailureReport::new(&statement(), &execution);
+
+        assert_eq!(report.state, "CANCELLED");
+        assert_eq!(report.error_category, None);
+        assert_eq!(report.retryable, None);
+        assert_eq!(
+            report.summary(),
+            "statement #3 (SELECT) from base/query.sql CANCELLED"
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Run journal of `apply`
+//!
+//! Each `apply` writes `.athena/runs/<run-id>.json`, updated as the statements finish:
+//! the hash and the status of every statement of the plan (`SUCCEEDED`, `FAILED`,
+//! `NOT STARTED`, ...).
+//!
+//! `athena apply --resume <run-id>` renders the plan again and runs the statements that
+//! did not succeed. The statements that succeeded must not have changed since, otherwise
+//! the run is not resumed. A resumed run has a new run id and its own journal.
+//!
+//! `athena apply --no-wait` leaves the statements it submitted `RUNNING` in the journal,
+//! `athena wait --run <journal>` waits for them and updates it, see [`crate::execution`].
+//! The journal keeps the environment, profile and region of the run, so `wait --run`
+//! polls the same account and region and updates the ledger of the same environment.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::plan::ExecutionPlan;
+use crate::state::{is_recorded, statement_hash, workgroup, LedgerEntry, Target};
+
+// Constants
+const RUNS_DIR: &str = "runs";
+const JOURNAL_VERSION: u32 = 1;
+
+/// Status of a statement in a run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RunStatus {
+    /// Not started
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    TimedOut,
+    /// Not started because a statement it depends on did not succeed
+    Skipped,
+    /// Recorded in the ledger by a previous apply, see [`crate::state`]
+    AlreadyApplied,
+}
+
+impl RunStatus {
+    /// Whether the statement does not need to run again
+    pub fn is_done(self) -> bool {
+        matches!(self, Self::Succeeded | Self::AlreadyApplied)
+    }
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Pending => "NOT STARTED",
+            Self::Running => "RUNNING",
+            Self::Succeeded => "SUCCEEDED",
+            Self::Failed => "FAILED",
+            Self::Cancelled => "CANCELLED",
+            Self::TimedOut => "TIMED OUT",
+            Self::Skipped => "SKIPPED",
+            Self::AlreadyApplied => "ALREADY APPLIED",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// A statement of the run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub index: usize,
+    /// Hash of the SQL and the target database, see [`statement_hash`]
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workgroup: Option<String>,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub status: RunStatus,
+    pub query_execution_id: Option<String>,
+    /// Recorded in the ledger once it succeeds, see [`crate::state::is_recorded`]
+    #[serde(default)]
+    pub recorded: bool,
+}
+
+impl JournalEntry {
+    /// Entry of the ledger once the statement is applied to `target`, see [`crate::state`]
+    pub fn ledger_entry(&self, target: &Target, applied_at: DateTime<Utc>) -> LedgerEntry {
+        LedgerEntry {
+            hash: self.hash.clone(),
+            catalog: self.catalog.clone(),
+            database: self.database.clone(),
+            kind: self.kind.clone(),
+            source: self.source.clone(),
+            account: target.account.clone(),
+            region: target.region.clone(),
+            workgroup: self.workgroup.clone(),
+            query_execution_id: self.query_execution_id.clone(),
+            applied_at,
+        }
+    }
+}
+
+/// The statements of a run and their status, in the order of the plan
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    pub version: u32,
+    pub run_id: String,
+    /// Run id of the journal this run resumes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resumed_from: Option<String>,
+    /// Environment, AWS profile and region the run was applied with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub statements: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Journal of a new run, every statement is pending
+    pub fn new(run_id: &str, plan: &ExecutionPlan) -> Self {
+        let now = Utc::now();
+
+        Self {
+            version: JOURNAL_VERSION,
+            run_id: run_id.to_string(),
+            resumed_from: None,
+            env: None,
+            profile: None,
+            region: None,
+            started_at: now,
+            updated_at: now,
+            statements: plan
+                .statements
+                .iter()
+                .map(|s| JournalEntry {
+                    index: s.index,
+                    hash: statement_hash(s),
+                    catalog: s.context.catalog.clone(),
+                    database: s.context.database.clone(),
+                    workgroup: Some(workgroup(s).to_string()),
+                    kind: s.kind.clone(),
+                    source: s.source.clone(),
+                    status: RunStatus::Pending,
+                    query_execution_id: None,
+                    recorded: is_recorded(s),
+                })
+                .collect(),
+        }
+    }
+
+    /// Path of the journal of a run in the `.athena` directory
+    pub fn path(state_dir: &Path, run_id: &str) -> Result<PathBuf> {
+        let valid = !run_id.is_empty()
+            && run_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            bail!("invalid run id `{}`", run_id);
+        }
+
+        Ok(state_dir.join(RUNS_DIR).join(format!("{}.json", run_id)))
+    }
+
+    /// The `.athena` directory of a journal file, `None` when it is not in a `runs` directory
+    pub fn state_dir_of(path: &Path) -> Option<PathBuf> {
+        let runs = path.parent()?;
+        if runs.file_name()? != RUNS_DIR {
+            return None;
+        }
+        runs.parent().map(Path::to_path_buf)
+    }
+
+    pub fn read(state_dir: &Path, run_id: &str) -> Result<Self> {
+        let path = Self::path(state_dir, run_id)?;
+        Self::read_file(&path)
+            .with_context(|| format!("could not read the journal of run {}", run_id))
+    }
+
+    /// Read a journal file, such as the one given to `athena wait --run`
+    pub fn read_file(path: &Path) -> Result<Self> {
+        let content =
+            fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+
+        let journal: Self = serde_json::from_slice(&content)
+            .with_context(|| format!("could not parse the journal {}", path.display()))?;
+        if journal.version != JOURNAL_VERSION {
+            bail!(
+                "unsupported journal version {}, expected {}",
+                journal.version,
+                JOURNAL_VERSION
+            );
+        }
+
+        Ok(journal)
+    }
+
+    pub fn write(&mut self, state_dir: &Path) -> Result<()> {
+        let path = Self::path(state_dir, &self.run_id)?;
+        self.write_file(&path)
+    }
+
+    pub fn write_file(&mut self, path: &Path) -> Result<()> {
+        self.updated_at = Utc::now();
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("could not create {}", dir.display()))?;
+        }
+
+        let content = serde_json::to_string_pretty(self)? + "\n";
+        fs::write(path, content)
+            .with_context(|| format!("could not write the journal {}", path.display()))
+    }
+
+    /// Set the status of a statement
+    pub fn set(&mut self, index: usize, status: RunStatus, query_execution_id: Option<String>) {
+        if let Some(entry) = self.statements.iter_mut().find(|e| e.index == index) {
+            entry.status = status;
+            entry.query_execution_id = query_execution_id.or(entry.query_execution_id.take());
+        }
+    }
+
+    pub fn status(&self, index: usize) -> Option<RunStatus> {
+        self.statements
+            .iter()
+            .find(|e| e.index == index)
+            .map(|e| e.status)
+    }
+
+    /// Continue a previous run: the statements it finished are done in this run too.
+    /// Fails when one of them changed
+    pub fn resume(&mut self, previous: &Journal) -> Result<()> {
+        let mut changed = vec![];
+
+        for done in previous.statements.iter().filter(|e| e.status.is_done()) {
+            match self.statements.iter_mut().find(|e| e.index == done.index) {
+                Some(entry) if entry.hash == done.hash => {
+                    entry.status = done.status;
+                    entry.query_execution_id = done.query_execution_id.clone();
+                }
+                Some(entry) => changed.push(format!("#{} ({})", entry.index, entry.kind)),
+                None => changed.push(format!("#{} ({}) was removed", done.index, done.kind)),
+            }
+        }
+
+        if !changed.is_empty() {
+            return Err(anyhow!(
+                "statements that already ran changed: {}",
+                changed.join(", ")
+            ))
+            .context(format!("cannot resume run {}", previous.run_id));
+        }
+
+        self.resumed_from = Some(previous.run_id.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::PlannedStatement;
+
+    fn plan(sqls: &[&str]) -> ExecutionPlan {
+        ExecutionPlan {
+            statements: sqls
+                .iter()
+                .enumerate()
+                .map(|(i, sql)| PlannedStatement {
+                    index: i + 1,
+                    kind: crate::sql::statement_kind(sql),
+                    sql: sql.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_state_dir_of() {
+        assert_eq!(
+            Journal::state_dir_of(Path::new("/project/.athena/runs/run-1.json")),
+            Some(PathBuf::from("/project/.athena"))
+        );
+        assert_eq!(Journal::state_dir_of(Path::new("/tmp/run-1.json")), None);
+    }
+
+    #[test]
+    fn test_journal_resume() {
+        let mut previous = Journal::new(
+            "run-1",
+            &plan(&["CREATE DATABASE db", "SELECT 1", "SELECT 2"]),
+        );
+        previous.set(1, RunStatus::Succeeded, Some("id-1".to_string()));
+        previous.set(2, RunStatus::Failed, Some("id-2".to_string()));
+
+        // The failed statement was fixed
+        let mut journal = Journal::new(
+            "run-2",
+            &plan(&["CREATE DATABASE db", "SELECT 10", "SELECT 2"]),
+        );
+        journal.resume(&previous).unwrap();
+        assert_eq!(journal.resumed_from.as_deref(), Some("run-1"));
+        assert_eq!(journal.status(1), Some(RunStatus::Succeeded));
+        assert_eq!(
+            journal.statements[0].query_execution_id.as_deref(),
+            Some("id-1")
+        );
+        assert_eq!(journal.status(2), Some(RunStatus::Pending));
+        assert_eq!(journal.status(3), Some(RunStatus::Pending));
+
+        // A statement that succeeded changed
+        let mut journal = Journal::new("run-3", &plan(&["CREATE DATABASE db2", "SELECT 1"]));
+        let err = journal.resume(&previous).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "cannot resume run run-1: statements that already ran changed: #1 (CREATE DATABASE)"
+        );
+    }
+
+    #[test]
+    fn test_journal_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::new("20240101T120000Z-abc123", &plan(&["SELECT 1"]));
+        journal.set(1, RunStatus::TimedOut, None);
+        journal.write(dir.path()).unwrap();
+
+        let read = Journal::read(dir.path(), "20240101T120000Z-abc123").unwrap();
+        assert_eq!(read, journal);
+        assert!(Journal::read(dir.path(), "other").is_err());
+        assert!(Journal::path(dir.path(), "../state").is_err());
+    }
+
+    #[test]
+    fn test_journal_ledger_entry() {
+        let mut plan = plan(&["CREATE TABLE t AS SELECT 1"]);
+        plan.statements[0].context.database = Some("db".to_string());
+        let mut journal = Journal::new("run-1", &plan);
+        journal.set(1, RunStatus::Succeeded, Some("id-1".to_string()));
+
+        let applied_at = Utc::now();
+        let target = Target {
+            account: Some("123456789012".to_string()),
+            region: Some("us-east-1".to_string()),
+        };
+        let entry = journal.statements[0].ledger_entry(&target, applied_at);
+        let mut ledger = crate::state::Ledger::default();
+        ledger.record(
+            &plan.statements[0],
+            &target,
+            Some("id-1".to_string()),
+            applied_at,
+        );
+        assert_eq!(ledger.statements, vec![entry]);
+    }
+}
@@ -1,8 +1,15 @@
 //! athena-rs: A CLI tool for managing AWS Athena schemas using templated SQL
 //!
-//! This application provides two main commands:
+//! This application provides the following commands:
 //! - `build`: Render SQL from template files using the Tera template engine
-//! - `apply`: Build and execute SQL statements in AWS Athena
+//! - `plan`: Save the statements to be executed to a plan file
+//! - `apply`: Build and execute SQL statements in AWS Athena, or a saved plan
+//! - `migrate`: Apply the versioned migrations of the `migrations/` directory
+//! - `rollback`: Revert the migrations newer than a version
+//! - `query`: Run one SQL statement and print its results
+//! - `shell`: Run SQL interactively
+//! - `status`, `wait`, `cancel`, `history`: Follow up on query executions
+//! - `config`: Inspect the project configuration (`athena.toml`)
 //!
 //! # Examples
 //!
@@ -15,25 +22,76 @@
 //! ```bash
 //! athena apply --output_location=s3://my-bucket/ ./templates
 //! ```
+//!
+//! The exit code tells why a command failed, see [`error`].
+//!
+//! Save a plan and apply it later:
+//! ```bash
+//! athena plan -o plan.json ./templates
+//! athena apply plan.json
+//! ```
 
 mod apply;
 mod build;
 mod cli;
+mod config;
+mod deps;
+mod directive;
+mod error;
+mod execution;
+mod failure;
+mod journal;
+mod migrate;
+mod plan;
+mod poll;
+mod query;
+mod result;
+mod retry;
+mod rollback;
+mod schedule;
+mod shell;
+mod signal;
+mod source;
+mod sql;
+mod state;
 mod tera;
 mod utils;
+mod vars;
 
 use anyhow::Result;
 use env_logger::Env;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let env = Env::new().default_filter_or("info,aws_config=error,aws_smithy_http_tower=warn");
     env_logger::init_from_env(env);
 
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(error::exit_code(&err))
+        }
+    }
+}
+
+async fn run() -> Result<()> {
     let args = cli::parse();
 
     match args.cmd {
         cli::Command::Build(args) => build::call(args).await,
+        cli::Command::Plan(args) => plan::call(args).await,
         cli::Command::Apply(args) => apply::call(args).await,
+        cli::Command::Migrate(args) => migrate::call(args).await,
+        cli::Command::Rollback(args) => rollback::call(args).await,
+        cli::Command::Query(args) => query::call(args).await,
+        cli::Command::Shell(args) => shell::call(args).await,
+        cli::Command::Status(args) => execution::status(args).await,
+        cli::Command::Wait(args) => execution::wait(args).await,
+        cli::Command::Cancel(args) => execution::cancel(args).await,
+        cli::Command::History(args) => execution::history(args).await,
+        cli::Command::Config(args) => config::call(args).await,
+        cli::Command::State(args) => state::call(args).await,
     }
 }
@@ -0,0 +1,1346 @@
+//! Versioned migrations
+//!
+//! The migrations are the templates of the `migrations/` directory of the project
+//! named `V<version>__<description>.sql`, such as `V0003__add_col.sql`. Versions are
+//! numbers, optionally dotted (`V1.2__fix.sql`), and are applied in numeric order.
+//! The other files of the directory, such as macros, are not migrations.
+//!
+//! `athena migrate up` renders the pending migrations like `athena build` and runs
+//! their statements one after the other. A migration is recorded in the history once
+//! all its statements succeeded, with the checksum of its template. The statements that
+//! succeeded are recorded in a journal, `.athena/runs/migrate-V<version>.json`
+//! (`migrate-<env>-V<version>.json` with `--env`): when one fails, the next `migrate up`
+//! resumes after them, and refuses to when one of them changed.
+//! `athena migrate status` lists the applied and pending migrations.
+//!
+//! A migration can have a `down` section, run by `athena rollback`, see [`crate::rollback`]:
+//!
+//! ```sql
+//! -- migrate:up
+//! ALTER TABLE events ADD COLUMNS (country string);
+//!
+//! -- migrate:down
+//! ALTER TABLE events REPLACE COLUMNS (id string, name string);
+//! ```
+//!
+//! The history is `.athena/migrations.json` (`migrations.<env>.json` with `--env`), or
+//! an Iceberg table with `migrations_table` and `migrations_location` in `athena.toml`,
+//! created by the first `migrate up` that applies a migration. `migrate status` and
+//! `migrate up --dry-run` read it without creating it:
+//!
+//! ```toml
+//! migrations_table = "admin.athena_migrations"
+//! migrations_location = "s3://bucket/athena/migrations/"
+//! ```
+//!
+//! A migration is applied once: editing an applied migration, or adding a migration
+//! older than the last applied one, is an error.
+
+use anyhow::{anyhow, bail, Context, Result};
+use aws_sdk_athena::{
+    types::{QueryExecution, QueryExecutionState},
+    Client,
+};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::apply::{self, QueryOptions};
+use crate::build::{self, Build};
+use crate::config::{self, AwsArgs, Settings};
+use crate::error::ErrorKind;
+use crate::failure::{ErrorFormat, FailureReport};
+use crate::journal::{Journal, RunStatus};
+use crate::plan::{ExecutionContext, ExecutionPlan, PlannedStatement};
+use crate::poll::PollArgs;
+use crate::result;
+use crate::retry::RetryArgs;
+use crate::signal::Shutdown;
+use crate::source::SourceMap;
+use crate::sql::statement_kind;
+use crate::state;
+use crate::utils::{format_table, new_run_id, pretty_print, sha256_hex};
+use crate::vars::VarArgs;
+
+// Constants
+const MIGRATIONS_DIR: &str = "migrations";
+const HISTORY_VERSION: u32 = 1;
+const SHORT_CHECKSUM_LEN: usize = 12;
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+#[allow(clippy::expect_used)]
+static MIGRATION_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: V0003__add_col.sql or V1.2__fix.sql
+    Regex::new(r"^V(\d+(?:\.\d+)*)__(\w+)\.sql$").expect("invalid regex pattern")
+});
+
+#[allow(clippy::expect_used)]
+static SECTION_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: -- migrate:up or -- migrate:down
+    Regex::new(r"(?im)^[ \t]*--[ \t]*migrate:[ \t]*(up|down)[ \t]*$")
+        .expect("invalid regex pattern")
+});
+
+#[allow(clippy::expect_used)]
+static TABLE_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: table or database.table
+    Regex::new(r"^\w+(?:\.\w+)?$").expect("invalid regex pattern")
+});
+
+/// Version of a migration, compared number by number: `1.10` comes after `1.9`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(Vec<u64>);
+
+impl FromStr for Version {
+    type Err = anyhow::Error;
+
+    /// Parse `3`, `0003`, `V3` or `1.2`
+    fn from_str(s: &str) -> Result<Self> {
+        s.trim_start_matches('V')
+            .split('.')
+            .map(|n| n.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+            .map_err(|_| anyhow!("invalid migration version `{}`", s))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let numbers = self.0.iter().map(u64::to_string).collect::<Vec<_>>();
+        write!(f, "{}", numbers.join("."))
+    }
+}
+
+/// A migration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    pub version: Version,
+    /// Description from the file name, `add_col` is `add col`
+    pub description: String,
+    pub path: PathBuf,
+    /// SHA-256 of the template
+    pub checksum: String,
+}
+
+impl Migration {
+    /// Read a migration file, `None` if the file name is not the one of a migration
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return Ok(None);
+        };
+
+        let Some(captures) = MIGRATION_PATTERN.captures(name) else {
+            // V<number> is a migration with a typo in its name
+            let looks_versioned = name.starts_with('V')
+                && name[1..].starts_with(|c: char| c.is_ascii_digit())
+                && name.ends_with(".sql");
+            if looks_versioned {
+                bail!(
+                    "invalid migration file name {}, expected V<version>__<description>.sql",
+                    path.display()
+                );
+            }
+            return Ok(None);
+        };
+
+        let content = fs::read(path)
+            .with_context(|| format!("could not read migration {}", path.display()))?;
+
+        Ok(Some(Self {
+            version: captures[1].parse()?,
+            description: captures[2].replace('_', " "),
+            path: path.to_path_buf(),
+            checksum: sha256_hex(&content),
+        }))
+    }
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V{} ({})", self.version, self.description)
+    }
+}
+
+/// The migrations of a directory, sorted by version
+pub fn discover(dir: &Path) -> Result<Vec<Migration>> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("could not read the migrations directory {}", dir.display()))?;
+
+    let mut migrations = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            migrations.extend(Migration::read(&path)?);
+        }
+    }
+    migrations.sort_by(|a, b| a.version.cmp(&b.version));
+
+    if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        bail!(
+            "duplicate migration version {}: {} and {}",
+            pair[0].version,
+            pair[0].path.display(),
+            pair[1].path.display()
+        );
+    }
+
+    Ok(migrations)
+}
+
+/// A migration recorded in the history
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AppliedMigration {
+    pub version: String,
+    pub description: String,
+    pub checksum: String,
+    pub applied_at: DateTime<Utc>,
+    /// Run id of the `migrate up` that applied it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+}
+
+/// The local history file
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct HistoryFile {
+    version: u32,
+    migrations: Vec<AppliedMigration>,
+}
+
+/// Status of a migration, see [`reconcile`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStatus {
+    Applied,
+    Pending,
+    /// Applied, then its file was edited
+    Changed,
+    /// Applied, its file was deleted
+    Missing,
+    /// Not applied and older than the last applied migration
+    OutOfOrder,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Applied => "APPLIED",
+            Self::Pending => "PENDING",
+            Self::Changed => "CHANGED",
+            Self::Missing => "MISSING",
+            Self::OutOfOrder => "OUT OF ORDER",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// A migration file, its history record, or both
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationState {
+    pub version: Version,
+    pub status: MigrationStatus,
+    pub migration: Option<Migration>,
+    pub applied: Option<AppliedMigration>,
+}
+
+impl MigrationState {
+    pub fn description(&self) -> &str {
+        match (&self.migration, &self.applied) {
+            (Some(m), _) => &m.description,
+            (None, Some(a)) => &a.description,
+            (None, None) => "",
+        }
+    }
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V{} ({})", self.version, self.description())
+    }
+}
+
+/// Match the migration files with the history, sorted by version
+pub fn reconcile(
+    migrations: &[Migration],
+    history: &[AppliedMigration],
+) -> Result<Vec<MigrationState>> {
+    let history = history
+        .iter()
+        .map(|a| Ok((a.version.parse::<Version>()?, a)))
+        .collect::<Result<Vec<_>>>()?;
+    let latest = history.iter().map(|(v, _)| v).max();
+
+    let mut states = migrations
+        .iter()
+        .map(|m| {
+            let applied = history.iter().find(|(v, _)| *v == m.version).map(|h| h.1);
+            let status = match applied {
+                Some(a) if a.checksum == m.checksum => MigrationStatus::Applied,
+                Some(_) => MigrationStatus::Changed,
+                None if latest.is_some_and(|latest| m.version < *latest) => {
+                    MigrationStatus::OutOfOrder
+                }
+                None => MigrationStatus::Pending,
+            };
+
+            MigrationState {
+                version: m.version.clone(),
+                status,
+                migration: Some(m.clone()),
+                applied: applied.cloned(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    for (version, applied) in &history {
+        if !migrations.iter().any(|m| m.version == *version) {
+            states.push(MigrationState {
+                version: version.clone(),
+                status: MigrationStatus::Missing,
+                migration: None,
+                applied: Some((*applied).clone()),
+            });
+        }
+    }
+    states.sort_by(|a, b| a.version.cmp(&b.version));
+
+    Ok(states)
+}
+
+/// Fail when a migration was edited after it was applied, or is older than the last
+/// applied one
+pub fn validate(states: &[MigrationState]) -> Result<()> {
+    let invalid = |status: MigrationStatus| {
+        states
+            .iter()
+            .filter(|s| s.status == status)
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let changed = invalid(MigrationStatus::Changed);
+    if !changed.is_empty() {
+        bail!(
+            "{} applied migration(s) changed since: {}, add a new migration instead",
+            changed.len(),
+            changed.join(", ")
+        );
+    }
+
+    let out_of_order = invalid(MigrationStatus::OutOfOrder);
+    if !out_of_order.is_empty() {
+        bail!(
+            "{} pending migration(s) older than the last applied one: {}, give them a newer version",
+            out_of_order.len(),
+            out_of_order.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Submits the statements of the migrations and of the history table
+#[derive(Debug, Clone)]
+pub struct Runner {
+    client: Client,
+    options: QueryOptions,
+    shutdown: Shutdown,
+}
+
+impl Runner {
+    pub async fn new(settings: &Settings, options: QueryOptions, shutdown: Shutdown) -> Self {
+        let shared_config = config::load_aws_config(settings).await;
+
+        Self {
+            client: Client::new(&shared_config),
+            options,
+            shutdown,
+        }
+    }
+
+    /// Run a statement, fails unless it succeeds
+    pub async fn run(&self, statement: &PlannedStatement, run_id: &str) -> Result<QueryExecution> {
+        let execution = apply::submit_and_wait(
+            self.client.clone(),
+            statement,
+            &self.options,
+            run_id,
+            self.shutdown.clone(),
+        )
+        .await?;
+
+        let state = execution.status().and_then(|s| s.state());
+        if state == Some(&QueryExecutionState::Succeeded) {
+            return Ok(execution);
+        }
+        if self.shutdown.is_requested() {
+            return Err(anyhow!("interrupted")).context(ErrorKind::Interrupted);
+        }
+
+        let report = FailureReport::new(statement, &execution);
+        report.print(ErrorFormat::Human);
+        Err(anyhow!(report.summary())).context(ErrorKind::QueryFailed)
+    }
+}
+
+/// Where the applied migrations are recorded
+#[derive(Debug, Clone)]
+pub enum History {
+    /// Local file in the `.athena` directory
+    File(PathBuf),
+    /// Iceberg table
+    Table {
+        name: String,
+        location: String,
+        context: ExecutionContext,
+        runner: Box<Runner>,
+    },
+}
+
+impl History {
+    /// The history of the settings, see the module documentation
+    pub async fn open(
+        settings: &Settings,
+        context: Option<&Path>,
+        options: &QueryOptions,
+        shutdown: &Shutdown,
+    ) -> Result<Self> {
+        let Some(table) = &settings.migrations_table else {
+            let filename = match &settings.env {
+                Some(env) => format!("migrations.{}.json", env),
+                None => "migrations.json".to_string(),
+            };
+            return Ok(Self::File(
+                state::state_dir(settings, context)?.join(filename),
+            ));
+        };
+
+        if !TABLE_PATTERN.is_match(&table.value) {
+            bail!(
+                "invalid migrations_table `{}`, expected `database.table`",
+                table.value
+            );
+        }
+        let location = settings.migrations_location.as_ref().ok_or_else(|| {
+            anyhow!("migrations_table requires migrations_location, the S3 location of the table")
+        })?;
+
+        Ok(Self::Table {
+            name: table.value.clone(),
+            location: location.value.clone(),
+            context: ExecutionContext::new(settings),
+            runner: Box::new(Runner::new(settings, options.clone(), shutdown.clone()).await),
+        })
+    }
+
+    pub fn runner(&self) -> Option<&Runner> {
+        match self {
+            Self::File(_) => None,
+            Self::Table { runner, .. } => Some(runner),
+        }
+    }
+
+    /// The applied migrations, an empty history when there is none yet
+    pub async fn load(&self) -> Result<Vec<AppliedMigration>> {
+        match self {
+            Self::File(path) => {
+                if !path.exists() {
+                    return Ok(vec![]);
+                }
+
+                let content = fs::read(path)
+                    .with_context(|| format!("could not read the history {}", path.display()))?;
+                let file: HistoryFile = serde_json::from_slice(&content)
+                    .with_context(|| format!("could not parse the history {}", path.display()))?;
+                if file.version != HISTORY_VERSION {
+                    bail!(
+                        "unsupported history version {}, expected {}",
+                        file.version,
+                        HISTORY_VERSION
+                    );
+                }
+                Ok(file.migrations)
+            }
+            Self::Table {
+                name,
+                context,
+                runner,
+                ..
+            } => {
+                // Not created yet, a read-only command must not create it
+                let exists = exists_query(name);
+                let tables = select(runner, context, exists)
+                    .await
+                    .with_context(|| format!("could not find the history table {}", name))?;
+                if tables.is_empty() {
+                    return Ok(vec![]);
+                }
+
+                let history = format!(
+                    "SELECT version, description, checksum, CAST(applied_at AS varchar) AS applied_at, run_id FROM {}",
+                    name
+                );
+                select(runner, context, history)
+                    .await
+                    .and_then(|rows| rows.iter().map(|row| parse_history_row(row)).collect())
+                    .with_context(|| format!("could not read the history table {}", name))
+            }
+        }
+    }
+
+    /// Create the history table if it does not exist yet, before the first migration
+    pub async fn create(&self) -> Result<()> {
+        let Self::Table {
+            name,
+            location,
+            context,
+            runner,
+        } = self
+        else {
+            // The file is written with the first migration
+            return Ok(());
+        };
+
+        let create = format!(
+            "CREATE TABLE IF NOT EXISTS {} (version string, description string, checksum string, applied_at timestamp, run_id string) LOCATION '{}' TBLPROPERTIES ('table_type' = 'ICEBERG')",
+            name,
+            escape(location)
+        );
+        runner
+            .run(&history_statement(create, context), &new_run_id())
+            .await
+            .with_context(|| format!("could not create the history table {}", name))?;
+        Ok(())
+    }
+
+    /// Record an applied migration
+    pub async fn record(&self, applied: &AppliedMigration) -> Result<()> {
+        match self {
+            Self::File(path) => {
+                let mut migrations = self.load().await?;
+                migrations.push(applied.clone());
+                write_history_file(path, migrations)
+            }
+            Self::Table {
+                name,
+                context,
+                runner,
+                ..
+            } => {
+                let insert = format!(
+                    "INSERT INTO {} VALUES ('{}', '{}', '{}', TIMESTAMP '{}', '{}')",
+                    name,
+                    escape(&applied.version),
+                    escape(&applied.description),
+                    escape(&applied.checksum),
+                    applied.applied_at.format("%Y-%m-%d %H:%M:%S%.6f"),
+                    escape(applied.run_id.as_deref().unwrap_or_default())
+                );
+                runner
+                    .run(&history_statement(insert, context), &new_run_id())
+                    .await
+                    .with_context(|| {
+                        format!("could not record V{} in {}", applied.version, name)
+                    })?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove a migration that was rolled back
+    pub async fn remove(&self, applied: &AppliedMigration) -> Result<()> {
+        match self {
+            Self::File(path) => {
+                let mut migrations = self.load().await?;
+                migrations.retain(|a| a.version != applied.version);
+                write_history_file(path, migrations)
+            }
+            Self::Table {
+                name,
+                context,
+                runner,
+                ..
+            } => {
+                let delete = format!(
+                    "DELETE FROM {} WHERE version = '{}'",
+                    name,
+                    escape(&applied.version)
+                );
+                runner
+                    .run(&history_statement(delete, context), &new_run_id())
+                    .await
+                    .with_context(|| {
+                        format!("could not remove V{} from {}", applied.version, name)
+                    })?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_history_file(path: &Path, migrations: Vec<AppliedMigration>) -> Result<()> {
+    let file = HistoryFile {
+        version: HISTORY_VERSION,
+        migrations,
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("could not create {}", dir.display()))?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&file)? + "\n")
+        .with_context(|| format!("could not write the history {}", path.display()))
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Table { name, .. } => write!(f, "table {}", name),
+        }
+    }
+}
+
+/// Query listing the history table if it exists, in the default database when unqualified
+fn exists_query(name: &str) -> String {
+    let (database, table) = name.split_once('.').unwrap_or(("default", name));
+    format!(
+        "SELECT table_name FROM information_schema.tables WHERE table_schema = '{}' AND table_name = '{}'",
+        escape(&database.to_lowercase()),
+        escape(&table.to_lowercase())
+    )
+}
+
+/// Rows of a query on the history table, `NULL` as an empty string
+async fn select(
+    runner: &Runner,
+    context: &ExecutionContext,
+    sql: String,
+) -> Result<Vec<Vec<String>>> {
+    let execution = runner
+        .run(&history_statement(sql, context), &new_run_id())
+        .await?;
+    let id = execution
+        .query_execution_id()
+        .ok_or_else(|| anyhow!("query execution id not found"))?;
+
+    Ok(result::fetch(&runner.client, id, None)
+        .await?
+        .rows
+        .into_iter()
+        .map(|row| row.into_iter().map(Option::unwrap_or_default).collect())
+        .collect())
+}
+
+fn history_statement(sql: String, context: &ExecutionContext) -> PlannedStatement {
+    PlannedStatement {
+        index: 1,
+        kind: statement_kind(&sql),
+        sql,
+        context: context.clone(),
+        ..Default::default()
+    }
+}
+
+/// Escape a SQL string literal
+fn escape(value: &str) -> String {
+    value.replace('\'', "''")
+}
+
+/// A row of the history table: version, description, checksum, applied at and run id
+fn parse_history_row(row: &[String]) -> Result<AppliedMigration> {
+    let [version, description, checksum, applied_at, run_id] = row else {
+        bail!("expected 5 columns, got {}", row.len());
+    };
+
+    let applied_at = NaiveDateTime::parse_from_str(applied_at, TIMESTAMP_FORMAT)
+        .with_context(|| format!("invalid applied_at `{}`", applied_at))?
+        .and_utc();
+
+    Ok(AppliedMigration {
+        version: version.clone(),
+        description: description.clone(),
+        checksum: checksum.clone(),
+        applied_at,
+        run_id: Some(run_id.clone()).filter(|r| !r.is_empty()),
+    })
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Migrate {
+    /// Apply the pending migrations in order
+    Up(Up),
+    /// List the applied and pending migrations
+    Status(Status),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct MigrateArgs {
+    /// Directory of the migrations, `migrations` next to athena.toml by default
+    #[arg(long)]
+    pub dir: Option<PathBuf>,
+
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`.
+    /// Each environment has its own history
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    /// AWS Profile
+    #[arg(long, short)]
+    pub profile: Option<String>,
+
+    /// AWS Region
+    #[arg(long, short)]
+    pub region: Option<String>,
+
+    /// AWS Athena Workgroup
+    #[arg(long, short)]
+    pub workgroup: Option<String>,
+
+    /// AWS Athena output location, such as `s3://path/to/query/bucket/`
+    #[arg(long, short)]
+    pub output_location: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Up {
+    #[command(flatten)]
+    pub common: MigrateArgs,
+
+    /// Apply the pending migrations up to this version, included
+    #[arg(long, value_name = "VERSION")]
+    pub to: Option<Version>,
+
+    /// Render the pending migrations and print their execution plan, nothing is applied
+    #[arg(long, short)]
+    pub dry_run: bool,
+
+    /// No pretty print for SQL
+    #[arg(long)]
+    pub no_pretty: bool,
+
+    /// Stop a statement that runs longer than this duration, such as `30s` or `10m`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    #[command(flatten)]
+    pub poll: PollArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Status {
+    #[command(flatten)]
+    pub common: MigrateArgs,
+}
+
+pub async fn call(args: Migrate) -> Result<()> {
+    match args {
+        Migrate::Up(args) => up(&args).await,
+        Migrate::Status(args) => status(&args).await,
+    }
+}
+
+/// The settings and the migration directory
+pub fn resolve(args: &MigrateArgs) -> Result<(Settings, PathBuf)> {
+    let aws_args = AwsArgs {
+        profile: args.profile.clone(),
+        region: args.region.clone(),
+        workgroup: args.workgroup.clone(),
+        output_location: args.output_location.clone(),
+    };
+    let settings = config::resolve(args.context.as_deref(), args.env.as_deref(), &aws_args)?;
+
+    let dir = match &args.dir {
+        Some(dir) => dir.clone(),
+        None => state::project_dir(&settings, args.context.as_deref())?.join(MIGRATIONS_DIR),
+    };
+
+    Ok((settings, dir))
+}
+
+async fn up(args: &Up) -> Result<()> {
+    let (settings, dir) = resolve(&args.common)?;
+    let context = args.common.context.as_deref();
+    let migrations = discover(&dir)?;
+
+    let options = QueryOptions {
+        no_pretty: args.no_pretty,
+        quiet: false,
+        poll: args.poll.clone(),
+        retry: args.retry.clone(),
+    };
+    let shutdown = Shutdown::listen();
+    let history = History::open(&settings, context, &options, &shutdown).await?;
+
+    let states = reconcile(&migrations, &history.load().await?)?;
+    validate(&states)?;
+    for missing in states
+        .iter()
+        .filter(|s| s.status == MigrationStatus::Missing)
+    {
+        warn!("{} is applied but its file is missing", missing);
+    }
+
+    let pending = states
+        .iter()
+        .filter(|s| s.status == MigrationStatus::Pending)
+        .filter(|s| args.to.as_ref().is_none_or(|to| s.version <= *to))
+        .filter_map(|s| s.migration.as_ref())
+        .collect::<Vec<_>>();
+    let current = states
+        .iter()
+        .filter(|s| s.applied.is_some())
+        .map(|s| s.version.to_string())
+        .next_back()
+        .unwrap_or_else(|| "(none)".to_string());
+
+    if pending.is_empty() {
+        info!("Nothing to migrate, the current version is {}", current);
+        return Ok(());
+    }
+    info!(
+        "{} pending migration(s), the current version is {}",
+        pending.len(),
+        current
+    );
+
+    let runner = match (args.dry_run, history.runner()) {
+        (true, _) => None,
+        (false, Some(runner)) => Some(runner.clone()),
+        (false, None) => Some(Runner::new(&settings, options.clone(), shutdown.clone()).await),
+    };
+    if runner.is_some() {
+        history.create().await?;
+    }
+    let state_dir = state::state_dir(&settings, context)?;
+    let run_id = new_run_id();
+
+    for migration in pending {
+        let up = render(migration, &args.common, &args.vars, &settings)?
+            .up
+            .with_default_timeout(args.timeout);
+
+        let Some(runner) = &runner else {
+            println!("\n-- {}", migration);
+            up.print(args.no_pretty);
+            continue;
+        };
+
+        let plan = up.plan;
+        info!(
+            "Applying {}: {} statement(s)",
+            migration,
+            plan.statements.len()
+        );
+        let mut progress = Progress::open(&state_dir, settings.env.as_deref(), migration, &plan)?;
+        for statement in &plan.statements {
+            if progress.is_done(statement.index) {
+                info!(
+                    "Statement #{} ({}) succeeded in a previous run, skipped",
+                    statement.index, statement.kind
+                );
+                continue;
+            }
+
+            // Unique per migration, the same statement may be in several migrations
+            let token_id = format!("{}-V{}", run_id, migration.version);
+            let execution = runner.run(statement, &token_id).await;
+            let (status, id) = match &execution {
+                Ok(execution) => (
+                    RunStatus::Succeeded,
+                    execution.query_execution_id().map(str::to_string),
+                ),
+                Err(_) => (RunStatus::Failed, None),
+            };
+            progress.set(statement.index, status, id)?;
+            execution.with_context(|| {
+                format!(
+                    "{} failed at statement #{} ({}), it is not recorded as applied: \
+                     the next `migrate up` resumes after the statements that succeeded",
+                    migration, statement.index, statement.kind
+                )
+            })?;
+        }
+
+        history
+            .record(&AppliedMigration {
+                version: migration.version.to_string(),
+                description: migration.description.clone(),
+                checksum: migration.checksum.clone(),
+                applied_at: Utc::now(),
+                run_id: Some(run_id.clone()),
+            })
+            .await?;
+        progress.remove();
+        info!("Applied {}", migration);
+    }
+
+    Ok(())
+}
+
+/// Statements of a migration that succeeded, in a journal of the `.athena` directory, so
+/// a migration that failed partway resumes after them, see [`crate::journal`]
+struct Progress {
+    path: PathBuf,
+    journal: Journal,
+}
+
+impl Progress {
+    /// The progress of a previous `migrate up`, if any. Fails when a statement that
+    /// succeeded changed since
+    fn open(
+        state_dir: &Path,
+        env: Option<&str>,
+        migration: &Migration,
+        plan: &ExecutionPlan,
+    ) -> Result<Self> {
+        let version = migration.version.to_string().replace('.', "_");
+        let run_id = match env {
+            Some(env) => format!("migrate-{}-V{}", env, version),
+            None => format!("migrate-V{}", version),
+        };
+        let path = Journal::path(state_dir, &run_id)?;
+
+        let mut journal = Journal::new(&run_id, plan);
+        if path.exists() {
+            journal
+                .resume(&Journal::read_file(&path)?)
+                .with_context(|| {
+                    format!(
+                        "{} failed partway and changed since: revert the statements that \
+                         succeeded, then remove {}",
+                        migration,
+                        path.display()
+                    )
+                })?;
+        }
+
+        Ok(Self { path, journal })
+    }
+
+    fn is_done(&self, index: usize) -> bool {
+        self.journal.status(index).is_some_and(RunStatus::is_done)
+    }
+
+    fn set(&mut self, index: usize, status: RunStatus, id: Option<String>) -> Result<()> {
+        self.journal.set(index, status, id);
+        self.journal.write_file(&self.path)
+    }
+
+    /// The migration is recorded in the history, its progress is not needed anymore
+    fn remove(&self) {
+        if !self.path.exists() {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("could not remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// A section of a rendered migration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    pub sql: String,
+    pub plan: ExecutionPlan,
+}
+
+impl Section {
+    fn new(sql: &str, sources: &SourceMap, settings: &Settings) -> Result<Self> {
+        Ok(Self {
+            sql: sql.trim().to_string(),
+            plan: ExecutionPlan::in_order(sql, sources, settings)?,
+        })
+    }
+
+    pub fn with_default_timeout(self, timeout: Option<std::time::Duration>) -> Self {
+        Self {
+            plan: self.plan.with_default_timeout(timeout),
+            ..self
+        }
+    }
+
+    /// Print the SQL and the execution plan
+    pub fn print(&self, no_pretty: bool) {
+        if no_pretty {
+            println!("{}", self.sql);
+        } else {
+            pretty_print(self.sql.as_bytes());
+        }
+        println!();
+        print!("{}", self.plan);
+    }
+}
+
+/// A rendered migration, split into its `up` and `down` sections
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rendered {
+    pub up: Section,
+    /// `None` without a `-- migrate:down` section
+    pub down: Option<Section>,
+}
+
+/// Render a migration and split it into sections and statements
+pub fn render(
+    migration: &Migration,
+    common: &MigrateArgs,
+    vars: &VarArgs,
+    settings: &Settings,
+) -> Result<Rendered> {
+    let build_args = Build {
+        file: Some(migration.path.clone()),
+        out: None,
+        context: Some(state::project_dir(settings, common.context.as_deref())?),
+        no_pretty: None,
+        env: common.env.clone(),
+        vars: vars.clone(),
+    };
+
+    build::render_with_sources(&build_args, settings)
+        .and_then(|(sql, sources)| split_sections(&sql, sources, settings))
+        .with_context(|| format!("could not render {}", migration))
+        .context(ErrorKind::Render)
+}
+
+/// Split the SQL at the `-- migrate:up` and `-- migrate:down` lines.
+/// The SQL before `-- migrate:down` is the `up` section
+fn split_sections(sql: &str, sources: SourceMap, settings: &Settings) -> Result<Rendered> {
+    let mut up = None;
+    let mut down = None;
+
+    for captures in SECTION_PATTERN.captures_iter(sql) {
+        let (Some(line), Some(name)) = (captures.get(0), captures.get(1)) else {
+            continue;
+        };
+        let section = match name.as_str().to_lowercase().as_str() {
+            "up" => &mut up,
+            _ => &mut down,
+        };
+        if section.is_some() {
+            bail!("more than one `-- migrate:{}` line", name.as_str());
+        }
+        *section = Some(line);
+    }
+
+    match (up, down) {
+        (Some(up), Some(down)) if up.start() > down.start() => {
+            bail!("`-- migrate:down` must come after `-- migrate:up`")
+        }
+        (_, Some(down)) => Ok(Rendered {
+            up: Section::new(&sql[..down.start()], &sources, settings)?,
+            down: Some(Section::new(
+                &sql[down.end()..],
+                &sources.trim_start(down.end()),
+                settings,
+            )?),
+        }),
+        (_, None) => Ok(Rendered {
+            up: Section::new(sql, &sources, settings)?,
+            down: None,
+        }),
+    }
+}
+
+async fn status(args: &Status) -> Result<()> {
+    let (settings, dir) = resolve(&args.common)?;
+    let migrations = discover(&dir)?;
+
+    let options = QueryOptions::default();
+    let shutdown = Shutdown::listen();
+    let history = History::open(
+        &settings,
+        args.common.context.as_deref(),
+        &options,
+        &shutdown,
+    )
+    .await?;
+    let states = reconcile(&migrations, &history.load().await?)?;
+
+    let count = |status: MigrationStatus| states.iter().filter(|s| s.status == status).count();
+    println!(
+        "History: {}, {} applied, {} pending",
+        history,
+        count(MigrationStatus::Applied),
+        count(MigrationStatus::Pending)
+    );
+    println!();
+
+    let rows = states
+        .iter()
+        .map(|s| {
+            let checksum = s
+                .migration
+                .as_ref()
+                .map(|m| &m.checksum)
+                .or(s.applied.as_ref().map(|a| &a.checksum));
+            vec![
+                s.version.to_string(),
+                s.description().to_string(),
+                s.status.to_string(),
+                s.applied
+                    .as_ref()
+                    .map(|a| a.applied_at.to_rfc3339_opts(SecondsFormat::Secs, true))
+                    .unwrap_or_else(|| "-".to_string()),
+                checksum
+                    .map(|c| c.chars().take(SHORT_CHECKSUM_LEN).collect())
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect::<Vec<_>>();
+    print!(
+        "{}",
+        format_table(
+            &["VERSION", "DESCRIPTION", "STATUS", "APPLIED AT", "CHECKSUM"],
+            &rows
+        )
+    );
+
+    validate(&states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migration(version: &str, checksum: &str) -> Migration {
+        Migration {
+            version: version.parse().unwrap(),
+            description: format!("migration {}", version),
+            path: PathBuf::from(format!("V{}__migration.sql", version)),
+            checksum: checksum.to_string(),
+        }
+    }
+
+    fn applied(version: &str, checksum: &str) -> AppliedMigration {
+        AppliedMigration {
+            version: version.to_string(),
+            description: format!("migration {}", version),
+            checksum: checksum.to_string(),
+            applied_at: Utc::now(),
+            run_id: None,
+        }
+    }
+
+    #[test]
+    fn test_version() {
+        let v = |s: &str| s.parse::<Version>().unwrap();
+
+        assert_eq!(v("0003"), v("3"));
+        assert_eq!(v("V3").to_string(), "3");
+        assert!(v("2") < v("10"));
+        assert!(v("1.9") < v("1.10"));
+        assert!(v("1") < v("1.1"));
+        assert!("1.a".parse::<Version>().is_err());
+        assert!("".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("V0010__add_col.sql"), "ALTER TABLE t").unwrap();
+        fs::write(dir.path().join("V2__init.sql"), "CREATE DATABASE db").unwrap();
+        fs::write(dir.path().join("_macros.sql"), "").unwrap();
+
+        let migrations = discover(dir.path()).unwrap();
+        assert_eq!(migrations.len(), 2);
+        assert_eq!(migrations[0].to_string(), "V2 (init)");
+        assert_eq!(migrations[1].to_string(), "V10 (add col)");
+        assert_eq!(migrations[1].checksum, sha256_hex(b"ALTER TABLE t"));
+
+        // Same version
+        fs::write(dir.path().join("V02__other.sql"), "").unwrap();
+        let err = discover(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("duplicate migration version 2"));
+        fs::remove_file(dir.path().join("V02__other.sql")).unwrap();
+
+        // Typo in the name
+        fs::write(dir.path().join("V3_fix.sql"), "").unwrap();
+        let err = discover(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("invalid migration file name"));
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let migrations = [
+            migration("1", "a"),
+            migration("2", "b"),
+            migration("3", "c"),
+        ];
+
+        let states = reconcile(&migrations, &[applied("1", "a")]).unwrap();
+        let statuses = states.iter().map(|s| s.status).collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                MigrationStatus::Applied,
+                MigrationStatus::Pending,
+                MigrationStatus::Pending
+            ]
+        );
+        assert!(validate(&states).is_ok());
+
+        // V1 edited, V2 added after V3 was applied, V4 deleted
+        let history = [applied("1", "x"), applied("3", "c"), applied("4", "d")];
+        let states = reconcile(&migrations, &history).unwrap();
+        let statuses = states.iter().map(|s| s.status).collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                MigrationStatus::Changed,
+                MigrationStatus::OutOfOrder,
+                MigrationStatus::Applied,
+                MigrationStatus::Missing
+            ]
+        );
+        let err = validate(&states).unwrap_err();
+        assert!(err.to_string().contains("changed since: V1 (migration 1)"));
+
+        let err = validate(&states[1..]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("older than the last applied one: V2"));
+    }
+
+    #[test]
+    fn test_split_sections() {
+        let settings = Settings::default();
+        let sql = "-- migrate:up\nCREATE DATABASE db;\nCREATE TABLE db.t (a int);\n\n-- migrate:down\nDROP TABLE db.t;\n";
+        let rendered = split_sections(sql, SourceMap::default(), &settings).unwrap();
+        assert_eq!(rendered.up.plan.statements.len(), 2);
+        let down = rendered.down.unwrap();
+        assert_eq!(down.sql, "DROP TABLE db.t;");
+        assert_eq!(down.plan.statements[0].kind, "DROP TABLE");
+
+        // No markers, everything is up
+        let rendered =
+            split_sections("CREATE DATABASE db", SourceMap::default(), &settings).unwrap();
+        assert_eq!(rendered.up.plan.statements.len(), 1);
+        assert!(rendered.down.is_none());
+
+        // An empty down section has nothing to revert
+        let rendered = split_sections(
+            "SELECT 1;\n-- migrate:down\n",
+            SourceMap::default(),
+            &settings,
+        )
+        .unwrap();
+        assert!(rendered.down.unwrap().plan.statements.is_empty());
+
+        // The statements of a migration keep their order, even before what they use
+        let sql = "CREATE VIEW v AS SELECT * FROM t;\nCREATE TABLE t (a int);";
+        let rendered = split_sections(sql, SourceMap::default(), &settings).unwrap();
+        let statements = rendered.up.plan.statements;
+        assert_eq!(
+            statements.iter().map(|s| s.index).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(statements.iter().all(|s| s.depends_on.is_empty()));
+
+        let sql = "-- migrate:down\nDROP TABLE t;\n-- migrate:up\nCREATE TABLE t (a int);";
+        assert!(split_sections(sql, SourceMap::default(), &settings).is_err());
+        let sql = "-- migrate:down\n-- migrate:down\n";
+        assert!(split_sections(sql, SourceMap::default(), &settings).is_err());
+    }
+
+    #[test]
+    fn test_exists_query() {
+        assert_eq!(
+            exists_query("Admin.Athena_Migrations"),
+            "SELECT table_name FROM information_schema.tables WHERE table_schema = 'admin' AND table_name = 'athena_migrations'"
+        );
+        assert!(exists_query("migrations").contains("table_schema = 'default'"));
+    }
+
+    #[test]
+    fn test_parse_history_row() {
+        let row =
+            ["3", "add col", "abc", "2024-01-01 12:00:00.000000", "run-1"].map(str::to_string);
+        let applied = parse_history_row(&row).unwrap();
+        assert_eq!(applied.version, "3");
+        assert_eq!(applied.applied_at.to_rfc3339(), "2024-01-01T12:00:00+00:00");
+        assert_eq!(applied.run_id.as_deref(), Some("run-1"));
+
+        assert!(parse_history_row(&row[..4]).is_err());
+        assert_eq!(escape("it's"), "it''s");
+    }
+
+    #[test]
+    fn test_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let migration = migration("1.2", "c1");
+        let plan = |sqls: &[&str]| ExecutionPlan {
+            statements: sqls
+                .iter()
+                .enumerate()
+                .map(|(i, sql)| PlannedStatement {
+                    index: i + 1,
+                    kind: crate::sql::statement_kind(sql),
+                    sql: sql.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+
+        let mut progress = Progress::open(
+            dir.path(),
+            None,
+            &migration,
+            &plan(&["CREATE DATABASE db", "SELECT 1"]),
+        )
+        .unwrap();
+        assert!(progress.path.ends_with("runs/migrate-V1_2.json"));
+        progress
+            .set(1, RunStatus::Succeeded, Some("id-1".to_string()))
+            .unwrap();
+        progress.set(2, RunStatus::Failed, None).unwrap();
+
+        // The failed statement was fixed
+        let resumed = Progress::open(
+            dir.path(),
+            None,
+            &migration,
+            &plan(&["CREATE DATABASE db", "SELECT 2"]),
+        )
+        .unwrap();
+        assert!(resumed.is_done(1));
+        assert!(!resumed.is_done(2));
+
+        // Another environment has its own progress
+        let progress = Progress::open(
+            dir.path(),
+            Some("prd"),
+            &migration,
+            &plan(&["CREATE DATABASE db"]),
+        )
+        .unwrap();
+        assert!(!progress.is_done(1));
+
+        // A statement that succeeded changed
+        assert!(Progress::open(
+            dir.path(),
+            None,
+            &migration,
+            &plan(&["CREATE DATABASE db2", "SELECT 2"]),
+        )
+        .is_err());
+
+        resumed.remove();
+        assert!(!dir.path().join("runs/migrate-V1_2.json").exists());
+    }
+}
@@ -0,0 +1,725 @@
+//! Execution plan of the rendered SQL
+//!
+//! A plan is the list of statements that `apply` would submit to Athena, with the
+//! database, workgroup and output location resolved for each of them.
+//! Building a plan does not call AWS, so it can be reviewed without credentials:
+//!
+//! ```bash
+//! athena apply --dry-run ./prd
+//! ```
+//!
+//! # Saved plans
+//!
+//! The plan can be saved to a file and applied later, so what was reviewed is exactly
+//! what gets executed, even if the templates or the variables change in between:
+//!
+//! ```bash
+//! athena plan -o plan.json ./prd
+//! athena apply plan.json
+//! ```
+//!
+//! The plan file contains the statements, the resolved execution context, the hashes
+//! of the template sources and a content hash. `apply` refuses a plan file whose
+//! content hash does not match (the file was modified) or that has expired.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::build::{self, Build};
+use crate::config::{self, AwsArgs, Settings, Source, Sourced};
+use crate::deps::{self, Objects};
+use crate::directive::{DatabaseResolver, StatementOptions};
+use crate::error::ErrorKind;
+use crate::schedule::{barrier_dependencies, format_indexes};
+use crate::source::SourceMap;
+use crate::sql::{split_statements, statement_kind};
+use crate::tera::template_files;
+use crate::utils::{format_table, get_current_working_dir, sha256_hex};
+use crate::vars::VarArgs;
+
+// Constants
+const PLAN_FILE_VERSION: u32 = 1;
+const PLAN_FILE_EXTENSION: &str = "json";
+
+/// Where a statement is executed
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionContext {
+    /// Data catalog, `None` for `AwsDataCatalog`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog: Option<String>,
+    /// Database, `None` for the default database of the workgroup
+    pub database: Option<String>,
+    /// Athena workgroup, `None` for the `primary` workgroup
+    pub workgroup: Option<String>,
+    /// S3 location of the query results, `None` for the workgroup setting
+    pub output_location: Option<String>,
+    /// The query is stopped after this duration, `None` to wait until it completes
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "humantime_serde"
+    )]
+    pub timeout: Option<Duration>,
+}
+
+impl ExecutionContext {
+    /// The execution context from the settings, on the default database
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            catalog: None,
+            database: None,
+            workgroup: settings.workgroup(),
+            output_location: settings.output_location(),
+            timeout: None,
+        }
+    }
+
+    /// Database for display, qualified with the catalog if any
+    pub fn database_name(&self) -> String {
+        let database = self.database.as_deref().unwrap_or("(default)");
+        match &self.catalog {
+            Some(catalog) => format!("{}.{}", catalog, database),
+            None => database.to_string(),
+        }
+    }
+
+    /// Override the context with the `-- athena:` options of a statement
+    fn with_options(self, options: StatementOptions) -> Self {
+        Self {
+            catalog: options.catalog.or(self.catalog),
+            database: options.database.or(self.database),
+            workgroup: options.workgroup.or(self.workgroup),
+            output_location: options.output_location.or(self.output_location),
+            timeout: options.timeout.or(self.timeout),
+        }
+    }
+}
+
+/// A statement to be submitted to Athena
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlannedStatement {
+    /// Position of the statement, starting from 1
+    pub index: usize,
+    /// Kind of the statement, such as `CREATE EXTERNAL TABLE`
+    pub kind: String,
+    /// Template the statement was rendered from, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The SQL statement
+    pub sql: String,
+    /// Where the statement is executed
+    pub context: ExecutionContext,
+    /// Indexes of the statements that must finish before this one starts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<usize>,
+    /// Recorded in the ledger once applied, see [`crate::state::is_recorded`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub once: bool,
+}
+
+/// The list of statements to be submitted to Athena
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionPlan {
+    pub statements: Vec<PlannedStatement>,
+}
+
+impl ExecutionPlan {
+    /// Split the rendered SQL, resolve the execution context and the source template
+    /// of each statement, and sort the statements by their dependencies
+    pub fn new(sql: &str, sources: &SourceMap, settings: &Settings) -> Result<Self> {
+        let statements = Self::split(sql, sources, settings)?;
+
+        let (barriers, keep_order): (Vec<_>, Vec<_>) = statements.iter().map(|(_, f)| *f).unzip();
+        let objects = statements
+            .iter()
+            .map(|(s, _)| Objects::parse(&s.sql, s.context.database.as_deref()))
+            .collect::<Vec<_>>();
+
+        // Dependencies as positions, from the SQL and from the barriers
+        let mut dependencies = deps::infer(&objects, &keep_order);
+        for (position, indexes) in barrier_dependencies(&barriers).into_iter().enumerate() {
+            dependencies[position].extend(indexes.into_iter().map(|index| index - 1));
+        }
+        let order = deps::topological_order(&dependencies)?;
+
+        let mut statements = statements
+            .into_iter()
+            .zip(dependencies)
+            .map(|((statement, _), depends_on)| {
+                Some(PlannedStatement {
+                    depends_on: depends_on.into_iter().map(|p| p + 1).collect(),
+                    ..statement
+                })
+            })
+            .collect::<Vec<_>>();
+        let statements = order
+            .into_iter()
+            .filter_map(|position| statements[position].take())
+            .collect();
+
+        Ok(Self { statements })
+    }
+
+    /// Split the rendered SQL like [`ExecutionPlan::new`], but keep the statements in the
+    /// order of the templates without inferring their dependencies
+    pub fn in_order(sql: &str, sources: &SourceMap, settings: &Settings) -> Result<Self> {
+        let statements = Self::split(sql, sources, settings)?
+            .into_iter()
+            .map(|(statement, _)| statement)
+            .collect();
+
+        Ok(Self { statements })
+    }
+
+    /// The statements in the order of the templates, with their `barrier` and `keep_order` flags
+    fn split(
+        sql: &str,
+        sources: &SourceMap,
+        settings: &Settings,
+    ) -> Result<Vec<(PlannedStatement, (bool, bool))>> {
+        let mut databases = DatabaseResolver::default();
+
+        split_statements(sql)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, statement)| {
+                let options = StatementOptions::from_comments(&statement.comments)
+                    .with_context(|| format!("statement #{}", i + 1))?;
+                let context = ExecutionContext {
+                    database: databases.resolve(&statement.comments),
+                    ..ExecutionContext::new(settings)
+                };
+                let flags = (options.barrier, options.keep_order);
+                let once = options.once;
+
+                let planned = PlannedStatement {
+                    index: i + 1,
+                    kind: statement_kind(statement.body()),
+                    source: sources
+                        .source_at(statement.offset + statement.body_offset)
+                        .map(str::to_string),
+                    context: context.with_options(options),
+                    sql: statement.sql,
+                    depends_on: vec![],
+                    once,
+                };
+                Ok((planned, flags))
+            })
+            .collect()
+    }
+
+    /// Set the timeout of the statements without a `timeout` directive
+    pub fn with_default_timeout(mut self, timeout: Option<Duration>) -> Self {
+        for statement in &mut self.statements {
+            statement.context.timeout = statement.context.timeout.or(timeout);
+        }
+        self
+    }
+}
+
+impl fmt::Display for ExecutionPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Plan: {} statement(s)", self.statements.len())?;
+        writeln!(f)?;
+
+        let rows = self
+            .statements
+            .iter()
+            .map(|s| {
+                vec![
+                    s.index.to_string(),
+                    s.kind.clone(),
+                    s.context.database_name(),
+                    s.context
+                        .workgroup
+                        .clone()
+                        .unwrap_or_else(|| "(default)".to_string()),
+                    s.context
+                        .output_location
+                        .clone()
+                        .unwrap_or_else(|| "(workgroup)".to_string()),
+                    s.context
+                        .timeout
+                        .map(|t| humantime::format_duration(t).to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    match s.depends_on.as_slice() {
+                        [] => "-".to_string(),
+                        indexes => format_indexes(indexes),
+                    },
+                    format!("{} B", s.sql.len()),
+                    s.source.clone().unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let header = [
+            "#",
+            "KIND",
+            "DATABASE",
+            "WORKGROUP",
+            "OUTPUT LOCATION",
+            "TIMEOUT",
+            "AFTER",
+            "SIZE",
+            "SOURCE",
+        ];
+        write!(f, "{}", format_table(&header, &rows))?;
+
+        Ok(())
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Plan {
+    /// Target path to render. If the target path is a directory,
+    /// the root folder must contains the index.sql file.
+    /// Optional when the `--env` environment defines a `target`
+    #[arg(required_unless_present = "env")]
+    pub file: Option<PathBuf>,
+
+    /// Output path of the plan file. The file will be overwritten if is already exists
+    #[arg(long, short)]
+    pub out: PathBuf,
+
+    /// The plan file cannot be applied after this duration, such as `30m`, `24h` or `7days`
+    #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+    pub expires_in: Duration,
+
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    /// AWS Profile
+    #[arg(long, short)]
+    pub profile: Option<String>,
+
+    /// AWS Region
+    #[arg(long, short)]
+    pub region: Option<String>,
+
+    /// AWS Athena Workgroup
+    #[arg(long, short)]
+    pub workgroup: Option<String>,
+
+    /// AWS Athena output location, such as `s3://path/to/query/bucket/`
+    #[arg(long)]
+    pub output_location: Option<String>,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+pub async fn call(args: Plan) -> Result<()> {
+    let build_args = Build {
+        file: args.file.clone(),
+        out: None,
+        context: args.context.clone(),
+        no_pretty: None,
+        env: args.env.clone(),
+        vars: args.vars.clone(),
+    };
+    let aws_args = AwsArgs {
+        profile: args.profile.clone(),
+        region: args.region.clone(),
+        workgroup: args.workgroup.clone(),
+        output_location: args.output_location.clone(),
+    };
+
+    let (settings, _, plan) = render_plan(&build_args, &aws_args)?;
+    print!("{}", plan);
+
+    let working_dir = get_current_working_dir(args.context.clone())?;
+    let target = config::target_path(args.file.as_deref(), &settings)?;
+    let plan_file = PlanFile::new(SavedPlan::new(
+        &plan,
+        &settings,
+        &working_dir,
+        &target,
+        args.expires_in,
+    )?)?;
+    plan_file.write(&args.out)?;
+
+    println!();
+    println!(
+        "Saved the plan to {}, expires at {}",
+        args.out.display(),
+        plan_file.plan.expires_at
+    );
+
+    Ok(())
+}
+
+/// Resolve the settings, render the target and split it into an execution plan
+pub fn render_plan(
+    build_args: &Build,
+    aws_args: &AwsArgs,
+) -> Result<(Settings, String, ExecutionPlan)> {
+    let settings = config::resolve(
+        build_args.context.as_deref(),
+        build_args.env.as_deref(),
+        aws_args,
+    )?;
+
+    let (sql, sources) =
+        build::render_with_sources(build_args, &settings).context(ErrorKind::Render)?;
+    let plan = ExecutionPlan::new(&sql, &sources, &settings).context(ErrorKind::Render)?;
+
+    Ok((settings, sql, plan))
+}
+
+/// Check if the path is a saved plan file rather than a template
+pub fn is_plan_file(path: &Path) -> bool {
+    path.is_file() && path.extension().and_then(|e| e.to_str()) == Some(PLAN_FILE_EXTENSION)
+}
+
+/// Read and verify a plan file, then resolve the settings to apply it.
+/// The profile and region of the command line override the ones of the plan file
+pub fn load_plan_file(path: &Path, aws_args: &AwsArgs) -> Result<(Settings, ExecutionPlan)> {
+    let plan_file = PlanFile::read(path)?;
+    plan_file.verify(Utc::now())?;
+
+    let drift = plan_file.plan.template_drift()?;
+    if !drift.is_empty() {
+        warn!(
+            "Templates changed since the plan was created, the plan is applied as saved: {:?}",
+            drift
+        );
+    }
+
+    let from_plan = |value: &Option<String>| {
+        value.clone().map(|value| Sourced {
+            value,
+            source: Source::Plan(path.to_path_buf()),
+        })
+    };
+    let from_cli = |value: &Option<String>| {
+        value.clone().map(|value| Sourced {
+            value,
+            source: Source::Cli,
+        })
+    };
+
+    let saved = &plan_file.plan;
+    let settings = Settings {
+        env: saved.env.clone(),
+        profile: from_cli(&aws_args.profile).or_else(|| from_plan(&saved.profile)),
+        region: from_cli(&aws_args.region).or_else(|| from_plan(&saved.region)),
+        workgroup: from_plan(&saved.workgroup),
+        output_location: from_plan(&saved.output_location),
+        ..Default::default()
+    };
+
+    let plan = ExecutionPlan {
+        statements: plan_file.plan.statements,
+    };
+
+    Ok((settings, plan))
+}
+
+/// Content of a plan file, covered by the content hash
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedPlan {
+    /// Version of the plan file format
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Selected environment
+    pub env: Option<String>,
+    /// Context directory the templates were loaded from
+    pub context: PathBuf,
+    /// Rendered target path
+    pub target: PathBuf,
+    pub profile: Option<String>,
+    pub region: Option<String>,
+    pub workgroup: Option<String>,
+    pub output_location: Option<String>,
+    /// SHA-256 of each template source, by template name
+    pub templates: BTreeMap<String, String>,
+    pub statements: Vec<PlannedStatement>,
+}
+
+impl SavedPlan {
+    pub fn new(
+        plan: &ExecutionPlan,
+        settings: &Settings,
+        working_dir: &Path,
+        target: &Path,
+        expires_in: Duration,
+    ) -> Result<Self> {
+        let created_at = Utc::now();
+        let expires_at = created_at
+            + chrono::Duration::from_std(expires_in).context("invalid plan expiration")?;
+
+        Ok(Self {
+            version: PLAN_FILE_VERSION,
+            created_at,
+            expires_at,
+            env: settings.env.clone(),
+            context: working_dir.to_path_buf(),
+            target: target.to_path_buf(),
+            profile: settings.profile(),
+            region: settings.region(),
+            workgroup: settings.workgroup(),
+            output_location: settings.output_location(),
+            templates: hash_templates(working_dir)?,
+            statements: plan.statements.clone(),
+        })
+    }
+
+    /// Content hash of the plan
+    pub fn hash(&self) -> Result<String> {
+        let content = serde_json::to_vec(self).context("could not serialize the plan")?;
+        Ok(sha256_hex(&content))
+    }
+
+    /// Names of the templates that were changed, added or removed since the plan was created
+    pub fn template_drift(&self) -> Result<Vec<String>> {
+        if !self.context.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let current = hash_templates(&self.context)?;
+        let names = self
+            .templates
+            .keys()
+            .chain(current.keys())
+            .collect::<BTreeSet<_>>();
+
+        Ok(names
+            .into_iter()
+            .filter(|name| self.templates.get(*name) != current.get(*name))
+            .cloned()
+            .collect())
+    }
+}
+
+/// A plan file: the saved plan and its content hash
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlanFile {
+    #[serde(flatten)]
+    pub plan: SavedPlan,
+    /// SHA-256 of the saved plan
+    pub content_hash: String,
+}
+
+impl PlanFile {
+    pub fn new(plan: SavedPlan) -> Result<Self> {
+        let content_hash = plan.hash()?;
+        Ok(Self { plan, content_hash })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("could not read plan file {}", path.display()))?;
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("could not parse plan file {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).context("could not serialize the plan")?;
+
+        fs::write(path, content)
+            .with_context(|| format!("could not write plan file {}", path.display()))
+    }
+
+    /// Check the version, the content hash and the expiration of the plan
+    pub fn verify(&self, now: DateTime<Utc>) -> Result<()> {
+        if self.plan.version != PLAN_FILE_VERSION {
+            bail!(
+                "unsupported plan file version {}, expected {}",
+                self.plan.version,
+                PLAN_FILE_VERSION
+            );
+        }
+
+        let hash = self.plan.hash()?;
+        if hash != self.content_hash {
+            bail!(
+                "the plan file was modified: content hash is {} but {} is expected",
+                hash,
+                self.content_hash
+            );
+        }
+
+        if now > self.plan.expires_at {
+            return Err(anyhow!(
+                "the plan expired at {}, create a new plan",
+                self.plan.expires_at
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// SHA-256 of each template in the working dir, by template name
+fn hash_templates(working_dir: &Path) -> Result<BTreeMap<String, String>> {
+    template_files(working_dir)?
+        .into_iter()
+        .map(|(path, name)| {
+            let content =
+                fs::read(&path).with_context(|| format!("could not read template {}", path))?;
+            Ok((name, sha256_hex(&content)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Source, Sourced};
+
+    #[test]
+    fn test_plan() {
+        let settings = Settings {
+            workgroup: Some(Sourced {
+                value: "etl".to_string(),
+                source: Source::Cli,
+            }),
+            ..Default::default()
+        };
+        let sql = "-- Database: db1\nCREATE VIEW v AS SELECT 1;\n\nMSCK REPAIR TABLE t;\n-- Database reset\nSELECT 1";
+        let plan = ExecutionPlan::new(sql, &SourceMap::default(), &settings).unwrap();
+
+        assert_eq!(plan.statements.len(), 3);
+        assert_eq!(plan.statements[0].index, 1);
+        assert_eq!(plan.statements[0].kind, "CREATE VIEW");
+        assert_eq!(plan.statements[0].context.database.as_deref(), Some("db1"));
+        assert_eq!(plan.statements[0].context.workgroup.as_deref(), Some("etl"));
+        assert_eq!(plan.statements[1].kind, "MSCK REPAIR TABLE");
+        assert_eq!(plan.statements[1].context.database.as_deref(), Some("db1"));
+        assert_eq!(plan.statements[2].context.database, None);
+
+        let out = plan.to_string();
+        assert!(out.contains("Plan: 3 statement(s)"));
+        assert!(out.contains("1  CREATE VIEW        db1"));
+        assert!(out.contains("2  MSCK REPAIR TABLE  db1"));
+        assert!(out.contains("3  SELECT             (default)"));
+    }
+
+    #[test]
+    fn test_plan_statement_options() {
+        let sql = indoc::indoc! {"
+            -- Database: db1
+            -- athena: catalog=hive workgroup=adhoc timeout=10m
+            SELECT 1;
+            -- athena: database=db2 output_location=s3://bucket/results/
+            SELECT 2;
+            SELECT 3;
+        "};
+        let plan = ExecutionPlan::new(sql, &SourceMap::default(), &Settings::default()).unwrap();
+
+        let first = &plan.statements[0].context;
+        assert_eq!(first.catalog.as_deref(), Some("hive"));
+        assert_eq!(first.database.as_deref(), Some("db1"));
+        assert_eq!(first.workgroup.as_deref(), Some("adhoc"));
+        assert_eq!(first.timeout, Some(Duration::from_secs(600)));
+        assert_eq!(first.database_name(), "hive.db1");
+
+        let second = &plan.statements[1].context;
+        assert_eq!(second.catalog, None);
+        assert_eq!(second.database.as_deref(), Some("db2"));
+        assert_eq!(
+            second.output_location.as_deref(),
+            Some("s3://bucket/results/")
+        );
+        assert_eq!(second.timeout, None);
+
+        // Options apply to a single statement, the database directive is carried over
+        let third = &plan.statements[2].context;
+        assert_eq!(third.database.as_deref(), Some("db1"));
+        assert_eq!(third.workgroup, None);
+
+        let out = plan.to_string();
+        assert!(out.contains("hive.db1"));
+        assert!(out.contains("10m"));
+
+        let err = ExecutionPlan::new(
+            "SELECT 1;\n-- athena: foo=bar\nSELECT 2;",
+            &SourceMap::default(),
+            &Settings::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("statement #2"));
+
+        let plan = plan.with_default_timeout(Some(Duration::from_secs(30)));
+        assert_eq!(
+            plan.statements[0].context.timeout,
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            plan.statements[1].context.timeout,
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_plan_barrier() {
+        let sql = "SELECT 1;\nSELECT 2;\n-- athena: barrier\nSELECT 3;\nSELECT 4;";
+        let plan = ExecutionPlan::new(sql, &SourceMap::default(), &Settings::default()).unwrap();
+
+        let depends_on = plan
+            .statements
+            .iter()
+            .map(|s| s.depends_on.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(depends_on, vec![vec![], vec![], vec![1, 2], vec![3]]);
+        assert!(plan.to_string().contains("AFTER"));
+        assert!(plan.to_string().contains("1-2"));
+    }
+
+    fn plan_file() -> PlanFile {
+        let plan =
+            ExecutionPlan::new("SELECT 1;", &SourceMap::default(), &Settings::default()).unwrap();
+        let saved = SavedPlan::new(
+            &plan,
+            &Settings::default(),
+            Path::new("/not/exists"),
+            Path::new("/not/exists/index.sql"),
+            Duration::from_secs(3600),
+        );
+        PlanFile::new(saved.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_plan_file_verify() {
+        let plan_file = plan_file();
+        assert!(plan_file.verify(Utc::now()).is_ok());
+
+        // Round trip
+        let content = serde_json::to_string(&plan_file).unwrap();
+        let read: PlanFile = serde_json::from_str(&content).unwrap();
+        assert!(read.verify(Utc::now()).is_ok());
+    }
+
+    #[test]
+    fn test_plan_file_modified() {
+        let mut plan_file = plan_file();
+        plan_file.plan.statements[0].sql = "DROP TABLE t".to_string();
+
+        let err = plan_file.verify(Utc::now()).unwrap_err();
+        assert!(err.to_string().contains("the plan file was modified"));
+    }
+
+    #[test]
+    fn test_plan_file_expired() {
+        let plan_file = plan_file();
+        let later = plan_file.plan.expires_at + chrono::Duration::seconds(1);
+
+        let err = plan_file.verify(later).unwrap_err();
+        assert!(err.to_string().contains("the plan expired"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Polling of the query state
+//!
+//! The state of a query is polled with an exponential backoff: the first poll is
+//! after `--poll-min` (100ms by default), then the delay doubles up to `--poll-max`
+//! (5s by default). Short statements such as `ALTER TABLE ADD PARTITION` complete
+//! in a few polls, long ones are not polled more than needed.
+//!
+//! The engine execution time of the query is used as a hint: a query that already
+//! ran for a minute is not polled every 100ms. A random jitter of ±20% spreads the
+//! polls of concurrent queries.
+
+use std::time::Duration;
+
+// Constants
+const HINT_DIVISOR: u32 = 4;
+const JITTER: f64 = 0.2;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PollArgs {
+    /// First delay between two polls of the query state, such as `100ms`
+    #[arg(long, default_value = "100ms", value_parser = humantime::parse_duration)]
+    pub poll_min: Duration,
+
+    /// Maximum delay between two polls of the query state, such as `5s`
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub poll_max: Duration,
+}
+
+impl Default for PollArgs {
+    fn default() -> Self {
+        Self {
+            poll_min: Duration::from_millis(100),
+            poll_max: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Delays between the polls of a query
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(args: &PollArgs) -> Self {
+        Self::with_bounds(args.poll_min, args.poll_max)
+    }
+
+    /// Delays from `min`, doubled up to `max`
+    pub fn with_bounds(min: Duration, max: Duration) -> Self {
+        let max = max.max(min);
+
+        Self {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    /// Delay before the next poll, `engine_time` is how long the query has run so far
+    pub fn next_delay(&mut self, engine_time: Option<Duration>) -> Duration {
+        let base = self.next;
+        self.next = base.saturating_mul(2).min(self.max);
+
+        // A query that already ran for a while is likely to run a while longer
+        let hint = engine_time.map_or(Duration::ZERO, |t| t / HINT_DIVISOR);
+
+        jitter(base.max(hint)).clamp(self.min, self.max)
+    }
+}
+
+/// Randomly change a delay by up to ±20%
+fn jitter(delay: Duration) -> Duration {
+    delay.mul_f64(1.0 - JITTER + fastrand::f64() * 2.0 * JITTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff::new(&PollArgs::default())
+    }
+
+    #[test]
+    fn test_backoff_grows_up_to_max() {
+        let mut backoff = backoff();
+        let delays = (0..10)
+            .map(|_| backoff.next_delay(None))
+            .collect::<Vec<_>>();
+
+        assert!(delays[0] <= Duration::from_millis(120));
+        assert!(delays[1] >= Duration::from_millis(160));
+        assert!(delays[1] <= Duration::from_millis(240));
+        for delay in &delays {
+            assert!(*delay >= Duration::from_millis(100));
+            assert!(*delay <= Duration::from_secs(5));
+        }
+        assert!(delays[9] >= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_backoff_engine_time_hint() {
+        let mut backoff = backoff();
+
+        // Already ran for 8 seconds, no need to poll every 100ms
+        let delay = backoff.next_delay(Some(Duration::from_secs(8)));
+        assert!(delay >= Duration::from_millis(1600));
+        assert!(delay <= Duration::from_millis(2400));
+
+        // Never more than the max
+        let delay = backoff.next_delay(Some(Duration::from_secs(3600)));
+        assert_eq!(delay, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_backoff_max_below_min() {
+        let mut backoff = Backoff::new(&PollArgs {
+            poll_min: Duration::from_secs(2),
+            poll_max: Duration::from_secs(1),
+        });
+        assert_eq!(backoff.next_delay(None), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,197 @@
+//! Ad-hoc queries
+//!
+//! `athena query` runs one SQL statement and prints its results, see [`crate::result`].
+//! The SQL is given as an argument, with `--file`, or on stdin:
+//!
+//! ```bash
+//! athena query "SELECT * FROM {{ db }}.events LIMIT 10"
+//! athena query -f reports/daily.sql --result-format csv
+//! echo "SHOW TABLES" | athena query --database analytics
+//! ```
+//!
+//! The SQL is a template rendered with the same variables as `athena build`, the
+//! templates of the context dir can be included. The workgroup and output location
+//! come from the settings, see [`crate::config`], and the `-- athena:` directives of
+//! the statement apply, see [`crate::directive`].
+//!
+//! Unlike `athena apply`, there is no health check, the SQL is not printed, and nothing
+//! is recorded. The exit code is the one of the final state of the query, see [`crate::error`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use aws_sdk_athena::{types::QueryExecutionState, Client};
+use std::{
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
+};
+
+use crate::apply::{execution_state, submit_and_wait, QueryOptions};
+use crate::build::{self, Build};
+use crate::config::{self, AwsArgs, Settings};
+use crate::error::ErrorKind;
+use crate::failure::{ErrorFormat, FailureReport};
+use crate::plan::{ExecutionPlan, PlannedStatement};
+use crate::poll::PollArgs;
+use crate::result::ResultArgs;
+use crate::retry::RetryArgs;
+use crate::signal::Shutdown;
+use crate::source::SourceMap;
+use crate::utils::new_run_id;
+use crate::vars::VarArgs;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Query {
+    /// SQL statement to run. Read from stdin when neither the SQL nor `--file` is given
+    #[arg(conflicts_with = "file")]
+    pub sql: Option<String>,
+
+    /// Read the SQL from this template file
+    #[arg(long, short)]
+    pub file: Option<PathBuf>,
+
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    /// Database of the query, a `-- athena: database=` directive overrides it
+    #[arg(long, short)]
+    pub database: Option<String>,
+
+    /// AWS Profile
+    #[arg(long, short)]
+    pub profile: Option<String>,
+
+    /// AWS Region
+    #[arg(long, short)]
+    pub region: Option<String>,
+
+    /// AWS Athena Workgroup
+    #[arg(long, short)]
+    pub workgroup: Option<String>,
+
+    /// AWS Athena output location, such as `s3://path/to/query/bucket/`
+    #[arg(long, short)]
+    pub output_location: Option<String>,
+
+    /// Stop the query when it runs longer than this duration, such as `30s` or `10m`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    /// Format of the failure report printed to stderr: `human` or `json`
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+
+    #[command(flatten)]
+    pub poll: PollArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+
+    #[command(flatten)]
+    pub result: ResultArgs,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+pub async fn call(args: Query) -> Result<()> {
+    let aws_args = AwsArgs {
+        profile: args.profile.clone(),
+        region: args.region.clone(),
+        workgroup: args.workgroup.clone(),
+        output_location: args.output_location.clone(),
+    };
+    let settings = config::resolve(args.context.as_deref(), args.env.as_deref(), &aws_args)?;
+    let statement = statement(&args, &settings).context(ErrorKind::Render)?;
+
+    let shared_config = config::load_aws_config(&settings).await;
+    let client = Client::new(&shared_config);
+    args.result.create_out()?;
+
+    let options = QueryOptions {
+        no_pretty: true,
+        quiet: true,
+        poll: args.poll.clone(),
+        retry: args.retry.clone(),
+    };
+    let shutdown = Shutdown::listen();
+    let execution = submit_and_wait(
+        client.clone(),
+        &statement,
+        &options,
+        &new_run_id(),
+        shutdown.clone(),
+    )
+    .await?;
+
+    match (execution_state(&execution), execution.query_execution_id()) {
+        (QueryExecutionState::Succeeded, Some(id)) => args.result.print(&client, id).await,
+        (QueryExecutionState::Succeeded, None) => Ok(()),
+        _ if shutdown.is_requested() => {
+            Err(anyhow!("the query was stopped")).context(ErrorKind::Interrupted)
+        }
+        _ => {
+            let report = FailureReport::new(&statement, &execution);
+            report.print(args.error_format);
+            Err(anyhow!(report.summary())).context(ErrorKind::QueryFailed)
+        }
+    }
+}
+
+/// Render the SQL and check that it is a single statement
+fn statement(args: &Query, settings: &Settings) -> Result<PlannedStatement> {
+    let (sql, sources) = render(args, settings)?;
+    let plan = ExecutionPlan::new(&sql, &sources, settings)?.with_default_timeout(args.timeout);
+
+    let mut statement = match <[PlannedStatement; 1]>::try_from(plan.statements) {
+        Ok([statement]) => statement,
+        Err(statements) if statements.is_empty() => bail!("no SQL statement to run"),
+        Err(statements) => bail!(
+            "expected one SQL statement, found {}, use `athena apply` to run several",
+            statements.len()
+        ),
+    };
+    statement.context.database = statement.context.database.or(args.database.clone());
+
+    Ok(statement)
+}
+
+/// Render the SQL of the argument, of `--file` or of stdin
+fn render(args: &Query, settings: &Settings) -> Result<(String, SourceMap)> {
+    if let Some(file) = &args.file {
+        let build_args = Build {
+            file: Some(file.clone()),
+            out: None,
+            context: args.context.clone(),
+            no_pretty: None,
+            env: args.env.clone(),
+            vars: args.vars.clone(),
+        };
+        return build::render_with_sources(&build_args, settings);
+    }
+
+    let sql = match &args.sql {
+        Some(sql) => sql.clone(),
+        None => read_stdin()?,
+    };
+    let sql = build::render_inline(&sql, args.context.clone(), &args.vars, settings)?;
+
+    Ok((sql, SourceMap::default()))
+}
+
+fn read_stdin() -> Result<String> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        bail!("no SQL given, pass it as an argument, with --file or on stdin");
+    }
+
+    let mut sql = String::new();
+    stdin
+        .read_to_string(&mut sql)
+        .context("could not read the SQL from stdin")?;
+
+    Ok(sql)
+}
@@ -0,0 +1,412 @@
+//! Query results
+//!
+//! The rows of a query are fetched page by page with `GetQueryResults`, up to
+//! `--max-rows`, and printed to stdout or appended to `--result-out` in one of the
+//! `--result-format` formats:
+//!
+//! - `table`: aligned columns, `NULL` for null values
+//! - `csv`, `tsv`: a header line with the column names, then one line per row
+//! - `json`: an array of objects, `ndjson`: one object per line. Numbers and booleans
+//!   are typed from the column types of the `ResultSetMetadata`
+//!
+//! The column names are the ones of the metadata. The header row Athena adds to the
+//! results of a `SELECT` is not a row.
+
+use anyhow::{Context, Result};
+use aws_sdk_athena::Client;
+use log::warn;
+use serde_json::{Number, Value};
+use std::{fs, io::Write, path::PathBuf};
+
+use crate::error::ErrorKind;
+use crate::utils::format_table;
+
+// Constants
+const PAGE_SIZE: usize = 1000;
+
+/// Output format of the query results
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Aligned columns
+    #[default]
+    Table,
+    Csv,
+    Tsv,
+    /// An array of objects
+    Json,
+    /// One object per line
+    Ndjson,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ResultArgs {
+    /// Format of the query results: `table`, `csv`, `tsv`, `json` or `ndjson`
+    #[arg(long, value_enum, default_value_t = ResultFormat::Table)]
+    pub result_format: ResultFormat,
+
+    /// Maximum number of rows fetched for each query
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_rows: u64,
+
+    /// Write the query results to this file instead of stdout.
+    /// The results of the statements are appended one after the other
+    #[arg(long, value_name = "FILE")]
+    pub result_out: Option<PathBuf>,
+}
+
+impl Default for ResultArgs {
+    fn default() -> Self {
+        Self {
+            result_format: ResultFormat::Table,
+            max_rows: 1000,
+            result_out: None,
+        }
+    }
+}
+
+impl ResultArgs {
+    /// Empty `--result-out`, before the first results are appended
+    pub fn create_out(&self) -> Result<()> {
+        if let Some(path) = &self.result_out {
+            fs::File::create(path)
+                .with_context(|| format!("could not create {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Fetch the results of a query and write them, nothing for a query without columns
+    pub async fn print(&self, client: &Client, query_execution_id: &str) -> Result<()> {
+        let max_rows = usize::try_from(self.max_rows).unwrap_or(usize::MAX);
+        let result = fetch(client, query_execution_id, Some(max_rows)).await?;
+        if result.columns.is_empty() {
+            return Ok(());
+        }
+        if result.truncated {
+            warn!(
+                "Showing the first {} rows of query {}, use --max-rows to fetch more",
+                result.rows.len(),
+                query_execution_id
+            );
+        }
+
+        let output = result.format(self.result_format)?;
+        match &self.result_out {
+            Some(path) => fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(output.as_bytes()))
+                .with_context(|| format!("could not write the results to {}", path.display())),
+            None => {
+                print!("{}", output);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A column of the results
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    /// Athena type, such as `varchar` or `bigint`
+    pub data_type: String,
+}
+
+/// The results of a query, `None` for null values
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryResult {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Option<String>>>,
+    /// More rows than fetched
+    pub truncated: bool,
+}
+
+/// Fetch the results of a query, following the pages of `GetQueryResults` up to
+/// `max_rows` rows
+pub async fn fetch(
+    client: &Client,
+    query_execution_id: &str,
+    max_rows: Option<usize>,
+) -> Result<QueryResult> {
+    let max_rows = max_rows.unwrap_or(usize::MAX);
+    let mut result = QueryResult::default();
+    let mut next_token = None;
+    let mut first_page = true;
+
+    loop {
+        // One more row on the first page for the header of a `SELECT`
+        let page_size = max_rows
+            .saturating_sub(result.rows.len())
+            .saturating_add(usize::from(first_page))
+            .min(PAGE_SIZE);
+        let resp = client
+            .get_query_results()
+            .query_execution_id(query_execution_id)
+            .set_next_token(next_token)
+            .max_results(i32::try_from(page_size).unwrap_or(i32::MAX))
+            .send()
+            .await
+            .with_context(|| {
+                format!(
+                    "could not get query results for query id {}",
+                    query_execution_id
+                )
+            })
+            .context(ErrorKind::Aws)?;
+
+        if let Some(result_set) = resp.result_set() {
+            if first_page {
+                result.columns = result_set
+                    .result_set_metadata()
+                    .map(|m| m.column_info())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|c| Column {
+                        name: c.name().to_string(),
+                        data_type: c.r#type().to_string(),
+                    })
+                    .collect();
+            }
+
+            let mut rows = result_set
+                .rows()
+                .iter()
+                .map(|row| {
+                    row.data()
+                        .iter()
+                        .map(|d| d.var_char_value().map(str::to_string))
+                        .collect::<Vec<_>>()
+                })
+                .peekable();
+            if first_page && rows.peek().is_some_and(|row| result.is_header(row)) {
+                rows.next();
+            }
+            result.rows.extend(rows);
+        }
+        first_page = false;
+
+        next_token = resp.next_token().map(str::to_string);
+        if result.rows.len() >= max_rows {
+            result.truncated = next_token.is_some() || result.rows.len() > max_rows;
+            result.rows.truncate(max_rows);
+            return Ok(result);
+        }
+        if next_token.is_none() {
+            return Ok(result);
+        }
+    }
+}
+
+impl QueryResult {
+    /// Whether a row is the column names, the first row of the results of a `SELECT`
+    fn is_header(&self, row: &[Option<String>]) -> bool {
+        !self.columns.is_empty()
+            && row.len() == self.columns.len()
+            && row
+                .iter()
+                .zip(&self.columns)
+                .all(|(value, column)| value.as_deref() == Some(column.name.as_str()))
+    }
+
+    pub fn format(&self, format: ResultFormat) -> Result<String> {
+        let names = self
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+
+        let output = match format {
+            ResultFormat::Table => {
+                let rows = self
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|v| v.clone().unwrap_or_else(|| "NULL".to_string()))
+                            .collect()
+                    })
+                    .collect::<Vec<_>>();
+                format_table(&names, &rows)
+            }
+            ResultFormat::Csv => self.delimited(&names, ",", csv_field),
+            ResultFormat::Tsv => self.delimited(&names, "\t", tsv_field),
+            ResultFormat::Json => {
+                let objects = self
+                    .rows
+                    .iter()
+                    .map(|row| self.json_object(row))
+                    .collect::<Result<Vec<_>>>()?;
+                match objects.is_empty() {
+                    true => "[]\n".to_string(),
+                    false => format!("[\n  {}\n]\n", objects.join(",\n  ")),
+                }
+            }
+            ResultFormat::Ndjson => self
+                .rows
+                .iter()
+                .map(|row| Ok(self.json_object(row)? + "\n"))
+                .collect::<Result<String>>()?,
+        };
+
+        Ok(output)
+    }
+
+    fn delimited(&self, names: &[&str], separator: &str, field: fn(&str) -> String) -> String {
+        let header = names.iter().map(|n| field(n)).collect::<Vec<_>>();
+        let rows = self.rows.iter().map(|row| {
+            row.iter()
+                .map(|v| v.as_deref().map(field).unwrap_or_default())
+                .collect::<Vec<_>>()
+        });
+
+        std::iter::once(header)
+            .chain(rows)
+            .map(|fields| fields.join(separator) + "\n")
+            .collect()
+    }
+
+    /// A row as a JSON object, with the keys in the order of the columns
+    fn json_object(&self, row: &[Option<String>]) -> Result<String> {
+        let fields = self
+            .columns
+            .iter()
+            .zip(row)
+            .map(|(column, value)| {
+                let value = json_value(&column.data_type, value.as_deref());
+                Ok(format!(
+                    "{}:{}",
+                    serde_json::to_string(&column.name)?,
+                    serde_json::to_string(&value)?
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(format!("{{{}}}", fields.join(",")))
+    }
+}
+
+/// A value typed from its Athena column type, a string for the other types
+fn json_value(data_type: &str, value: Option<&str>) -> Value {
+    let Some(value) = value else {
+        return Value::Null;
+    };
+
+    let typed = match data_type {
+        "boolean" => value.parse::<bool>().ok().map(Value::Bool),
+        "tinyint" | "smallint" | "integer" | "int" | "bigint" => {
+            value.parse::<i64>().ok().map(Value::from)
+        }
+        "float" | "real" | "double" => value
+            .parse::<f64>()
+            .ok()
+            .and_then(Number::from_f64)
+            .map(Value::Number),
+        "json" => serde_json::from_str(value).ok(),
+        _ => None,
+    };
+
+    typed.unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// A CSV field, quoted when it contains a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A TSV field, with tabs, line breaks and backslashes escaped
+fn tsv_field(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result() -> QueryResult {
+        let column = |name: &str, data_type: &str| Column {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+        };
+        let row = |values: [Option<&str>; 3]| values.map(|v| v.map(str::to_string)).to_vec();
+
+        QueryResult {
+            columns: vec![
+                column("id", "bigint"),
+                column("name", "varchar"),
+                column("active", "boolean"),
+            ],
+            rows: vec![
+                row([Some("1"), Some("Jane, \"JJ\""), Some("true")]),
+                row([Some("2"), None, Some("false")]),
+            ],
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let result = result();
+
+        assert_eq!(
+            result.format(ResultFormat::Table).unwrap(),
+            "id  name        active\n1   Jane, \"JJ\"  true\n2   NULL        false\n"
+        );
+        assert_eq!(
+            result.format(ResultFormat::Csv).unwrap(),
+            "id,name,active\n1,\"Jane, \"\"JJ\"\"\",true\n2,,false\n"
+        );
+        assert_eq!(
+            result.format(ResultFormat::Tsv).unwrap(),
+            "id\tname\tactive\n1\tJane, \"JJ\"\ttrue\n2\t\tfalse\n"
+        );
+        assert_eq!(
+            result.format(ResultFormat::Ndjson).unwrap(),
+            "{\"id\":1,\"name\":\"Jane, \\\"JJ\\\"\",\"active\":true}\n{\"id\":2,\"name\":null,\"active\":false}\n"
+        );
+
+        let json: Value =
+            serde_json::from_str(&result.format(ResultFormat::Json).unwrap()).unwrap();
+        assert_eq!(json[1]["name"], Value::Null);
+        assert_eq!(json[0]["id"], 1);
+
+        let empty = QueryResult {
+            rows: vec![],
+            ..result
+        };
+        assert_eq!(empty.format(ResultFormat::Json).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn test_is_header() {
+        let result = result();
+        let header = ["id", "name", "active"].map(|v| Some(v.to_string()));
+        assert!(result.is_header(&header));
+        assert!(!result.is_header(&result.rows[0]));
+    }
+
+    #[test]
+    fn test_json_value() {
+        assert_eq!(json_value("double", Some("1.5")), Value::from(1.5));
+        assert_eq!(json_value("double", Some("NaN")), Value::from("NaN"));
+        assert_eq!(json_value("json", Some("{\"a\":1}"))["a"], 1);
+        assert_eq!(json_value("decimal", Some("1.10")), Value::from("1.10"));
+        assert_eq!(
+            json_value("date", Some("2024-01-01")),
+            Value::from("2024-01-01")
+        );
+    }
+
+    #[test]
+    fn test_tsv_field() {
+        assert_eq!(tsv_field("a\tb\nc\\"), "a\\tb\\nc\\\\");
+    }
+}
@@ -0,0 +1,250 @@
+//! Retries of transient Athena failures
+//!
+//! Two kinds of failures are retried:
+//!
+//! - AWS requests that fail with a throttling or internal error, a timeout or a lost
+//!   response are sent again as is. `StartQueryExecution` is sent with the same
+//!   `ClientRequestToken`, so Athena never starts the same statement twice.
+//! - Queries that end in the `FAILED` state are submitted again, up to
+//!   `--retry-max-attempts`, when the error is transient: Athena marks it as retryable,
+//!   its category or type is in `--retry-category` / `--retry-error-type`, or its
+//!   message contains one of `--retry-message` (`ICEBERG_COMMIT_ERROR`, S3 slowdowns).
+//!   Each attempt has its own `ClientRequestToken`, derived from the run id, the
+//!   statement and the attempt number.
+//!
+//! The delay between two attempts grows exponentially from `--retry-min-delay`
+//! to `--retry-max-delay`.
+
+use aws_sdk_athena::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_athena::types::{QueryExecution, QueryExecutionState};
+use std::time::Duration;
+
+use crate::plan::PlannedStatement;
+use crate::poll::Backoff;
+use crate::utils::sha256_hex;
+
+// Constants
+const TRANSIENT_ERROR_CODES: [&str; 4] = [
+    "TooManyRequestsException",
+    "ThrottlingException",
+    "InternalServerException",
+    "ServiceUnavailableException",
+];
+
+/// Category of an Athena error
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// Internal error of Athena
+    System,
+    /// Error in the query, such as a syntax error or a missing table
+    User,
+    Other,
+}
+
+impl ErrorCategory {
+    fn code(self) -> i32 {
+        match self {
+            Self::System => 1,
+            Self::User => 2,
+            Self::Other => 3,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RetryArgs {
+    /// Maximum number of attempts of a statement that fails with a transient error,
+    /// `1` disables the retries
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub retry_max_attempts: u32,
+
+    /// First delay before submitting a failed statement again, such as `1s`
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    pub retry_min_delay: Duration,
+
+    /// Maximum delay before submitting a failed statement again, such as `30s`
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    pub retry_max_delay: Duration,
+
+    /// Athena error categories to retry, can be repeated
+    #[arg(long = "retry-category", value_enum, default_values_t = [ErrorCategory::System])]
+    pub retry_categories: Vec<ErrorCategory>,
+
+    /// Athena error types to retry, such as `1101`, can be repeated
+    #[arg(long = "retry-error-type")]
+    pub retry_error_types: Vec<i32>,
+
+    /// Retry the failures whose reason contains this text, can be repeated
+    #[arg(
+        long = "retry-message",
+        default_values_t = [
+            "ICEBERG_COMMIT_ERROR".to_string(),
+            "SlowDown".to_string(),
+            "Please reduce your request rate".to_string(),
+        ]
+    )]
+    pub retry_messages: Vec<String>,
+}
+
+impl Default for RetryArgs {
+    fn default() -> Self {
+        Self {
+            retry_max_attempts: 3,
+            retry_min_delay: Duration::from_secs(1),
+            retry_max_delay: Duration::from_secs(30),
+            retry_categories: vec![ErrorCategory::System],
+            retry_error_types: vec![],
+            retry_messages: vec![
+                "ICEBERG_COMMIT_ERROR".to_string(),
+                "SlowDown".to_string(),
+                "Please reduce your request rate".to_string(),
+            ],
+        }
+    }
+}
+
+impl RetryArgs {
+    /// Delays between the attempts of a statement
+    pub fn backoff(&self) -> Backoff {
+        Backoff::with_bounds(self.retry_min_delay, self.retry_max_delay)
+    }
+
+    /// Whether a query that ended after `attempt` attempts should be submitted again
+    pub fn should_retry(&self, execution: &QueryExecution, attempt: u32) -> bool {
+        let Some(status) = execution.status() else {
+            return false;
+        };
+        if attempt >= self.retry_max_attempts
+            || status.state() != Some(&QueryExecutionState::Failed)
+        {
+            return false;
+        }
+
+        let error = status.athena_error();
+        let retryable = error.is_some_and(|e| e.retryable());
+        let category = error.and_then(|e| e.error_category()).is_some_and(|c| {
+            self.retry_categories
+                .iter()
+                .any(|category| category.code() == c)
+        });
+        let error_type = error
+            .and_then(|e| e.error_type())
+            .is_some_and(|t| self.retry_error_types.contains(&t));
+        let message = [
+            status.state_change_reason(),
+            error.and_then(|e| e.error_message()),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|reason| {
+            self.retry_messages
+                .iter()
+                .any(|m| reason.contains(m.as_str()))
+        });
+
+        retryable || category || error_type || message
+    }
+}
+
+/// Whether an AWS request can be sent again as is
+pub fn is_transient<E: ProvideErrorMetadata, R>(err: &SdkError<E, R>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        SdkError::ServiceError(e) => e
+            .err()
+            .code()
+            .is_some_and(|code| TRANSIENT_ERROR_CODES.contains(&code)),
+        _ => false,
+    }
+}
+
+/// `ClientRequestToken` of an attempt of a statement, the same for the same run,
+/// statement and attempt
+pub fn client_request_token(run_id: &str, statement: &PlannedStatement, attempt: u32) -> String {
+    sha256_hex(
+        format!(
+            "{}\n{}\n{}\n{}",
+            run_id, statement.index, attempt, statement.sql
+        )
+        .as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_athena::types::{AthenaError, QueryExecutionStatus};
+
+    fn failed(reason: &str, category: i32, error_type: i32, retryable: bool) -> QueryExecution {
+        QueryExecution::builder()
+            .status(
+                QueryExecutionStatus::builder()
+                    .state(QueryExecutionState::Failed)
+                    .state_change_reason(reason)
+                    .athena_error(
+                        AthenaError::builder()
+                            .error_category(category)
+                            .error_type(error_type)
+                            .retryable(retryable)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let args = RetryArgs::default();
+
+        // Syntax error
+        let execution = failed("line 1:8: mismatched input", 2, 1301, false);
+        assert!(!args.should_retry(&execution, 1));
+        // Internal error
+        let execution = failed("Internal error", 1, 401, false);
+        assert!(args.should_retry(&execution, 1));
+        assert!(args.should_retry(&execution, 2));
+        assert!(!args.should_retry(&execution, 3));
+        // Marked as retryable by Athena
+        assert!(args.should_retry(&failed("", 2, 1, true), 1));
+        // Iceberg commit conflict
+        let execution = failed("ICEBERG_COMMIT_ERROR: Failed to commit", 2, 1, false);
+        assert!(args.should_retry(&execution, 1));
+
+        let args = RetryArgs {
+            retry_categories: vec![],
+            retry_error_types: vec![1301],
+            ..RetryArgs::default()
+        };
+        assert!(!args.should_retry(&failed("Internal error", 1, 401, false), 1));
+        assert!(args.should_retry(&failed("", 2, 1301, false), 1));
+
+        // Cancelled queries are not retried
+        let execution = QueryExecution::builder()
+            .status(
+                QueryExecutionStatus::builder()
+                    .state(QueryExecutionState::Cancelled)
+                    .build(),
+            )
+            .build();
+        assert!(!RetryArgs::default().should_retry(&execution, 1));
+    }
+
+    #[test]
+    fn test_client_request_token() {
+        let statement = PlannedStatement {
+            index: 1,
+            sql: "CREATE DATABASE db".to_string(),
+            ..Default::default()
+        };
+        let token = client_request_token("run", &statement, 1);
+
+        // Between 32 and 128 characters
+        assert_eq!(token.len(), 64);
+        assert_eq!(token, client_request_token("run", &statement, 1));
+        assert_ne!(token, client_request_token("run", &statement, 2));
+        assert_ne!(token, client_request_token("other", &statement, 1));
+    }
+}
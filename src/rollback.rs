@@ -0,0 +1,354 @@
+//! Rollback of the versioned migrations
+//!
+//! `athena rollback --to <version>` reverts the applied migrations newer than `<version>`,
+//! the newest first, by running their `-- migrate:down` section, see [`crate::migrate`].
+//! A migration without a `down` section is reverted with the inverse of its statements,
+//! in reverse order:
+//!
+//! - `CREATE DATABASE db` is reverted with `DROP DATABASE IF EXISTS db`
+//! - `CREATE [EXTERNAL] TABLE t`, `CREATE TABLE t AS ...` with `DROP TABLE IF EXISTS t`
+//! - `CREATE VIEW v` with `DROP VIEW IF EXISTS v`
+//!
+//! Other statements, such as `ALTER TABLE` or `INSERT INTO`, have no inverse and need a
+//! `down` section. So do `CREATE OR REPLACE` and `CREATE ... IF NOT EXISTS`: the object
+//! may have existed before the migration, dropping it would lose its definition or data. The plan of every migration to revert is printed before anything runs.
+//! A migration is removed from the history once its `down` statements succeeded.
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::apply::QueryOptions;
+use crate::deps::{Object, Objects};
+use crate::migrate::{
+    self, History, MigrateArgs, Migration, MigrationStatus, Rendered, Runner, Section, Version,
+};
+use crate::plan::{ExecutionPlan, PlannedStatement};
+use crate::poll::PollArgs;
+use crate::retry::RetryArgs;
+use crate::signal::Shutdown;
+use crate::sql::{statement_kind, tokenize, TokenKind};
+use crate::utils::new_run_id;
+use crate::vars::VarArgs;
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Rollback {
+    #[command(flatten)]
+    pub common: MigrateArgs,
+
+    /// Revert the applied migrations newer than this version, `0` reverts all of them
+    #[arg(long, value_name = "VERSION")]
+    pub to: Version,
+
+    /// Print the rollback plan only, nothing is reverted
+    #[arg(long, short)]
+    pub dry_run: bool,
+
+    /// No pretty print for SQL
+    #[arg(long)]
+    pub no_pretty: bool,
+
+    /// Stop a statement that runs longer than this duration, such as `30s` or `10m`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    #[command(flatten)]
+    pub poll: PollArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+pub async fn call(args: Rollback) -> Result<()> {
+    let (settings, dir) = migrate::resolve(&args.common)?;
+    let migrations = migrate::discover(&dir)?;
+
+    let options = QueryOptions {
+        no_pretty: args.no_pretty,
+        quiet: false,
+        poll: args.poll.clone(),
+        retry: args.retry.clone(),
+    };
+    let shutdown = Shutdown::listen();
+    let history = History::open(
+        &settings,
+        args.common.context.as_deref(),
+        &options,
+        &shutdown,
+    )
+    .await?;
+    let states = migrate::reconcile(&migrations, &history.load().await?)?;
+
+    // The newest first
+    let targets = states
+        .iter()
+        .rev()
+        .filter(|s| s.applied.is_some() && s.version > args.to)
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        info!("Nothing to roll back to version {}", args.to);
+        return Ok(());
+    }
+
+    // Every down plan is built before anything runs
+    let mut plans = vec![];
+    for state in targets {
+        let (Some(migration), Some(applied)) = (&state.migration, &state.applied) else {
+            bail!("cannot roll back {}: its file is missing", state);
+        };
+        if state.status == MigrationStatus::Changed {
+            bail!(
+                "cannot roll back {}: it changed since it was applied",
+                state
+            );
+        }
+
+        let rendered = migrate::render(migration, &args.common, &args.vars, &settings)?;
+        let down = down_section(migration, rendered)?.with_default_timeout(args.timeout);
+        plans.push((migration, applied, down));
+    }
+
+    info!(
+        "Rolling back {} migration(s) to version {}",
+        plans.len(),
+        args.to
+    );
+    for (migration, _, down) in &plans {
+        println!("\n-- {}", migration);
+        down.print(args.no_pretty);
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let runner = match history.runner() {
+        Some(runner) => runner.clone(),
+        None => Runner::new(&settings, options, shutdown).await,
+    };
+    let run_id = new_run_id();
+
+    for (migration, applied, down) in plans {
+        info!(
+            "Rolling back {}: {} statement(s)",
+            migration,
+            down.plan.statements.len()
+        );
+        for statement in &down.plan.statements {
+            let token_id = format!("{}-V{}-down", run_id, migration.version);
+            runner.run(statement, &token_id).await.with_context(|| {
+                format!(
+                    "rollback of {} failed at statement #{} ({}), it is still recorded as applied",
+                    migration, statement.index, statement.kind
+                )
+            })?;
+        }
+
+        history.remove(applied).await?;
+        info!("Rolled back {}", migration);
+    }
+
+    Ok(())
+}
+
+/// The `down` section of a migration, or the inverse of its statements
+fn down_section(migration: &Migration, rendered: Rendered) -> Result<Section> {
+    if let Some(down) = rendered.down {
+        return Ok(down);
+    }
+
+    let mut statements = vec![];
+    for statement in rendered.up.plan.statements.iter().rev() {
+        let Some(inverse) = inverse(statement) else {
+            bail!(
+                "{} has no `-- migrate:down` section and statement #{} ({}) cannot be reverted automatically",
+                migration,
+                statement.index,
+                statement.kind
+            );
+        };
+        statements.push(PlannedStatement {
+            index: statements.len() + 1,
+            ..inverse
+        });
+    }
+
+    let sql = statements
+        .iter()
+        .map(|s| format!("{};", s.sql))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(Section {
+        sql,
+        plan: ExecutionPlan { statements },
+    })
+}
+
+/// `DROP ... IF EXISTS` of the database, table or view created by a plain `CREATE`
+pub fn inverse(statement: &PlannedStatement) -> Option<PlannedStatement> {
+    if !is_plain_create(&statement.sql) {
+        return None;
+    }
+    let objects = Objects::parse(&statement.sql, statement.context.database.as_deref());
+    let [created] = objects.creates.iter().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+
+    let kind = statement.kind.as_str();
+    let sql = match created {
+        Object::Database(database) if kind.ends_with("DATABASE") || kind.ends_with("SCHEMA") => {
+            format!("DROP DATABASE IF EXISTS `{}`", database)
+        }
+        Object::Table { database, name } if kind.ends_with("VIEW") => match database {
+            Some(database) => format!("DROP VIEW IF EXISTS \"{}\".\"{}\"", database, name),
+            None => format!("DROP VIEW IF EXISTS \"{}\"", name),
+        },
+        Object::Table { database, name } if kind.ends_with("TABLE") => match database {
+            Some(database) => format!("DROP TABLE IF EXISTS `{}`.`{}`", database, name),
+            None => format!("DROP TABLE IF EXISTS `{}`", name),
+        },
+        _ => return None,
+    };
+
+    Some(PlannedStatement {
+        index: statement.index,
+        kind: statement_kind(&sql),
+        source: statement.source.clone(),
+        sql,
+        context: statement.context.clone(),
+        ..Default::default()
+    })
+}
+
+/// A `CREATE` without `OR REPLACE` nor `IF NOT EXISTS`
+fn is_plain_create(sql: &str) -> bool {
+    let Ok(tokens) = tokenize(sql) else {
+        return false;
+    };
+    let words = tokens
+        .iter()
+        .filter(|t| !t.is_trivia())
+        .take_while(|t| t.kind == TokenKind::Word)
+        .map(|t| t.text.to_uppercase())
+        .collect::<Vec<_>>();
+
+    let Some(kind) = words
+        .iter()
+        .position(|w| matches!(w.as_str(), "TABLE" | "VIEW" | "DATABASE" | "SCHEMA"))
+    else {
+        return false;
+    };
+    words.first().is_some_and(|w| w == "CREATE")
+        && !words[..kind].iter().any(|w| w == "REPLACE")
+        && words.get(kind + 1).is_none_or(|w| w != "IF")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::ExecutionContext;
+
+    fn statement(sql: &str, database: Option<&str>) -> PlannedStatement {
+        PlannedStatement {
+            index: 1,
+            kind: statement_kind(sql),
+            sql: sql.to_string(),
+            context: ExecutionContext {
+                database: database.map(str::to_string),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn inverse_sql(sql: &str, database: Option<&str>) -> Option<String> {
+        inverse(&statement(sql, database)).map(|s| s.sql)
+    }
+
+    #[test]
+    fn test_inverse() {
+        assert_eq!(
+            inverse_sql("CREATE DATABASE analytics", None).as_deref(),
+            Some("DROP DATABASE IF EXISTS `analytics`")
+        );
+        assert_eq!(
+            inverse_sql(
+                "CREATE EXTERNAL TABLE `events` (id string) LOCATION 's3://b/'",
+                Some("db")
+            )
+            .as_deref(),
+            Some("DROP TABLE IF EXISTS `db`.`events`")
+        );
+        assert_eq!(
+            inverse_sql("CREATE TABLE db.t AS SELECT 1 AS a", None).as_deref(),
+            Some("DROP TABLE IF EXISTS `db`.`t`")
+        );
+        assert_eq!(
+            inverse_sql("CREATE VIEW v AS SELECT * FROM db.t", None).as_deref(),
+            Some("DROP VIEW IF EXISTS \"v\"")
+        );
+
+        // The object may have existed before
+        assert!(inverse_sql("CREATE DATABASE IF NOT EXISTS analytics", None).is_none());
+        assert!(inverse_sql(
+            "CREATE EXTERNAL TABLE IF NOT EXISTS events (id string) LOCATION 's3://b/'",
+            Some("db")
+        )
+        .is_none());
+        assert!(inverse_sql("CREATE OR REPLACE VIEW v AS SELECT * FROM db.t", None).is_none());
+
+        let drop = inverse(&statement("CREATE VIEW v AS SELECT 1", Some("db"))).unwrap();
+        assert_eq!(drop.kind, "DROP VIEW");
+        assert_eq!(drop.context.database.as_deref(), Some("db"));
+
+        assert!(inverse_sql("ALTER TABLE t ADD COLUMNS (a int)", Some("db")).is_none());
+        assert!(inverse_sql("INSERT INTO t VALUES (1)", Some("db")).is_none());
+    }
+
+    #[test]
+    fn test_down_section() {
+        let migration = Migration {
+            version: "1".parse().unwrap(),
+            description: "init".to_string(),
+            path: "V1__init.sql".into(),
+            checksum: String::new(),
+        };
+        let up = |sqls: &[&str]| Section {
+            sql: String::new(),
+            plan: ExecutionPlan {
+                statements: sqls.iter().map(|sql| statement(sql, None)).collect(),
+            },
+        };
+
+        let rendered = Rendered {
+            up: up(&["CREATE DATABASE db", "CREATE TABLE db.t (a int)"]),
+            down: None,
+        };
+        let down = down_section(&migration, rendered).unwrap();
+        assert_eq!(
+            down.sql,
+            "DROP TABLE IF EXISTS `db`.`t`;\nDROP DATABASE IF EXISTS `db`;"
+        );
+        assert_eq!(down.plan.statements[1].index, 2);
+
+        // An explicit down section is used as is
+        let rendered = Rendered {
+            up: up(&["ALTER TABLE db.t ADD COLUMNS (b int)"]),
+            down: Some(up(&["ALTER TABLE db.t REPLACE COLUMNS (a int)"])),
+        };
+        let down = down_section(&migration, rendered).unwrap();
+        assert_eq!(down.plan.statements[0].kind, "ALTER TABLE");
+
+        let rendered = Rendered {
+            up: up(&["ALTER TABLE db.t ADD COLUMNS (b int)"]),
+            down: None,
+        };
+        let err = down_section(&migration, rendered).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("statement #1 (ALTER TABLE) cannot be reverted automatically"));
+    }
+}
@@ -0,0 +1,248 @@
+//! Scheduling of the statements
+//!
+//! With `--concurrency N`, up to N statements are in flight at the same time.
+//! A statement starts once every statement it depends on has finished, the
+//! statements that are ready start in the order of the plan.
+//!
+//! Dependencies are inferred from the SQL, see [`crate::deps`], and come from
+//! barriers: a statement with a `-- athena: barrier` directive starts after every
+//! previous statement has finished, and the following statements start after it
+//! has finished. When a statement does not succeed, the statements depending on
+//! it are skipped.
+//!
+//! ```sql
+//! ALTER TABLE t ADD PARTITION (dt = '2024-01-01') LOCATION 's3://bucket/t/2024-01-01/';
+//! ALTER TABLE t ADD PARTITION (dt = '2024-01-02') LOCATION 's3://bucket/t/2024-01-02/';
+//! -- athena: barrier
+//! CREATE OR REPLACE VIEW v AS SELECT * FROM t;
+//! ```
+//!
+//! With the default `--concurrency 1`, the statements run one after another.
+
+use std::fmt::Write;
+
+use crate::plan::ExecutionPlan;
+
+/// Dependencies of each statement from the barriers, as 1-based statement indexes
+pub fn barrier_dependencies(barriers: &[bool]) -> Vec<Vec<usize>> {
+    let mut dependencies = Vec::with_capacity(barriers.len());
+    let mut last_barrier: Option<usize> = None;
+
+    for (i, barrier) in barriers.iter().enumerate() {
+        let index = i + 1;
+        let since = last_barrier.unwrap_or(1);
+
+        if *barrier {
+            // Every statement since the last barrier, the barrier included
+            dependencies.push((since..index).collect());
+            last_barrier = Some(index);
+        } else {
+            dependencies.push(last_barrier.into_iter().collect());
+        }
+    }
+
+    dependencies
+}
+
+/// Format statement indexes as ranges, such as `1-3,5`
+pub fn format_indexes(indexes: &[usize]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < indexes.len() {
+        let start = indexes[i];
+        let mut end = start;
+        while i + 1 < indexes.len() && indexes[i + 1] == end + 1 {
+            i += 1;
+            end = indexes[i];
+        }
+
+        if !out.is_empty() {
+            out.push(',');
+        }
+        let _ = match end - start {
+            0 => write!(out, "{}", start),
+            _ => write!(out, "{}-{}", start, end),
+        };
+        i += 1;
+    }
+
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    /// Not started because a dependency did not succeed
+    Skipped,
+}
+
+/// Tracks which statements can start
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    /// Positions in the plan of the dependencies of each statement
+    dependencies: Vec<Vec<usize>>,
+    status: Vec<Status>,
+}
+
+impl Scheduler {
+    /// The statements of the plan are sorted by their dependencies
+    pub fn new(plan: &ExecutionPlan) -> Self {
+        let dependencies = plan
+            .statements
+            .iter()
+            .map(|s| {
+                s.depends_on
+                    .iter()
+                    .filter_map(|index| plan.statements.iter().position(|d| d.index == *index))
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            dependencies,
+            status: vec![Status::Pending; plan.statements.len()],
+        }
+    }
+
+    /// Position of the next statement that can start, marked as running
+    pub fn next_ready(&mut self) -> Option<usize> {
+        let position = (0..self.status.len()).find(|&p| {
+            self.status[p] == Status::Pending
+                && self.dependencies[p]
+                    .iter()
+                    .all(|&d| self.status[d] == Status::Succeeded)
+        })?;
+
+        self.status[position] = Status::Running;
+        Some(position)
+    }
+
+    /// Positions of up to `limit` statements that can start now, marked as running
+    pub fn take_ready(&mut self, limit: usize) -> Vec<usize> {
+        std::iter::from_fn(|| self.next_ready())
+            .take(limit)
+            .collect()
+    }
+
+    /// Mark a statement as finished. When it did not succeed, the statements depending
+    /// on it, directly or not, are skipped: their positions are returned
+    pub fn finish(&mut self, position: usize, succeeded: bool) -> Vec<usize> {
+        self.status[position] = match succeeded {
+            true => Status::Succeeded,
+            false => Status::Failed,
+        };
+
+        let mut skipped = vec![];
+        // Dependencies come first in the plan
+        for p in position + 1..self.status.len() {
+            if self.status[p] == Status::Pending
+                && self.dependencies[p]
+                    .iter()
+                    .any(|&d| matches!(self.status[d], Status::Failed | Status::Skipped))
+            {
+                self.status[p] = Status::Skipped;
+                skipped.push(p);
+            }
+        }
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::PlannedStatement;
+
+    fn plan(dependencies: Vec<Vec<usize>>) -> ExecutionPlan {
+        ExecutionPlan {
+            statements: dependencies
+                .into_iter()
+                .enumerate()
+                .map(|(i, depends_on)| PlannedStatement {
+                    index: i + 1,
+                    depends_on,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_barrier_dependencies() {
+        assert_eq!(
+            barrier_dependencies(&[false, false, false]),
+            vec![vec![], vec![], vec![]] as Vec<Vec<usize>>
+        );
+        assert_eq!(
+            barrier_dependencies(&[false, false, true, false, false, true, true]),
+            vec![
+                vec![],
+                vec![],
+                vec![1, 2],
+                vec![3],
+                vec![3],
+                vec![3, 4, 5],
+                vec![6]
+            ]
+        );
+        // A barrier on the first statement has nothing to wait for
+        assert_eq!(barrier_dependencies(&[true, false]), vec![vec![], vec![1]]);
+    }
+
+    #[test]
+    fn test_format_indexes() {
+        assert_eq!(format_indexes(&[]), "");
+        assert_eq!(format_indexes(&[3]), "3");
+        assert_eq!(format_indexes(&[1, 2, 3, 5, 7, 8]), "1-3,5,7-8");
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let plan = plan(barrier_dependencies(&[false, false, true, false]));
+        let mut scheduler = Scheduler::new(&plan);
+
+        // The first two are independent
+        assert_eq!(scheduler.next_ready(), Some(0));
+        assert_eq!(scheduler.next_ready(), Some(1));
+        // The barrier waits for both
+        assert_eq!(scheduler.next_ready(), None);
+        scheduler.finish(1, true);
+        assert_eq!(scheduler.next_ready(), None);
+        scheduler.finish(0, true);
+        assert_eq!(scheduler.next_ready(), Some(2));
+        assert_eq!(scheduler.next_ready(), None);
+        scheduler.finish(2, true);
+        assert_eq!(scheduler.next_ready(), Some(3));
+        scheduler.finish(3, true);
+        assert_eq!(scheduler.next_ready(), None);
+    }
+
+    #[test]
+    fn test_scheduler_take_ready() {
+        let plan = plan(vec![vec![], vec![], vec![], vec![1]]);
+        let mut scheduler = Scheduler::new(&plan);
+
+        // Up to the limit, the others stay pending
+        assert_eq!(scheduler.take_ready(2), vec![0, 1]);
+        assert_eq!(scheduler.take_ready(2), vec![2]);
+        scheduler.finish(0, true);
+        assert_eq!(scheduler.take_ready(2), vec![3]);
+    }
+
+    #[test]
+    fn test_scheduler_skip_dependents() {
+        let plan = plan(vec![vec![], vec![1], vec![2], vec![]]);
+        let mut scheduler = Scheduler::new(&plan);
+
+        assert_eq!(scheduler.next_ready(), Some(0));
+        assert_eq!(scheduler.next_ready(), Some(3));
+        // #2 and #3, which depends on #2, are skipped
+        assert_eq!(scheduler.finish(0, false), vec![1, 2]);
+        assert_eq!(scheduler.finish(3, true), Vec::<usize>::new());
+        assert_eq!(scheduler.next_ready(), None);
+    }
+}
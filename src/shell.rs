@@ -0,0 +1,572 @@
+//! Interactive SQL shell
+//!
+//! `athena shell` reads SQL with line editing and runs it in Athena:
+//!
+//! - A statement can span several lines, it ends with `;`
+//! - The SQL is rendered with the template variables, like `athena query`
+//! - The results are printed as a table, see [`crate::result`]
+//! - Ctrl-C stops the running query and goes back to the prompt, Ctrl-D exits
+//! - The input is saved to `.athena/shell_history` and loaded again on the next start
+//! - Tab completes the databases and tables, listed with `ListDatabases` and
+//!   `ListTableMetadata` on first use
+//!
+//! Lines starting with `\` are meta-commands:
+//!
+//! | Command             | Description                                        |
+//! |---------------------|----------------------------------------------------|
+//! | `\use [database]`   | Run the next queries in the database, or print it  |
+//! | `\workgroup [name]` | Run the next queries in the workgroup, or print it |
+//! | `\help`             | Print the meta-commands                            |
+//! | `\quit`             | Exit, like Ctrl-D                                  |
+//!
+//! The progress of the queries is not logged unless `RUST_LOG` is set.
+
+use anyhow::{bail, Context, Result};
+use aws_sdk_athena::{
+    types::{QueryExecution, QueryExecutionState},
+    Client,
+};
+use log::{debug, error, LevelFilter};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    history::DefaultHistory,
+    Editor, Helper, Highlighter, Hinter, Validator,
+};
+use std::{cell::RefCell, collections::HashMap, fs, path::PathBuf, str::FromStr};
+use tokio::{runtime::Handle, task::block_in_place};
+
+use crate::apply::{execution_state, submit_and_wait, QueryOptions};
+use crate::build;
+use crate::config::{self, AwsArgs, Settings, Source, Sourced};
+use crate::error::ErrorKind;
+use crate::failure::{ErrorFormat, FailureReport};
+use crate::plan::{ExecutionPlan, PlannedStatement};
+use crate::poll::PollArgs;
+use crate::result::ResultArgs;
+use crate::retry::RetryArgs;
+use crate::signal::Shutdown;
+use crate::source::SourceMap;
+use crate::sql;
+use crate::state;
+use crate::utils::new_run_id;
+use crate::vars::VarArgs;
+
+// Constants
+const HISTORY_FILENAME: &str = "shell_history";
+const DEFAULT_CATALOG: &str = "AwsDataCatalog";
+const META_COMMANDS: [&str; 4] = ["\\use", "\\workgroup", "\\help", "\\quit"];
+const HELP: &str = "\
+Statements end with `;` and can span several lines. Ctrl-C stops the running query, Ctrl-D exits.
+
+  \\use [database]     Run the next queries in the database, or print it
+  \\workgroup [name]   Run the next queries in the workgroup, or print it
+  \\help               Print this help
+  \\quit               Exit";
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Shell {
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+
+    /// Database of the queries, `\use <database>` changes it
+    #[arg(long, short)]
+    pub database: Option<String>,
+
+    /// AWS Profile
+    #[arg(long, short)]
+    pub profile: Option<String>,
+
+    /// AWS Region
+    #[arg(long, short)]
+    pub region: Option<String>,
+
+    /// AWS Athena Workgroup, `\workgroup <name>` changes it
+    #[arg(long, short)]
+    pub workgroup: Option<String>,
+
+    /// AWS Athena output location, such as `s3://path/to/query/bucket/`
+    #[arg(long, short)]
+    pub output_location: Option<String>,
+
+    /// History file, `.athena/shell_history` by default
+    #[arg(long, value_name = "FILE")]
+    pub history: Option<PathBuf>,
+
+    /// Stop a query that runs longer than this duration, such as `30s` or `10m`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub timeout: Option<std::time::Duration>,
+
+    #[command(flatten)]
+    pub poll: PollArgs,
+
+    #[command(flatten)]
+    pub retry: RetryArgs,
+
+    #[command(flatten)]
+    pub result: ResultArgs,
+
+    #[command(flatten)]
+    pub vars: VarArgs,
+}
+
+pub async fn call(args: Shell) -> Result<()> {
+    let aws_args = AwsArgs {
+        profile: args.profile.clone(),
+        region: args.region.clone(),
+        workgroup: args.workgroup.clone(),
+        output_location: args.output_location.clone(),
+    };
+    let settings = config::resolve(args.context.as_deref(), args.env.as_deref(), &aws_args)?;
+    if std::env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(LevelFilter::Warn);
+    }
+
+    let shared_config = config::load_aws_config(&settings).await;
+    let client = Client::new(&shared_config);
+    args.result.create_out()?;
+
+    let history = match &args.history {
+        Some(path) => path.clone(),
+        None => state::state_dir(&settings, args.context.as_deref())?.join(HISTORY_FILENAME),
+    };
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::new()
+        .context("could not initialize the line editor")?;
+    editor.set_helper(Some(ShellHelper::new(
+        client.clone(),
+        args.database.clone(),
+    )));
+    if let Err(e) = editor.load_history(&history) {
+        debug!("No history loaded from {}: {}", history.display(), e);
+    }
+
+    let mut session = Session {
+        client,
+        settings,
+        database: args.database.clone(),
+        args: &args,
+    };
+    println!("Type \\help for help, end the statements with `;`");
+
+    let mut buffer = String::new();
+    loop {
+        // The next lines of a statement are aligned with the first one
+        let prompt = if buffer.is_empty() {
+            session.prompt()
+        } else {
+            format!("{:>width$}> ", "", width = session.prompt().len() - 2)
+        };
+        let line = match block_in_place(|| editor.readline(&prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e).context("could not read the input"),
+        };
+
+        if buffer.is_empty() && line.trim_start().starts_with('\\') {
+            let _ = editor.add_history_entry(line.trim());
+            match line.parse::<MetaCommand>() {
+                Ok(MetaCommand::Quit) => break,
+                Ok(command) => session.meta_command(command),
+                Err(e) => error!("{:#}", e),
+            }
+            if let Some(helper) = editor.helper_mut() {
+                helper.database = session.database.clone();
+            }
+            continue;
+        }
+
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(&line);
+        if buffer.trim().is_empty() {
+            buffer.clear();
+            continue;
+        }
+        if !sql::is_terminated(&buffer) {
+            continue;
+        }
+
+        let sql = std::mem::take(&mut buffer);
+        let _ = editor.add_history_entry(sql.trim());
+        if let Err(e) = session.run(&sql).await {
+            error!("{:#}", e);
+        }
+    }
+
+    if let Some(dir) = history.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("could not create directory {}", dir.display()))?;
+    }
+    editor
+        .save_history(&history)
+        .with_context(|| format!("could not save the history to {}", history.display()))
+}
+
+/// A `\` command of the shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MetaCommand {
+    Use(Option<String>),
+    Workgroup(Option<String>),
+    Help,
+    Quit,
+}
+
+impl FromStr for MetaCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next().map(|w| w.trim_end_matches(';').to_string());
+        if words.next().is_some() {
+            bail!("too many arguments for {}", command);
+        }
+
+        match (command, arg) {
+            ("\\use" | "\\u", arg) => Ok(Self::Use(arg)),
+            ("\\workgroup" | "\\w", arg) => Ok(Self::Workgroup(arg)),
+            ("\\help" | "\\h" | "\\?", None) => Ok(Self::Help),
+            ("\\quit" | "\\q", None) => Ok(Self::Quit),
+            ("\\help" | "\\h" | "\\?" | "\\quit" | "\\q", Some(_)) => {
+                bail!("{} takes no argument", command)
+            }
+            _ => bail!("unknown command {}, type \\help for help", command),
+        }
+    }
+}
+
+/// The settings of the queries, changed by the meta-commands
+struct Session<'a> {
+    client: Client,
+    settings: Settings,
+    database: Option<String>,
+    args: &'a Shell,
+}
+
+impl Session<'_> {
+    fn prompt(&self) -> String {
+        format!(
+            "athena:{}> ",
+            self.database.as_deref().unwrap_or("(default)")
+        )
+    }
+
+    fn meta_command(&mut self, command: MetaCommand) {
+        match command {
+            MetaCommand::Use(Some(database)) => self.database = Some(database),
+            MetaCommand::Use(None) => println!(
+                "Database: {}",
+                self.database.as_deref().unwrap_or("(default)")
+            ),
+            MetaCommand::Workgroup(Some(workgroup)) => {
+                self.settings.workgroup = Some(Sourced {
+                    value: workgroup,
+                    source: Source::Cli,
+                })
+            }
+            MetaCommand::Workgroup(None) => println!(
+                "Workgroup: {}",
+                self.settings.workgroup().as_deref().unwrap_or("(default)")
+            ),
+            MetaCommand::Help => println!("{}", HELP),
+            MetaCommand::Quit => {}
+        }
+    }
+
+    /// Render the SQL and run its statements, up to the first one that does not succeed
+    async fn run(&self, sql: &str) -> Result<()> {
+        let sql = build::render_inline(
+            sql,
+            self.args.context.clone(),
+            &self.args.vars,
+            &self.settings,
+        )
+        .context(ErrorKind::Render)?;
+        let plan = ExecutionPlan::new(&sql, &SourceMap::default(), &self.settings)?
+            .with_default_timeout(self.args.timeout);
+
+        for mut statement in plan.statements {
+            statement.context.database = statement.context.database.or(self.database.clone());
+            if !self.execute(&statement).await? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a statement and print its results, `false` if it did not succeed
+    async fn execute(&self, statement: &PlannedStatement) -> Result<bool> {
+        let options = QueryOptions {
+            no_pretty: true,
+            quiet: true,
+            poll: self.args.poll.clone(),
+            retry: self.args.retry.clone(),
+        };
+
+        // Ctrl-C stops this query only
+        let (shutdown, listener) = Shutdown::listen_once();
+        let execution = submit_and_wait(
+            self.client.clone(),
+            statement,
+            &options,
+            &new_run_id(),
+            shutdown.clone(),
+        )
+        .await;
+        listener.abort();
+        let execution = execution?;
+
+        if execution_state(&execution) == QueryExecutionState::Succeeded {
+            if let Some(id) = execution.query_execution_id() {
+                self.args.result.print(&self.client, id).await?;
+            }
+            println!("{}", summary(&execution));
+            return Ok(true);
+        }
+
+        if shutdown.is_requested() {
+            println!(
+                "Stopped {}",
+                execution.query_execution_id().unwrap_or("the query")
+            );
+        } else {
+            FailureReport::new(statement, &execution).print(ErrorFormat::Human);
+        }
+        Ok(false)
+    }
+}
+
+/// The state, run time and data scanned by a query
+fn summary(execution: &QueryExecution) -> String {
+    let statistics = execution.statistics();
+    let millis = statistics
+        .and_then(|s| s.total_execution_time_in_millis())
+        .and_then(|millis| u64::try_from(millis).ok())
+        .unwrap_or_default();
+    let scanned = statistics
+        .and_then(|s| s.data_scanned_in_bytes())
+        .unwrap_or_default();
+
+    format!(
+        "{} in {}, {} bytes scanned",
+        execution_state(execution).as_str(),
+        humantime::format_duration(std::time::Duration::from_millis(millis)),
+        scanned
+    )
+}
+
+/// Completion of the meta-commands, databases and tables
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ShellHelper {
+    client: Client,
+    handle: Handle,
+    /// Database of the tables without a database
+    database: Option<String>,
+    databases: RefCell<Option<Vec<String>>>,
+    tables: RefCell<HashMap<String, Vec<String>>>,
+}
+
+impl ShellHelper {
+    fn new(client: Client, database: Option<String>) -> Self {
+        Self {
+            client,
+            handle: Handle::current(),
+            database,
+            databases: RefCell::default(),
+            tables: RefCell::default(),
+        }
+    }
+
+    /// The databases of the catalog, listed once
+    fn databases(&self) -> Vec<String> {
+        if let Some(databases) = self.databases.borrow().as_ref() {
+            return databases.clone();
+        }
+
+        let databases = self
+            .handle
+            .block_on(list_databases(&self.client))
+            .unwrap_or_else(|e| {
+                debug!("Could not list the databases: {:#}", e);
+                vec![]
+            });
+        *self.databases.borrow_mut() = Some(databases.clone());
+        databases
+    }
+
+    /// The tables of a database, listed once
+    fn tables(&self, database: &str) -> Vec<String> {
+        if let Some(tables) = self.tables.borrow().get(database) {
+            return tables.clone();
+        }
+
+        let tables = self
+            .handle
+            .block_on(list_tables(&self.client, database))
+            .unwrap_or_else(|e| {
+                debug!("Could not list the tables of {}: {:#}", database, e);
+                vec![]
+            });
+        self.tables
+            .borrow_mut()
+            .insert(database.to_string(), tables.clone());
+        tables
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = word_start(&line[..pos]);
+        let (before, word) = line[..pos].split_at(start);
+
+        let candidates = if before.trim().is_empty() && word.starts_with('\\') {
+            META_COMMANDS.iter().map(|c| c.to_string()).collect()
+        } else if let Some((database, _)) = word.rsplit_once('.') {
+            self.tables(database)
+                .into_iter()
+                .map(|table| format!("{}.{}", database, table))
+                .collect()
+        } else if before.trim_start().starts_with("\\u") {
+            self.databases()
+        } else {
+            let mut candidates = self.databases();
+            if let Some(database) = &self.database {
+                candidates.extend(self.tables(database));
+            }
+            candidates
+        };
+
+        let pairs = candidates
+            .into_iter()
+            .filter(|c| starts_with_ignore_case(c, word))
+            .map(|c| Pair {
+                display: c.clone(),
+                replacement: c,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+/// Start of the word to complete: a name, `database.table` or a meta-command
+fn word_start(line: &str) -> usize {
+    line.char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '.' | '\\')))
+        .map_or(0, |(i, c)| i + c.len_utf8())
+}
+
+fn starts_with_ignore_case(candidate: &str, prefix: &str) -> bool {
+    candidate
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+async fn list_databases(client: &Client) -> Result<Vec<String>> {
+    let mut databases = vec![];
+    let mut next_token = None;
+
+    loop {
+        let resp = client
+            .list_databases()
+            .catalog_name(DEFAULT_CATALOG)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .context("could not list the databases")?;
+
+        databases.extend(resp.database_list().iter().map(|d| d.name().to_string()));
+        match resp.next_token() {
+            Some(token) => next_token = Some(token.to_string()),
+            None => return Ok(databases),
+        }
+    }
+}
+
+async fn list_tables(client: &Client, database: &str) -> Result<Vec<String>> {
+    let mut tables = vec![];
+    let mut next_token = None;
+
+    loop {
+        let resp = client
+            .list_table_metadata()
+            .catalog_name(DEFAULT_CATALOG)
+            .database_name(database)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .with_context(|| format!("could not list the tables of {}", database))?;
+
+        tables.extend(
+            resp.table_metadata_list()
+                .iter()
+                .map(|t| t.name().to_string()),
+        );
+        match resp.next_token() {
+            Some(token) => next_token = Some(token.to_string()),
+            None => return Ok(tables),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_command() {
+        assert_eq!(
+            "\\use analytics".parse::<MetaCommand>().unwrap(),
+            MetaCommand::Use(Some("analytics".to_string()))
+        );
+        assert_eq!(
+            "  \\use analytics;  ".parse::<MetaCommand>().unwrap(),
+            MetaCommand::Use(Some("analytics".to_string()))
+        );
+        assert_eq!(
+            "\\w".parse::<MetaCommand>().unwrap(),
+            MetaCommand::Workgroup(None)
+        );
+        assert_eq!("\\q".parse::<MetaCommand>().unwrap(), MetaCommand::Quit);
+        assert_eq!("\\?".parse::<MetaCommand>().unwrap(), MetaCommand::Help);
+
+        assert!("\\use a b".parse::<MetaCommand>().is_err());
+        assert!("\\quit now".parse::<MetaCommand>().is_err());
+        assert!("\\drop".parse::<MetaCommand>().is_err());
+    }
+
+    #[test]
+    fn test_word_start() {
+        assert_eq!(word_start(""), 0);
+        assert_eq!(word_start("\\us"), 0);
+        assert_eq!(word_start("SELECT * FROM analytics.ev"), 14);
+        assert_eq!(word_start("SELECT * FROM (ev"), 15);
+        assert_eq!(word_start("SELECT 'é' FROM e"), 17);
+    }
+
+    #[test]
+    fn test_starts_with_ignore_case() {
+        assert!(starts_with_ignore_case("events", "EV"));
+        assert!(starts_with_ignore_case("events", ""));
+        assert!(!starts_with_ignore_case("ev", "events"));
+        assert!(!starts_with_ignore_case("événements", "e"));
+    }
+}
@@ -0,0 +1,104 @@
+//! Ctrl-C and SIGTERM handling
+//!
+//! The first signal asks the running command to stop: `apply` stops the queries in
+//! flight with `StopQueryExecution` and does not start the remaining statements.
+//! A second signal exits immediately with the exit code 130.
+//!
+//! In `athena shell`, a signal only stops the running query, see [`crate::shell`].
+
+use log::warn;
+use tokio::{sync::watch, task::JoinHandle};
+
+/// Exit code when the process is interrupted by a signal
+pub const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+/// Receives the stop request of the first signal
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Listen to Ctrl-C and SIGTERM from now on
+    pub fn listen() -> Self {
+        let (tx, rx) = watch::channel(false);
+
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            warn!("Stopping the queries in flight, press Ctrl-C again to exit immediately");
+            let _ = tx.send(true);
+
+            wait_for_signal().await;
+            warn!("Exiting immediately, the queries in flight may still be running");
+            std::process::exit(i32::from(INTERRUPTED_EXIT_CODE));
+        });
+
+        Self { rx }
+    }
+
+    /// Listen to the first Ctrl-C or SIGTERM only, until the returned task is aborted.
+    /// `athena shell` stops the running query and goes back to the prompt
+    pub fn listen_once() -> (Self, JoinHandle<()>) {
+        let (tx, rx) = watch::channel(false);
+
+        let task = tokio::spawn(async move {
+            wait_for_signal().await;
+            let _ = tx.send(true);
+        });
+
+        (Self { rx }, task)
+    }
+
+    /// Whether a stop was requested
+    pub fn is_requested(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Wait until a stop is requested
+    pub async fn requested(&mut self) {
+        if self.rx.wait_for(|requested| *requested).await.is_err() {
+            // The listener is gone, no stop will ever be requested
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = sigterm.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (tx, rx) = watch::channel(false);
+        let mut shutdown = Shutdown { rx };
+        let other = shutdown.clone();
+        assert!(!shutdown.is_requested());
+
+        tx.send(true).unwrap();
+        shutdown.requested().await;
+        assert!(shutdown.is_requested());
+        assert!(other.is_requested());
+    }
+}
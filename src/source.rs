@@ -0,0 +1,159 @@
+//! Source templates of the rendered SQL
+//!
+//! To tell which template a statement comes from, every template is wrapped with
+//! markers before rendering:
+//!
+//! ```text
+//! /*athena-source-begin:base/table_1.sql*/ ...template... /*athena-source-end*/
+//! ```
+//!
+//! The markers are removed from the rendered SQL by [`SourceMap::extract`], so the
+//! SQL is exactly the same as without them. The begin marker goes after the leading
+//! `{% import %}` tags. Templates that define macros or extend another template are
+//! not wrapped, their content is not rendered as is.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+const BEGIN_MARKER: &str = "/*athena-source-begin:";
+const END_MARKER: &str = "/*athena-source-end*/";
+
+#[allow(clippy::expect_used)]
+static MARKER_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"/\*athena-source-begin:([^*]*)\*/|/\*athena-source-end\*/")
+        .expect("invalid regex pattern")
+});
+
+#[allow(clippy::expect_used)]
+static NOT_WRAPPED_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches: {% macro ... %} or {% extends ... %}
+    Regex::new(r"\{%-?\s*(?:macro|extends)\s").expect("invalid regex pattern")
+});
+
+#[allow(clippy::expect_used)]
+static IMPORTS_PATTERN: Lazy<Regex> = Lazy::new(|| {
+    // Matches the leading {% import ... %} tags and {# comments #}, they must stay first
+    Regex::new(r"^(?:\s*(?:\{%-?\s*import\s[^%]*%\}|\{#(?s:.*?)#\}))*")
+        .expect("invalid regex pattern")
+});
+
+/// Wrap the content of a template with the source markers
+pub fn wrap(name: &str, content: &str) -> String {
+    if NOT_WRAPPED_PATTERN.is_match(content) {
+        return content.to_string();
+    }
+
+    let imports = IMPORTS_PATTERN.find(content).map_or(0, |m| m.end());
+    format!(
+        "{}{}{}*/{}{}",
+        &content[..imports],
+        BEGIN_MARKER,
+        name,
+        &content[imports..],
+        END_MARKER
+    )
+}
+
+/// Template names by byte offset in the rendered SQL
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Start offset and template name, sorted by offset
+    spans: Vec<(usize, Option<String>)>,
+}
+
+impl SourceMap {
+    /// Remove the source markers from the rendered SQL
+    pub fn extract(rendered: &str) -> (String, Self) {
+        let mut sql = String::with_capacity(rendered.len());
+        let mut spans = vec![];
+        let mut stack: Vec<String> = vec![];
+        let mut last = 0;
+
+        for caps in MARKER_PATTERN.captures_iter(rendered) {
+            let Some(marker) = caps.get(0) else {
+                continue;
+            };
+            sql.push_str(&rendered[last..marker.start()]);
+            last = marker.end();
+
+            match caps.get(1) {
+                Some(name) => stack.push(name.as_str().to_string()),
+                None => {
+                    stack.pop();
+                }
+            }
+            spans.push((sql.len(), stack.last().cloned()));
+        }
+        sql.push_str(&rendered[last..]);
+
+        (sql, Self { spans })
+    }
+
+    /// Shift the offsets after `count` bytes were removed from the start of the SQL
+    pub fn trim_start(mut self, count: usize) -> Self {
+        for (offset, _) in &mut self.spans {
+            *offset = offset.saturating_sub(count);
+        }
+        self
+    }
+
+    /// The template rendered at a byte offset of the SQL
+    pub fn source_at(&self, offset: usize) -> Option<&str> {
+        self.spans
+            .iter()
+            .take_while(|(start, _)| *start <= offset)
+            .last()
+            .and_then(|(_, name)| name.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap("a.sql", "SELECT 1;"),
+            "/*athena-source-begin:a.sql*/SELECT 1;/*athena-source-end*/"
+        );
+        let macros = "{% macro m() %}1{% endmacro m %}";
+        assert_eq!(wrap("macros.sql", macros), macros);
+        assert_eq!(
+            wrap("b.sql", "{% import \"m.sql\" as m %}\n{{ m::f() }}"),
+            "{% import \"m.sql\" as m %}/*athena-source-begin:b.sql*/\n{{ m::f() }}/*athena-source-end*/"
+        );
+        let child = "{% extends \"base.sql\" %}";
+        assert_eq!(wrap("child.sql", child), child);
+    }
+
+    #[test]
+    fn test_extract() {
+        let rendered = format!(
+            "{}\n{}\nSELECT 1;{}\nSELECT 2;{}",
+            wrap("index.sql", "").trim_end_matches(END_MARKER),
+            wrap("a.sql", "").trim_end_matches(END_MARKER),
+            END_MARKER,
+            END_MARKER,
+        );
+        let (sql, sources) = SourceMap::extract(&rendered);
+
+        assert_eq!(sql, "\n\nSELECT 1;\nSELECT 2;");
+        assert_eq!(sources.source_at(0), Some("index.sql"));
+        assert_eq!(
+            sources.source_at(sql.find("SELECT 1").unwrap()),
+            Some("a.sql")
+        );
+        assert_eq!(
+            sources.source_at(sql.find("SELECT 2").unwrap()),
+            Some("index.sql")
+        );
+
+        let sources = sources.trim_start(2);
+        assert_eq!(sources.source_at(0), Some("a.sql"));
+
+        let (sql, sources) = SourceMap::extract("SELECT 1");
+        assert_eq!(sql, "SELECT 1");
+        assert_eq!(sources.source_at(0), None);
+    }
+}
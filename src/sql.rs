@@ -0,0 +1,509 @@
+//! SQL statement helpers
+//!
+//! This module provides functionality to:
+//! - Tokenize SQL, aware of quoting and comments
+//! - Split the rendered SQL into statements
+//! - Detect the kind of a statement (`CREATE EXTERNAL TABLE`, `ALTER TABLE`, ...)
+//!
+//! # Splitting
+//!
+//! Statements are separated by `;`. A `;` inside a quoted string (`'...'`),
+//! a quoted identifier (`"..."` or `` `...` ``), a line comment (`-- ...`) or a block
+//! comment (`/* ... */`) does not end the statement:
+//!
+//! ```sql
+//! -- Database: db1
+//! CREATE EXTERNAL TABLE logs (line string)
+//! ROW FORMAT SERDE 'org.apache.hadoop.hive.serde2.RegexSerDe'
+//! WITH SERDEPROPERTIES ('input.regex' = '^([^;]*);(.*)$')
+//! LOCATION 's3://bucket/logs/';
+//! ```
+//!
+//! The comments before a statement stay attached to it, so directives such as
+//! `-- Database: db1` apply to the statement that follows them.
+//! Quotes are escaped by doubling them (`'it''s'`). Like Trino, a backslash is not an escape
+//! (`'C:\'`), except in the string literals of the `CREATE` and `ALTER` statements run by
+//! Hive, where it escapes the next character (`'^(\'[^\']*\')$'`). `CREATE VIEW` and
+//! `CREATE TABLE ... AS SELECT` run on Trino and keep its semantics.
+
+use anyhow::{bail, Result};
+
+/// Kind of a SQL token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Spaces, tabs and newlines
+    Whitespace,
+    /// `-- ...` up to the end of the line
+    LineComment,
+    /// `/* ... */`
+    BlockComment,
+    /// A quoted string or identifier, with its quote character
+    Quoted(char),
+    /// A keyword, an identifier or a number
+    Word,
+    /// The statement separator `;`
+    Semicolon,
+    /// Any other character
+    Symbol,
+}
+
+/// A SQL token and its position in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Byte offset of the token in the input
+    pub offset: usize,
+}
+
+impl Token<'_> {
+    /// Whitespace and comments
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self.kind,
+            TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment
+        )
+    }
+
+    pub fn is_comment(&self) -> bool {
+        matches!(self.kind, TokenKind::LineComment | TokenKind::BlockComment)
+    }
+}
+
+/// A SQL statement, without the trailing `;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    /// The statement with its leading comments
+    pub sql: String,
+    /// The leading comments, including the comment markers
+    pub comments: Vec<String>,
+    /// Byte offset of the statement in the whole SQL
+    pub offset: usize,
+    /// Byte offset of the statement body (after the leading comments) in `sql`
+    pub body_offset: usize,
+}
+
+impl Statement {
+    /// The statement without its leading comments
+    pub fn body(&self) -> &str {
+        &self.sql[self.body_offset..]
+    }
+}
+
+/// Split SQL into tokens
+pub fn tokenize(sql: &str) -> Result<Vec<Token<'_>>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = vec![];
+    let mut pos = 0;
+    // No word yet in the current statement
+    let mut first_word = true;
+    // The statement is run by Hive, a backslash escapes the next character in its strings
+    let mut hive = false;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+
+        let kind = match c {
+            b'-' if bytes.get(pos + 1) == Some(&b'-') => {
+                pos = sql[pos..].find('\n').map_or(bytes.len(), |i| pos + i);
+                TokenKind::LineComment
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                match sql[pos + 2..].find("*/") {
+                    Some(i) => pos += 2 + i + 2,
+                    None => bail!(
+                        "unterminated block comment starting at line {}",
+                        line_number(sql, start)
+                    ),
+                }
+                TokenKind::BlockComment
+            }
+            b'\'' | b'"' | b'`' => {
+                let backslash_escapes = hive && c == b'\'';
+                pos = end_of_quoted(bytes, pos, backslash_escapes).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "unterminated quote {} starting at line {}",
+                        c as char,
+                        line_number(sql, start)
+                    )
+                })?;
+                TokenKind::Quoted(c as char)
+            }
+            b';' => {
+                pos += 1;
+                first_word = true;
+                hive = false;
+                TokenKind::Semicolon
+            }
+            _ if c.is_ascii_whitespace() => {
+                while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                TokenKind::Whitespace
+            }
+            _ if is_word_byte(c) => {
+                while pos < bytes.len() && is_word_byte(bytes[pos]) {
+                    pos += 1;
+                }
+                let word = &sql[start..pos];
+                if first_word {
+                    first_word = false;
+                    hive =
+                        word.eq_ignore_ascii_case("CREATE") || word.eq_ignore_ascii_case("ALTER");
+                } else if word.eq_ignore_ascii_case("VIEW") || word.eq_ignore_ascii_case("AS") {
+                    hive = false;
+                }
+                TokenKind::Word
+            }
+            _ => {
+                // Keep multi-byte characters in one token
+                pos += sql[pos..].chars().next().map_or(1, char::len_utf8);
+                TokenKind::Symbol
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            text: &sql[start..pos],
+            offset: start,
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Split SQL into statements. Statements without any SQL (empty or only comments)
+/// are removed
+pub fn split_statements(sql: &str) -> Result<Vec<Statement>> {
+    let tokens = tokenize(sql)?;
+
+    Ok(tokens
+        .split(|t| t.kind == TokenKind::Semicolon)
+        .filter_map(|tokens| statement(sql, tokens))
+        .collect())
+}
+
+/// Build a statement from its tokens, `None` if there is no SQL in it
+fn statement(sql: &str, tokens: &[Token]) -> Option<Statement> {
+    let first = tokens.iter().find(|t| t.kind != TokenKind::Whitespace)?;
+    let body = tokens.iter().find(|t| !t.is_trivia())?;
+    let last = tokens
+        .iter()
+        .rev()
+        .find(|t| t.kind != TokenKind::Whitespace)?;
+
+    let comments = tokens
+        .iter()
+        .take_while(|t| t.is_trivia())
+        .filter(|t| t.is_comment())
+        .map(|t| t.text.to_string())
+        .collect();
+
+    Some(Statement {
+        sql: sql[first.offset..last.offset + last.text.len()].to_string(),
+        comments,
+        offset: first.offset,
+        body_offset: body.offset - first.offset,
+    })
+}
+
+/// Whether the SQL ends with a `;`, outside of quotes and comments.
+/// An unterminated quote or block comment is not terminated
+pub fn is_terminated(sql: &str) -> bool {
+    tokenize(sql).is_ok_and(|tokens| {
+        tokens
+            .iter()
+            .rev()
+            .find(|t| !t.is_trivia())
+            .is_some_and(|t| t.kind == TokenKind::Semicolon)
+    })
+}
+
+/// Remove the comments at the beginning of a statement
+pub fn strip_leading_comments(sql: &str) -> &str {
+    match tokenize(sql) {
+        Ok(tokens) => tokens
+            .iter()
+            .find(|t| !t.is_trivia())
+            .map_or("", |t| &sql[t.offset..]),
+        // Not a valid SQL, only trim the spaces
+        Err(_) => sql.trim_start(),
+    }
+}
+
+/// Get the kind of a statement from its leading keywords, such as
+/// `CREATE EXTERNAL TABLE`, `ALTER TABLE`, `MSCK REPAIR TABLE` or `SELECT`
+pub fn statement_kind(sql: &str) -> String {
+    let words = strip_leading_comments(sql)
+        .split(|c: char| c.is_whitespace() || c == '(')
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_uppercase())
+        .collect::<Vec<_>>();
+
+    let Some(first) = words.first() else {
+        return String::new();
+    };
+
+    // Keywords that can be part of the kind after the first one
+    let modifiers: &[&str] = match first.as_str() {
+        "CREATE" => &[
+            "OR",
+            "REPLACE",
+            "EXTERNAL",
+            "PROTECTED",
+            "MULTI_DIALECT",
+            "TABLE",
+            "VIEW",
+            "DATABASE",
+            "SCHEMA",
+        ],
+        "ALTER" | "DROP" => &["TABLE", "VIEW", "DATABASE", "SCHEMA"],
+        "MSCK" => &["REPAIR", "TABLE"],
+        "INSERT" => &["INTO"],
+        "SHOW" => &[
+            "CREATE",
+            "TABLE",
+            "VIEW",
+            "TABLES",
+            "DATABASES",
+            "SCHEMAS",
+            "PARTITIONS",
+            "COLUMNS",
+            "VIEWS",
+        ],
+        "DESCRIBE" => &["VIEW"],
+        _ => &[],
+    };
+
+    let mut kind = vec![first.as_str()];
+    for word in words.iter().skip(1) {
+        if !modifiers.contains(&word.as_str()) {
+            break;
+        }
+        kind.push(word.as_str());
+
+        // The object type is the last keyword of a CREATE, ALTER or DROP
+        if matches!(word.as_str(), "TABLE" | "VIEW" | "DATABASE" | "SCHEMA")
+            && matches!(first.as_str(), "CREATE" | "ALTER" | "DROP")
+        {
+            break;
+        }
+    }
+
+    kind.join(" ")
+}
+
+/// Position after the closing quote of the quoted token starting at `start`.
+/// Like Trino, a quote is escaped by doubling it, and a backslash is a plain character
+/// unless `backslash_escapes`, like Hive
+fn end_of_quoted(bytes: &[u8], start: usize, backslash_escapes: bool) -> Option<usize> {
+    let quote = bytes[start];
+    let mut pos = start + 1;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' if backslash_escapes => pos += 2,
+            c if c == quote => {
+                // Doubled quote is an escaped quote
+                if bytes.get(pos + 1) == Some(&quote) {
+                    pos += 2;
+                } else {
+                    return Some(pos + 1);
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+
+    None
+}
+
+fn is_word_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || !c.is_ascii()
+}
+
+fn line_number(sql: &str, offset: usize) -> usize {
+    sql[..offset].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(sql: &str) -> Vec<String> {
+        split_statements(sql)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.sql)
+            .collect()
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let sql = "SELECT 1;\n\nSELECT 2 ;\n;\n  ";
+        assert_eq!(split(sql), vec!["SELECT 1", "SELECT 2"]);
+    }
+
+    #[test]
+    fn test_split_statements_quotes() {
+        let sql = "SELECT 'a;b', \"c;d\", `e;f`; SELECT 2";
+        assert_eq!(split(sql), vec!["SELECT 'a;b', \"c;d\", `e;f`", "SELECT 2"]);
+
+        // Escaped quotes
+        let sql = "SELECT 'it''s;'; SELECT \"a\"\";\"";
+        assert_eq!(split(sql), vec!["SELECT 'it''s;'", "SELECT \"a\"\";\""]);
+
+        // Backslash is not an escape
+        let sql = r#"SELECT 'C:\' AS p; SELECT "a\"; SELECT `a\`; SELECT 2"#;
+        assert_eq!(
+            split(sql),
+            vec![
+                r"SELECT 'C:\' AS p",
+                r#"SELECT "a\""#,
+                r"SELECT `a\`",
+                "SELECT 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_statements_comments() {
+        let sql = "SELECT 1 -- it's; a comment\n; /* a; 'b */ SELECT 2; -- only; a comment";
+        assert_eq!(
+            split(sql),
+            vec!["SELECT 1 -- it's; a comment", "/* a; 'b */ SELECT 2"]
+        );
+
+        // Comment markers in strings
+        let sql = "SELECT '--;', '/*;'; SELECT 2";
+        assert_eq!(split(sql), vec!["SELECT '--;', '/*;'", "SELECT 2"]);
+    }
+
+    #[test]
+    fn test_split_statements_serde_properties() {
+        let sql = indoc::indoc! { r#"
+            CREATE EXTERNAL TABLE logs (line string)
+            ROW FORMAT SERDE 'org.apache.hadoop.hive.serde2.RegexSerDe'
+            WITH SERDEPROPERTIES ('input.regex' = '^([^;]*);\\"(.*)$')
+            LOCATION 's3://bucket/a;b/';
+
+            MSCK REPAIR TABLE logs;
+        "# };
+
+        let statements = split(sql);
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].ends_with("LOCATION 's3://bucket/a;b/'"));
+        assert_eq!(statements[1], "MSCK REPAIR TABLE logs");
+    }
+
+    #[test]
+    fn test_split_statements_backslash() {
+        // Hive DDL, a backslash escapes a quote
+        let sql = indoc::indoc! { r"
+            CREATE EXTERNAL TABLE logs (line string)
+            ROW FORMAT SERDE 'org.apache.hadoop.hive.serde2.RegexSerDe'
+            WITH SERDEPROPERTIES ('input.regex' = '^(\'[^\']*\');(.*)$')
+            LOCATION 's3://bucket/logs/';
+            ALTER TABLE logs SET TBLPROPERTIES ('comment' = 'it\'s; a log');
+            SELECT 2
+        " };
+        let statements = split(sql);
+        assert_eq!(statements.len(), 3);
+        assert!(statements[0].contains(r"'^(\'[^\']*\');(.*)$'"));
+        assert!(statements[1].ends_with(r"'it\'s; a log')"));
+        assert_eq!(statements[2], "SELECT 2");
+
+        // Trino, a backslash is a plain character
+        let sql = r"SELECT 'C:\'; CREATE VIEW v AS SELECT 'C:\' AS p; SELECT 2";
+        assert_eq!(
+            split(sql),
+            vec![
+                r"SELECT 'C:\'",
+                r"CREATE VIEW v AS SELECT 'C:\' AS p",
+                "SELECT 2"
+            ]
+        );
+        let sql = r"CREATE TABLE t WITH (format = 'PARQUET') AS SELECT 'C:\' AS p; SELECT 2";
+        assert_eq!(split(sql).len(), 2);
+    }
+
+    #[test]
+    fn test_split_statements_leading_comments() {
+        let sql = "-- Database: db1\n/* note */\nCREATE VIEW v AS SELECT 1;\n\n/* Database: db2 */ SELECT 2";
+        let statements = split_statements(sql).unwrap();
+
+        assert_eq!(statements.len(), 2);
+        assert_eq!(
+            statements[0].comments,
+            vec!["-- Database: db1", "/* note */"]
+        );
+        assert_eq!(statements[0].body(), "CREATE VIEW v AS SELECT 1");
+        assert_eq!(statements[1].comments, vec!["/* Database: db2 */"]);
+        assert_eq!(statements[1].body(), "SELECT 2");
+    }
+
+    #[test]
+    fn test_split_statements_unterminated() {
+        let err = split_statements("SELECT 1;\nSELECT 'a;").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("unterminated quote ' starting at line 2"));
+
+        let err = split_statements("SELECT 1 /* a;").unwrap_err();
+        assert!(err.to_string().contains("unterminated block comment"));
+    }
+
+    #[test]
+    fn test_is_terminated() {
+        assert!(is_terminated("SELECT 1;"));
+        assert!(is_terminated("SELECT 1\nFROM t ; -- done\n"));
+        assert!(!is_terminated("SELECT 1"));
+        assert!(!is_terminated("SELECT ';"));
+        assert!(!is_terminated("SELECT 1 -- ;"));
+        assert!(!is_terminated("SELECT 1 /* ; "));
+        assert!(!is_terminated(""));
+    }
+
+    #[test]
+    fn test_strip_leading_comments() {
+        let sql = "-- Database: db1\n/* comment */\n  SELECT 1";
+        assert_eq!(strip_leading_comments(sql), "SELECT 1");
+
+        let sql = "-- only a comment";
+        assert_eq!(strip_leading_comments(sql), "");
+    }
+
+    #[test]
+    fn test_statement_kind() {
+        let cases = [
+            (
+                "CREATE EXTERNAL TABLE IF NOT EXISTS t (id int)",
+                "CREATE EXTERNAL TABLE",
+            ),
+            ("create table t as select 1", "CREATE TABLE"),
+            (
+                "CREATE OR REPLACE VIEW v AS SELECT 1",
+                "CREATE OR REPLACE VIEW",
+            ),
+            ("CREATE DATABASE IF NOT EXISTS db", "CREATE DATABASE"),
+            (
+                "ALTER TABLE t ADD IF NOT EXISTS PARTITION (a=1)",
+                "ALTER TABLE",
+            ),
+            ("MSCK REPAIR TABLE t", "MSCK REPAIR TABLE"),
+            ("DROP TABLE IF EXISTS t", "DROP TABLE"),
+            ("INSERT INTO t SELECT 1", "INSERT INTO"),
+            ("-- Database: db1\nSELECT * FROM t", "SELECT"),
+            (
+                "/* Database: db1 */ with a as (select 1) select * from a",
+                "WITH",
+            ),
+            ("", ""),
+        ];
+
+        for (sql, expected) in cases {
+            assert_eq!(statement_kind(sql), expected, "sql: {}", sql);
+        }
+    }
+}
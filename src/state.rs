@@ -0,0 +1,823 @@
+//! Ledger of the applied statements
+//!
+//! The idempotent statements that succeed, `CREATE ... IF NOT EXISTS` and
+//! `ALTER TABLE ... ADD [IF NOT EXISTS] PARTITION`, are recorded in `.athena/state.json`
+//! (`state.<env>.json` with `--env`), next to `athena.toml` (or in the context directory
+//! without one): the hash of its SQL and target database, the AWS account, region and
+//! workgroup it ran on, its query execution id and when it was applied. `apply` skips
+//! the statements whose hash is in the ledger for the same account, region and
+//! workgroup, unless `--force` is given. Other statements, such as `INSERT INTO` or
+//! `MSCK REPAIR TABLE`, run on every apply unless they have a `-- athena: once` directive.
+//!
+//! With `state_location = "s3://bucket/athena/state.json"` in `athena.toml`, the ledger
+//! is downloaded from S3 before `apply` and uploaded after it, so it is shared between
+//! machines. The local file is kept as a copy. A top-level `state_location` is also
+//! split by environment, `s3://bucket/athena/state.prd.json` with `--env prd`.
+//!
+//! ```bash
+//! athena state list
+//! athena state rm 3f2a9c
+//! ```
+
+use anyhow::{anyhow, bail, Context, Result};
+use aws_config::SdkConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::config::{self, AwsArgs, Settings, Source};
+use crate::plan::PlannedStatement;
+use crate::sql::{strip_leading_comments, tokenize, TokenKind};
+use crate::utils::{format_table, get_current_working_dir, sha256_hex};
+
+// Constants
+const STATE_DIR: &str = ".athena";
+const STATE_FILENAME: &str = "state.json";
+/// Workgroup of the statements without one
+const PRIMARY_WORKGROUP: &str = "primary";
+const STATE_VERSION: u32 = 1;
+/// Length of the hashes printed by `athena state list`
+const SHORT_HASH_LEN: usize = 12;
+
+/// A statement that was applied
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    /// Hash of the SQL and the target database, see [`statement_hash`]
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catalog: Option<String>,
+    pub database: Option<String>,
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// AWS account, region and workgroup the statement ran on. `None` in the entries
+    /// recorded before they were, such entries match any of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workgroup: Option<String>,
+    pub query_execution_id: Option<String>,
+    pub applied_at: DateTime<Utc>,
+}
+
+impl LedgerEntry {
+    /// Whether the entry records the statement of this hash on this workgroup and target
+    fn matches(&self, hash: &str, workgroup: &str, target: &Target) -> bool {
+        let same = |recorded: &Option<String>, current: Option<&str>| {
+            recorded.as_deref().is_none_or(|r| Some(r) == current)
+        };
+
+        self.hash == hash
+            && same(&self.account, target.account.as_deref())
+            && same(&self.region, target.region.as_deref())
+            && same(&self.workgroup, Some(workgroup))
+    }
+}
+
+/// AWS account and region the statements are applied to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    pub account: Option<String>,
+    pub region: Option<String>,
+}
+
+impl Target {
+    /// The account of the credentials, with `GetCallerIdentity`, and the region.
+    /// The account is unknown when the call fails, such as without `sts:GetCallerIdentity`
+    pub async fn resolve(aws: &SdkConfig) -> Self {
+        let identity = aws_sdk_sts::Client::new(aws)
+            .get_caller_identity()
+            .send()
+            .await
+            .context("could not get the AWS account of the credentials");
+        let account = match identity {
+            Ok(identity) => identity.account().map(str::to_string),
+            Err(e) => {
+                warn!("{:#}, the ledger entries are not scoped to an account", e);
+                None
+            }
+        };
+
+        Self {
+            account,
+            region: aws.region().map(|r| r.to_string()),
+        }
+    }
+}
+
+/// The statements that were applied, in the order they were applied
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Ledger {
+    pub version: u32,
+    pub statements: Vec<LedgerEntry>,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self {
+            version: STATE_VERSION,
+            statements: vec![],
+        }
+    }
+}
+
+impl Ledger {
+    fn from_json(content: &[u8]) -> Result<Self> {
+        let ledger: Self = serde_json::from_slice(content)?;
+        if ledger.version != STATE_VERSION {
+            bail!(
+                "unsupported state version {}, expected {}",
+                ledger.version,
+                STATE_VERSION
+            );
+        }
+        Ok(ledger)
+    }
+
+    fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Whether a statement with the same SQL and target database was applied,
+    /// on the same workgroup and target
+    pub fn contains(&self, statement: &PlannedStatement, target: &Target) -> bool {
+        let hash = statement_hash(statement);
+        self.statements
+            .iter()
+            .any(|e| e.matches(&hash, workgroup(statement), target))
+    }
+
+    /// Record a statement that succeeded
+    pub fn record(
+        &mut self,
+        statement: &PlannedStatement,
+        target: &Target,
+        query_execution_id: Option<String>,
+        applied_at: DateTime<Utc>,
+    ) {
+        self.insert(LedgerEntry {
+            hash: statement_hash(statement),
+            catalog: statement.context.catalog.clone(),
+            database: statement.context.database.clone(),
+            kind: statement.kind.clone(),
+            source: statement.source.clone(),
+            account: target.account.clone(),
+            region: target.region.clone(),
+            workgroup: Some(workgroup(statement).to_string()),
+            query_execution_id,
+            applied_at,
+        });
+    }
+
+    /// Record an entry, replacing the one of the same statement, workgroup and target
+    pub fn insert(&mut self, entry: LedgerEntry) {
+        let target = Target {
+            account: entry.account.clone(),
+            region: entry.region.clone(),
+        };
+        let workgroup = entry.workgroup.as_deref().unwrap_or(PRIMARY_WORKGROUP);
+        self.statements
+            .retain(|e| !e.matches(&entry.hash, workgroup, &target));
+        self.statements.push(entry);
+    }
+
+    /// Remove the statements whose hash starts with one of the prefixes.
+    /// Every prefix must match exactly one statement hash
+    pub fn remove(&mut self, prefixes: &[String]) -> Result<Vec<LedgerEntry>> {
+        let mut hashes = vec![];
+        for prefix in prefixes {
+            let mut matches = self
+                .statements
+                .iter()
+                .map(|e| &e.hash)
+                .filter(|hash| hash.starts_with(prefix.as_str()))
+                .collect::<Vec<_>>();
+            matches.dedup();
+            match matches.as_slice() {
+                [hash] => hashes.push(hash.to_string()),
+                [] => bail!("no statement matches `{}`", prefix),
+                _ => bail!(
+                    "`{}` matches {} statements, use a longer prefix",
+                    prefix,
+                    matches.len()
+                ),
+            }
+        }
+
+        let (removed, kept) = self
+            .statements
+            .drain(..)
+            .partition(|e| hashes.contains(&e.hash));
+        self.statements = kept;
+        Ok(removed)
+    }
+}
+
+/// Hash of the SQL of a statement and its target database.
+/// Leading comments and directives do not change the hash
+pub fn statement_hash(statement: &PlannedStatement) -> String {
+    let context = &statement.context;
+    sha256_hex(
+        format!(
+            "{}\n{}\n{}",
+            context.catalog.as_deref().unwrap_or_default(),
+            context.database.as_deref().unwrap_or_default(),
+            strip_leading_comments(&statement.sql).trim()
+        )
+        .as_bytes(),
+    )
+}
+
+/// Workgroup of a statement, `primary` by default
+pub fn workgroup(statement: &PlannedStatement) -> &str {
+    statement
+        .context
+        .workgroup
+        .as_deref()
+        .unwrap_or(PRIMARY_WORKGROUP)
+}
+
+/// Ledger file name of an environment: `state.json`, or `state.<env>.json`
+fn state_filename(env: Option<&str>) -> String {
+    match env {
+        Some(env) => format!("state.{}.json", env),
+        None => STATE_FILENAME.to_string(),
+    }
+}
+
+/// Whether a statement is recorded in the ledger once applied: an idempotent statement,
+/// or one with the `-- athena: once` directive
+pub fn is_recorded(statement: &PlannedStatement) -> bool {
+    statement.once || is_idempotent(&statement.sql)
+}
+
+/// `CREATE ... IF NOT EXISTS` or `ALTER TABLE ... ADD [IF NOT EXISTS] PARTITION`
+fn is_idempotent(sql: &str) -> bool {
+    let Ok(tokens) = tokenize(sql) else {
+        return false;
+    };
+    let words = tokens
+        .iter()
+        .filter(|t| !t.is_trivia())
+        .map(|t| match t.kind {
+            TokenKind::Word => t.text.to_uppercase(),
+            _ => String::new(),
+        })
+        .collect::<Vec<_>>();
+    let if_not_exists = |i: usize| {
+        words
+            .get(i..i + 3)
+            .is_some_and(|w| w == ["IF", "NOT", "EXISTS"])
+    };
+
+    match words.first().map(String::as_str) {
+        // `CREATE [EXTERNAL] TABLE IF NOT EXISTS`, `CREATE DATABASE IF NOT EXISTS`
+        Some("CREATE") => words
+            .iter()
+            .position(|w| matches!(w.as_str(), "TABLE" | "DATABASE" | "SCHEMA"))
+            .is_some_and(|i| {
+                words[1..i]
+                    .iter()
+                    .all(|w| matches!(w.as_str(), "EXTERNAL" | "PROTECTED" | "MULTI_DIALECT"))
+                    && if_not_exists(i + 1)
+            }),
+        Some("ALTER") if words.get(1).is_some_and(|w| w == "TABLE") => {
+            words.iter().position(|w| w == "ADD").is_some_and(|i| {
+                let i = if if_not_exists(i + 1) { i + 4 } else { i + 1 };
+                words.get(i).is_some_and(|w| w == "PARTITION")
+            })
+        }
+        _ => false,
+    }
+}
+
+/// An S3 object, such as `s3://bucket/athena/state.json`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Location {
+    /// Parse an S3 URI, a URI ending with `/` is a prefix for `filename`
+    pub fn parse(uri: &str, filename: &str) -> Result<Self> {
+        let path = uri
+            .strip_prefix("s3://")
+            .ok_or_else(|| anyhow!("invalid S3 location `{}`, expected s3://bucket/key", uri))?;
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            bail!("invalid S3 location `{}`, expected s3://bucket/key", uri);
+        }
+
+        let key = match key {
+            "" => filename.to_string(),
+            key if key.ends_with('/') => format!("{}{}", key, filename),
+            key => key.to_string(),
+        };
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            key,
+        })
+    }
+}
+
+impl fmt::Display for S3Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "s3://{}/{}", self.bucket, self.key)
+    }
+}
+
+/// Directory of the project: the one of `athena.toml`, or the context directory
+pub fn project_dir(settings: &Settings, context: Option<&Path>) -> Result<PathBuf> {
+    match settings.config_path.as_deref().and_then(Path::parent) {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => get_current_working_dir(context.map(Path::to_path_buf)),
+    }
+}
+
+/// `.athena` directory of the project, see [`project_dir`]
+pub fn state_dir(settings: &Settings, context: Option<&Path>) -> Result<PathBuf> {
+    Ok(project_dir(settings, context)?.join(STATE_DIR))
+}
+
+/// Where the ledger is stored
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    /// `.athena` directory of the project
+    pub dir: PathBuf,
+    /// Local file
+    pub path: PathBuf,
+    /// Copy shared in S3
+    pub remote: Option<S3Location>,
+}
+
+impl StateStore {
+    /// The ledger of the project, see [`state_dir`]
+    pub fn resolve(context: Option<&Path>, env: Option<&str>) -> Result<Self> {
+        let settings = config::resolve(context, env, &AwsArgs::default())?;
+        Self::from_settings(&settings, context)
+    }
+
+    /// The same ledger file in another `.athena` directory
+    pub fn with_dir(self, dir: PathBuf) -> Self {
+        let path = match self.path.file_name() {
+            Some(filename) => dir.join(filename),
+            None => dir.join(STATE_FILENAME),
+        };
+        Self { dir, path, ..self }
+    }
+
+    pub fn from_settings(settings: &Settings, context: Option<&Path>) -> Result<Self> {
+        let dir = state_dir(settings, context)?;
+        let filename = state_filename(settings.env.as_deref());
+
+        let remote = match &settings.state_location {
+            Some(location) => {
+                let mut remote = S3Location::parse(&location.value, &filename)?;
+                // A top-level ledger file is split by environment, like the local one
+                let top_level = matches!(location.source, Source::Config { section: None, .. });
+                if let (true, Some(env)) = (top_level, settings.env.as_deref()) {
+                    if !remote.key.ends_with(&filename) {
+                        remote.key = match remote.key.strip_suffix(".json") {
+                            Some(stem) => format!("{}.{}.json", stem, env),
+                            None => format!("{}.{}", remote.key, env),
+                        };
+                    }
+                }
+                Some(remote)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            path: dir.join(filename),
+            dir,
+            remote,
+        })
+    }
+
+    /// Read the ledger, from S3 when it is shared. A missing ledger is empty
+    pub async fn load(&self, aws: &SdkConfig) -> Result<Ledger> {
+        let Some(remote) = &self.remote else {
+            return self.read_local();
+        };
+
+        let client = aws_sdk_s3::Client::new(aws);
+        let ledger = match client
+            .get_object()
+            .bucket(&remote.bucket)
+            .key(&remote.key)
+            .send()
+            .await
+        {
+            Ok(output) => {
+                let content = output
+                    .body
+                    .collect()
+                    .await
+                    .with_context(|| format!("could not download the state from {}", remote))?
+                    .into_bytes();
+                Ledger::from_json(&content)
+                    .with_context(|| format!("could not parse the state {}", remote))?
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ledger::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("could not download the state from {}", remote))
+            }
+        };
+
+        self.save(&ledger)?;
+        Ok(ledger)
+    }
+
+    /// Read the local file
+    pub fn read_local(&self) -> Result<Ledger> {
+        if !self.path.exists() {
+            return Ok(Ledger::default());
+        }
+
+        let content = fs::read(&self.path)
+            .with_context(|| format!("could not read the state {}", self.path.display()))?;
+        Ledger::from_json(&content)
+            .with_context(|| format!("could not parse the state {}", self.path.display()))
+    }
+
+    /// Write the local file
+    pub fn save(&self, ledger: &Ledger) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("could not create {}", dir.display()))?;
+        }
+
+        fs::write(&self.path, ledger.to_json()?)
+            .with_context(|| format!("could not write the state {}", self.path.display()))
+    }
+
+    /// Upload the ledger to S3 when it is shared
+    pub async fn upload(&self, ledger: &Ledger, aws: &SdkConfig) -> Result<()> {
+        let Some(remote) = &self.remote else {
+            return Ok(());
+        };
+
+        aws_sdk_s3::Client::new(aws)
+            .put_object()
+            .bucket(&remote.bucket)
+            .key(&remote.key)
+            .content_type("application/json")
+            .body(ByteStream::from(ledger.to_json()?.into_bytes()))
+            .send()
+            .await
+            .with_context(|| format!("could not upload the state to {}", remote))?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for StateStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.remote {
+            Some(remote) => write!(f, "{}", remote),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum State {
+    /// List the statements recorded as applied
+    List(List),
+    /// Remove statements from the ledger, so that the next apply runs them again
+    Rm(Rm),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct List {
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Rm {
+    /// Hashes of the statements, or their first characters as printed by `athena state list`
+    #[arg(required_unless_present = "all")]
+    pub hashes: Vec<String>,
+
+    /// Remove every statement
+    #[arg(long, conflicts_with = "hashes")]
+    pub all: bool,
+
+    /// Change the context current working dir
+    #[arg(long, short)]
+    pub context: Option<PathBuf>,
+
+    /// Environment defined in athena.toml, such as `prd`
+    #[arg(long, short)]
+    pub env: Option<String>,
+}
+
+pub async fn call(args: State) -> Result<()> {
+    match args {
+        State::List(args) => list(&args).await,
+        State::Rm(args) => rm(&args).await,
+    }
+}
+
+/// The ledger store and the ledger, with the AWS configuration when it is shared in S3
+async fn open(
+    context: Option<&Path>,
+    env: Option<&str>,
+) -> Result<(StateStore, Ledger, Option<SdkConfig>)> {
+    let settings = config::resolve(context, env, &AwsArgs::default())?;
+    let store = StateStore::from_settings(&settings, context)?;
+
+    match store.remote {
+        Some(_) => {
+            let aws = config::load_aws_config(&settings).await;
+            let ledger = store.load(&aws).await?;
+            Ok((store, ledger, Some(aws)))
+        }
+        None => {
+            let ledger = store.read_local()?;
+            Ok((store, ledger, None))
+        }
+    }
+}
+
+async fn list(args: &List) -> Result<()> {
+    let (store, ledger, _) = open(args.context.as_deref(), args.env.as_deref()).await?;
+
+    if ledger.statements.is_empty() {
+        println!("No statement applied yet ({})", store);
+        return Ok(());
+    }
+
+    let rows = ledger
+        .statements
+        .iter()
+        .map(|e| {
+            vec![
+                e.hash.chars().take(SHORT_HASH_LEN).collect(),
+                match (&e.catalog, &e.database) {
+                    (Some(catalog), Some(database)) => format!("{}.{}", catalog, database),
+                    (_, Some(database)) => database.clone(),
+                    (_, None) => "(default)".to_string(),
+                },
+                match (&e.account, &e.region, &e.workgroup) {
+                    (None, None, None) => "-".to_string(),
+                    (account, region, workgroup) => [account, region, workgroup]
+                        .map(|v| v.as_deref().unwrap_or("?"))
+                        .join("/"),
+                },
+                e.kind.clone(),
+                e.source.clone().unwrap_or_else(|| "-".to_string()),
+                e.query_execution_id
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+                e.applied_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    println!("State: {}, {} statement(s)", store, rows.len());
+    println!();
+    print!(
+        "{}",
+        format_table(
+            &[
+                "HASH",
+                "DATABASE",
+                "TARGET",
+                "KIND",
+                "SOURCE",
+                "QUERY EXECUTION ID",
+                "APPLIED AT"
+            ],
+            &rows
+        )
+    );
+
+    Ok(())
+}
+
+async fn rm(args: &Rm) -> Result<()> {
+    let (store, mut ledger, aws) = open(args.context.as_deref(), args.env.as_deref()).await?;
+
+    let removed = match args.all {
+        true => std::mem::take(&mut ledger.statements),
+        false => ledger.remove(&args.hashes)?,
+    };
+
+    store.save(&ledger)?;
+    if let Some(aws) = &aws {
+        store.upload(&ledger, aws).await?;
+    }
+
+    println!(
+        "Removed {} statement(s) from {}, they will run again on the next apply",
+        removed.len(),
+        store
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Sourced;
+    use crate::plan::ExecutionContext;
+
+    fn target(account: &str) -> Target {
+        Target {
+            account: Some(account.to_string()),
+            region: Some("us-east-1".to_string()),
+        }
+    }
+
+    fn statement(sql: &str, database: Option<&str>) -> PlannedStatement {
+        PlannedStatement {
+            index: 1,
+            kind: crate::sql::statement_kind(sql),
+            sql: sql.to_string(),
+            context: ExecutionContext {
+                database: database.map(str::to_string),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_statement_hash() {
+        let create = statement("CREATE DATABASE db", None);
+        assert_eq!(
+            statement_hash(&create),
+            statement_hash(&statement("-- comment\nCREATE DATABASE db\n", None))
+        );
+        assert_ne!(
+            statement_hash(&create),
+            statement_hash(&statement("CREATE DATABASE db", Some("db")))
+        );
+
+        assert!(!is_recorded(&create));
+        assert!(!is_recorded(&statement("SELECT 1", None)));
+        assert!(!is_recorded(&statement("SHOW TABLES", None)));
+    }
+
+    #[test]
+    fn test_is_recorded() {
+        for sql in [
+            "CREATE DATABASE IF NOT EXISTS db",
+            "-- comment\ncreate external table if not exists `t` (id string) LOCATION 's3://b/t/'",
+            "CREATE TABLE IF NOT EXISTS t WITH (format = 'PARQUET') AS SELECT 1 AS id",
+            "ALTER TABLE db.t ADD PARTITION (dt = '1') LOCATION 's3://b/t/1/'",
+            "ALTER TABLE t ADD IF NOT EXISTS PARTITION (dt = '1')",
+        ] {
+            assert!(is_recorded(&statement(sql, None)), "{}", sql);
+        }
+
+        // Run on every apply
+        for sql in [
+            "CREATE TABLE t (id int)",
+            "CREATE TABLE t AS SELECT 1 AS id",
+            "CREATE OR REPLACE VIEW v AS SELECT * FROM t WHERE NOT EXISTS (SELECT 1)",
+            "INSERT INTO t SELECT 1",
+            "MSCK REPAIR TABLE t",
+            "DROP TABLE IF EXISTS t",
+            "ALTER TABLE t ADD COLUMNS (name string)",
+            "ALTER TABLE t DROP PARTITION (dt = '1')",
+        ] {
+            assert!(!is_recorded(&statement(sql, None)), "{}", sql);
+        }
+
+        // Unless they are applied once
+        let insert = PlannedStatement {
+            once: true,
+            ..statement("INSERT INTO t SELECT 1", None)
+        };
+        assert!(is_recorded(&insert));
+    }
+
+    #[test]
+    fn test_ledger() {
+        let mut ledger = Ledger::default();
+        let create = statement("CREATE DATABASE db", None);
+        let alter = statement("ALTER TABLE t ADD PARTITION (dt = '1')", Some("db"));
+        let now = Utc::now();
+
+        let target = target("123456789012");
+
+        ledger.record(&create, &target, Some("id-1".to_string()), now);
+        ledger.record(&create, &target, Some("id-2".to_string()), now);
+        ledger.record(&alter, &target, None, now);
+        assert_eq!(ledger.statements.len(), 2);
+        assert!(ledger.contains(&create, &target));
+        assert_eq!(
+            ledger.statements[0].query_execution_id.as_deref(),
+            Some("id-2")
+        );
+
+        let json = ledger.to_json().unwrap();
+        assert_eq!(Ledger::from_json(json.as_bytes()).unwrap(), ledger);
+
+        let hash = statement_hash(&alter);
+        assert!(ledger.remove(&["zzz".to_string()]).is_err());
+        let removed = ledger.remove(&[hash[..8].to_string()]).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(!ledger.contains(&alter, &target));
+        assert!(ledger.contains(&create, &target));
+    }
+
+    #[test]
+    fn test_ledger_target() {
+        let mut ledger = Ledger::default();
+        let create = statement("CREATE DATABASE db", None);
+        let (stg, prd) = (target("111111111111"), target("222222222222"));
+
+        // Applied on stg, not on prd nor on another workgroup
+        ledger.record(&create, &stg, Some("id-1".to_string()), Utc::now());
+        assert!(ledger.contains(&create, &stg));
+        assert!(!ledger.contains(&create, &prd));
+        let mut etl = create.clone();
+        etl.context.workgroup = Some("etl".to_string());
+        assert!(!ledger.contains(&etl, &stg));
+
+        // Both are kept
+        ledger.record(&create, &prd, Some("id-2".to_string()), Utc::now());
+        assert_eq!(ledger.statements.len(), 2);
+        assert!(ledger.contains(&create, &prd));
+        assert_eq!(ledger.remove(&[statement_hash(&create)]).unwrap().len(), 2);
+
+        // An entry recorded without a target matches any target
+        ledger.record(&create, &Target::default(), None, Utc::now());
+        ledger.statements[0].workgroup = None;
+        assert!(ledger.contains(&create, &prd));
+    }
+
+    #[test]
+    fn test_state_store_env() {
+        let mut settings = Settings {
+            config_path: Some(PathBuf::from("/project/athena.toml")),
+            ..Default::default()
+        };
+        let store = StateStore::from_settings(&settings, None).unwrap();
+        assert_eq!(store.path, PathBuf::from("/project/.athena/state.json"));
+
+        // Each environment has its own ledger
+        settings.env = Some("prd".to_string());
+        let store = StateStore::from_settings(&settings, None).unwrap();
+        assert_eq!(store.path, PathBuf::from("/project/.athena/state.prd.json"));
+        let store = store.with_dir(PathBuf::from("/other/.athena"));
+        assert_eq!(store.path, PathBuf::from("/other/.athena/state.prd.json"));
+
+        let location = |value: &str, section: Option<&str>| {
+            Some(Sourced {
+                value: value.to_string(),
+                source: Source::Config {
+                    path: PathBuf::from("/project/athena.toml"),
+                    section: section.map(str::to_string),
+                },
+            })
+        };
+        let remote = |settings: &Settings| {
+            let store = StateStore::from_settings(settings, None).unwrap();
+            store.remote.unwrap().to_string()
+        };
+        settings.state_location = location("s3://bucket/athena/", None);
+        assert_eq!(remote(&settings), "s3://bucket/athena/state.prd.json");
+        settings.state_location = location("s3://bucket/athena/state.json", None);
+        assert_eq!(remote(&settings), "s3://bucket/athena/state.prd.json");
+        // Set for the environment, used as is
+        settings.state_location = location("s3://bucket/athena/state.json", Some("prd"));
+        assert_eq!(remote(&settings), "s3://bucket/athena/state.json");
+    }
+
+    #[test]
+    fn test_s3_location() {
+        let location = S3Location::parse("s3://bucket/athena/state.json", STATE_FILENAME).unwrap();
+        assert_eq!(location.bucket, "bucket");
+        assert_eq!(location.key, "athena/state.json");
+
+        let location = S3Location::parse("s3://bucket/athena/", STATE_FILENAME).unwrap();
+        assert_eq!(location.to_string(), "s3://bucket/athena/state.json");
+        let location = S3Location::parse("s3://bucket", STATE_FILENAME).unwrap();
+        assert_eq!(location.key, "state.json");
+
+        assert!(S3Location::parse("bucket/key", STATE_FILENAME).is_err());
+        assert!(S3Location::parse("s3:///key", STATE_FILENAME).is_err());
+    }
+}
@@ -27,32 +27,80 @@
 //! PARTITION (date='2022-01-04')
 //! ```
 
+use anyhow::Context;
 use chrono::{Duration, NaiveDate};
 use log::debug;
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::fs;
+use std::path::Path;
 use tera::{from_value, to_value, Error, Tera, Value};
 use walkdir::WalkDir;
 
+use crate::source;
 use crate::utils::is_dir;
 
 // Constants
 const DATE_FORMAT: &str = "%Y-%m-%d";
 const SQL_FILE_EXTENSION: &str = "sql";
 
-/// Get Tera template, load the template from working dir
-pub fn get_tera(target_path: PathBuf, working_dir: PathBuf) -> anyhow::Result<Tera> {
-    let is_dir = is_dir(&target_path);
+/// Get Tera template, load the template from working dir.
+/// With `trace_sources`, the templates are wrapped with source markers, see [`crate::source`]
+pub fn get_tera(
+    target_path: &Path,
+    working_dir: &Path,
+    trace_sources: bool,
+) -> anyhow::Result<Tera> {
+    let is_dir = is_dir(target_path);
+
+    let mut tera = Tera::default();
+
+    // Scan working_dir and adding .sql file as template
+    let mut templates = template_files(working_dir)?;
+
+    if !is_dir {
+        let template_path = target_path.display().to_string();
+        let template_name = target_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| anyhow::anyhow!("could not get file name from target path"))?;
+        templates.push((template_path, template_name.to_string()));
+    }
+
+    debug!("Loaded: {:?}", templates);
+    if trace_sources {
+        let raw_templates = templates
+            .into_iter()
+            .map(|(template_path, template_name)| {
+                let content = fs::read_to_string(&template_path)
+                    .with_context(|| format!("could not read template {}", template_path))?;
+                let content = source::wrap(&template_name, &content);
+                Ok((template_name, content))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        tera.add_raw_templates(raw_templates)?;
+    } else {
+        tera.add_template_files(
+            templates
+                .into_iter()
+                .map(|(template_path, template_name)| (template_path, Some(template_name))),
+        )?;
+    }
+
+    // Register functions
+    tera.register_function("date_range", date_range);
+
+    Ok(tera)
+}
+
+/// List the `.sql` files in the working dir as `(path, template name)`
+pub fn template_files(working_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
     let working_dir_str = working_dir
         .to_str()
         .ok_or_else(|| anyhow::anyhow!("working directory path is not valid UTF-8"))?;
     let prefix = format!("{}/", working_dir_str);
 
-    let mut tera = Tera::default();
-
-    // Scan working_dir and adding .sql file as template
-    let templates = WalkDir::new(&working_dir)
+    let templates = WalkDir::new(working_dir)
         .into_iter()
         .filter_map(|e| e.ok())
         .filter_map(|e| {
@@ -65,37 +113,19 @@ pub fn get_tera(target_path: PathBuf, working_dir: PathBuf) -> anyhow::Result<Te
         .map(|e| {
             let template_path = e.path().display().to_string();
             let template_name = template_path.trim_start_matches(&prefix).to_string();
-            (template_path, Some(template_name))
+            (template_path, template_name)
         })
         .collect::<Vec<_>>();
 
-    debug!("Loaded: {:?}", templates);
-    tera.add_template_files(templates)?;
-
-    if !is_dir {
-        let template_path = target_path.display().to_string();
-        let template_name = target_path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .ok_or_else(|| anyhow::anyhow!("could not get file name from target path"))?;
-        tera.add_template_file(template_path, Some(template_name))?;
-    }
-
-    // Register functions
-    tera.register_function("date_range", date_range);
-
-    Ok(tera)
+    Ok(templates)
 }
 
 fn get_native_date(key: &str, input: Option<&Value>) -> tera::Result<NaiveDate> {
-    if input.is_none() {
+    let Some(input) = input else {
         return Err(Error::msg(format!(
             "Function `date_range` was called without a `{key}` argument",
         )));
-    }
-
-    // Safety: We just checked that input is Some above
-    let input = input.unwrap();
+    };
 
     match from_value::<String>(input.clone()) {
         Ok(val) => match NaiveDate::parse_from_str(&val, DATE_FORMAT) {
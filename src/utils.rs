@@ -3,9 +3,12 @@
 //! This module provides common utility functions used across the application:
 //! - Path manipulation and canonicalization
 //! - Directory checking
-//! - Pretty printing SQL output using `bat`
+//! - Pretty printing SQL output using `bat`, and tables
+//! - Hashing content
+//! - Run ids
 
 use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
 use std::{
     fs::canonicalize,
     path::{Path, PathBuf},
@@ -45,6 +48,44 @@ pub fn pretty_print(input: &[u8]) {
     }
 }
 
+/// Format rows as a table with aligned columns, one line per row
+pub fn format_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths = header.iter().map(|h| h.len()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header = header.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// SHA-256 of the input, as a lowercase hex string
+pub fn sha256_hex(input: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(input))
+}
+
+/// Id of a run, such as `20240101T120000Z-1a2b3c`: the start time and a random suffix
+pub fn new_run_id() -> String {
+    format!(
+        "{}-{:06x}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ"),
+        fastrand::u32(..0x0100_0000)
+    )
+}
+
 /// Check if a path is a directory
 pub fn is_dir(path: &Path) -> bool {
     path.is_dir()
@@ -101,8 +142,8 @@ mod tests {
         assert!(predicate_fn.eval(&get_full_path_str(&test_path).unwrap()));
 
         // Folder is not found
-        let test_path = dir.path().join("/not/exists/dir///");
-        assert!(get_full_path_str(&test_path).is_err());
+        let test_path = Path::new("/not/exists/dir///");
+        assert!(get_full_path_str(test_path).is_err());
 
         // Could not work with a file
         let path = Path::new("/tmp/dir/a.sql");
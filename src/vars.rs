@@ -0,0 +1,266 @@
+//! Template variables for the Tera render context
+//!
+//! This module collects the variables that are exposed to templates at render time.
+//! Variables can come from:
+//! - `_vars.toml`, `_vars.yaml`, `_vars.yml` or `_vars.json` files in every directory
+//!   from the context directory down to the target
+//! - `--vars-file <FILE>`: JSON (`.json`), YAML (`.yaml`/`.yml`) or TOML (`.toml`) files
+//! - `--var key=value`: single values on the command line
+//!
+//! Files are merged in that order, later files override earlier ones, so a `_vars`
+//! file in a deeper directory overrides the shallower ones. Maps are merged
+//! recursively, any other value is replaced. `--var` values are applied last so they
+//! always win.
+//!
+//! # Per-directory variables
+//!
+//! ```text
+//! .
+//! ├── _vars.toml      # team = "data"
+//! ├── base
+//! ├── prd
+//! │   ├── _vars.toml  # s3_bucket = "s3://prd"
+//! │   └── index.sql
+//! └── stg
+//!     ├── _vars.toml  # s3_bucket = "s3://stg"
+//!     └── index.sql
+//! ```
+//!
+//! `athena build prd` renders with `team` and `s3_bucket = "s3://prd"`.
+//!
+//! # Typed values
+//!
+//! `--var` values are parsed as JSON when possible, otherwise they are used as a plain string:
+//!
+//! ```bash
+//! athena build --var s3_bucket=s3://prd --var retention=30 --var 'tables=["a","b"]' ./prd
+//! ```
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::{fs, path::Path, path::PathBuf};
+use tera::{Map, Value};
+
+// Constants
+const DIR_VARS_FILENAMES: [&str; 4] = ["_vars.toml", "_vars.yaml", "_vars.yml", "_vars.json"];
+
+/// Variables passed to the Tera render context
+pub type Vars = Map<String, Value>;
+
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct VarArgs {
+    /// Set a template variable, can be repeated.
+    /// The value is parsed as JSON when possible (numbers, bools, lists, maps),
+    /// otherwise it is used as a string, e.g. `--var account_id=123 --var env=prd`
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, Value)>,
+
+    /// Load template variables from a JSON, YAML or TOML file, can be repeated.
+    /// Later files override earlier ones
+    #[arg(long = "vars-file", value_name = "FILE")]
+    pub vars_files: Vec<PathBuf>,
+}
+
+/// Load all variables from `--vars-file` and `--var`, in that order
+pub fn load(args: &VarArgs) -> Result<Vars> {
+    let mut vars = Vars::new();
+
+    for path in &args.vars_files {
+        merge(&mut vars, read_vars_file(path)?);
+    }
+
+    for (key, value) in &args.vars {
+        vars.insert(key.clone(), value.clone());
+    }
+
+    Ok(vars)
+}
+
+/// Load the `_vars.*` files of every directory from `working_dir` down to `target`.
+/// `target` can be a directory or a template file, it must be inside `working_dir`
+pub fn load_dir_vars(working_dir: &Path, target: &Path) -> Result<Vars> {
+    let target_dir = match target.parent() {
+        _ if target.is_dir() => target,
+        // A file of the current dir, such as `index.sql`
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => target,
+    };
+    let target_dir = fs::canonicalize(target_dir)
+        .with_context(|| format!("could not get full path: {:?}", target_dir))?;
+
+    let relative = match target_dir.strip_prefix(working_dir) {
+        Ok(relative) => relative,
+        // Target outside of the context dir, only the target dir itself
+        Err(_) => return read_dir_vars(&target_dir).map(Option::unwrap_or_default),
+    };
+
+    let mut vars = Vars::new();
+    let mut dir = working_dir.to_path_buf();
+    if let Some(layer) = read_dir_vars(&dir)? {
+        merge(&mut vars, layer);
+    }
+    for component in relative.components() {
+        dir.push(component);
+        if let Some(layer) = read_dir_vars(&dir)? {
+            merge(&mut vars, layer);
+        }
+    }
+
+    Ok(vars)
+}
+
+/// Read the `_vars.*` file of a directory, if any
+fn read_dir_vars(dir: &Path) -> Result<Option<Vars>> {
+    let files = DIR_VARS_FILENAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+
+    match files.as_slice() {
+        [] => Ok(None),
+        [path] => {
+            debug!("Loading vars file: {}", path.display());
+            read_vars_file(path).map(Some)
+        }
+        _ => bail!(
+            "only one vars file is allowed per directory, found: {:?}",
+            files
+        ),
+    }
+}
+
+/// Read a variables file, the format is detected from the file extension
+pub fn read_vars_file(path: &Path) -> Result<Vars> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("could not read vars file {}", path.display()))?;
+
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    let value: Value = match extension {
+        "json" => serde_json::from_str(&content)
+            .with_context(|| format!("could not parse JSON vars file {}", path.display()))?,
+        "yaml" | "yml" => serde_yaml::from_str(&content)
+            .with_context(|| format!("could not parse YAML vars file {}", path.display()))?,
+        "toml" => toml::from_str(&content)
+            .with_context(|| format!("could not parse TOML vars file {}", path.display()))?,
+        _ => bail!(
+            "unsupported vars file {}, expected .json, .yaml, .yml or .toml",
+            path.display()
+        ),
+    };
+
+    match value {
+        Value::Object(map) => Ok(map),
+        // An empty YAML document
+        Value::Null => Ok(Vars::new()),
+        _ => bail!(
+            "vars file {} must contain a map at the top-level",
+            path.display()
+        ),
+    }
+}
+
+/// Merge `src` into `dst`, maps are merged recursively and other values are replaced
+pub fn merge(dst: &mut Vars, src: Vars) {
+    for (key, value) in src {
+        match (dst.get_mut(&key), value) {
+            (Some(Value::Object(dst)), Value::Object(src)) => merge(dst, src),
+            (_, value) => {
+                dst.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parse a `key=value` pair from the command line
+fn parse_var(input: &str) -> Result<(String, Value), String> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or_else(|| format!("invalid KEY=VALUE: no `=` found in `{input}`"))?;
+
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("invalid KEY=VALUE: empty key in `{input}`"));
+    }
+
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+    Ok((key.to_string(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_var_typed_values() {
+        assert_eq!(parse_var("a=1").unwrap(), ("a".to_string(), json!(1)));
+        assert_eq!(parse_var("a=true").unwrap(), ("a".to_string(), json!(true)));
+        assert_eq!(
+            parse_var(r#"a=["x", "y"]"#).unwrap(),
+            ("a".to_string(), json!(["x", "y"]))
+        );
+        assert_eq!(
+            parse_var(r#"a={"b": 2}"#).unwrap(),
+            ("a".to_string(), json!({"b": 2}))
+        );
+        assert_eq!(
+            parse_var("a=s3://bucket/path").unwrap(),
+            ("a".to_string(), json!("s3://bucket/path"))
+        );
+        assert_eq!(parse_var("a=x=y").unwrap(), ("a".to_string(), json!("x=y")));
+        assert_eq!(parse_var("a=").unwrap(), ("a".to_string(), json!("")));
+    }
+
+    #[test]
+    fn test_parse_var_invalid() {
+        assert!(parse_var("a").is_err());
+        assert!(parse_var("=1").is_err());
+    }
+
+    #[test]
+    fn test_load_dir_vars() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("prd/sub")).unwrap();
+        fs::write(root.join("_vars.toml"), "a = 1\nb = 1\n[m]\nx = 1").unwrap();
+        fs::write(root.join("prd/_vars.yaml"), "b: 2\nm:\n  y: 2").unwrap();
+        fs::write(root.join("prd/sub/_vars.json"), r#"{"c": 3}"#).unwrap();
+        fs::write(root.join("prd/index.sql"), "SELECT 1").unwrap();
+
+        let expected = json!({"a": 1, "b": 2, "m": {"x": 1, "y": 2}});
+        let vars = load_dir_vars(&root, &root.join("prd")).unwrap();
+        assert_eq!(Value::Object(vars), expected);
+
+        // Same with a file as the target
+        let vars = load_dir_vars(&root, &root.join("prd/index.sql")).unwrap();
+        assert_eq!(Value::Object(vars), expected);
+
+        let vars = load_dir_vars(&root, &root.join("prd/sub")).unwrap();
+        assert_eq!(vars["c"], json!(3));
+
+        // Ambiguous vars files
+        fs::write(root.join("prd/_vars.toml"), "b = 3").unwrap();
+        assert!(load_dir_vars(&root, &root.join("prd")).is_err());
+    }
+
+    #[test]
+    fn test_merge_nested_maps() {
+        let mut dst = json!({"a": 1, "m": {"x": 1, "y": 2}})
+            .as_object()
+            .unwrap()
+            .clone();
+        let src = json!({"b": 2, "m": {"y": 3}}).as_object().unwrap().clone();
+        merge(&mut dst, src);
+
+        assert_eq!(
+            Value::Object(dst),
+            json!({"a": 1, "b": 2, "m": {"x": 1, "y": 3}})
+        );
+    }
+}
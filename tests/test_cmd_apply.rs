@@ -0,0 +1,319 @@
+use assert_cmd::prelude::*;
+use indoc::indoc;
+use predicates::prelude::*;
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::path::PathBuf;
+use std::process::Command;
+use tempfile::tempdir;
+
+macro_rules! setup_env {
+    () => {
+        // Set working dir to examples/
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let dir = dir.join("examples");
+        assert!(set_current_dir(&dir).is_ok());
+    };
+}
+
+/// $ athena apply --dry-run stg
+/// Print the plan without any AWS credentials
+#[test]
+#[serial]
+fn test_apply_dry_run() {
+    setup_env!();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg("stg")
+        .arg("--dry-run")
+        .arg("--no-pretty")
+        .arg("true")
+        .arg("--workgroup")
+        .arg("etl")
+        .env_remove("AWS_PROFILE")
+        .env_remove("AWS_ACCESS_KEY_ID")
+        .env_remove("AWS_SECRET_ACCESS_KEY")
+        .env("AWS_REGION", "us-east-1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Plan: 6 statement(s)"))
+        .stdout(predicate::str::is_match(r"2\s+CREATE EXTERNAL TABLE\s+db\s+etl").unwrap())
+        .stdout(predicate::str::is_match(r"5\s+CREATE VIEW\s+db1\s+etl").unwrap())
+        .stdout(predicate::str::contains("Submitting").count(0));
+}
+
+/// $ athena plan -o plan.json stg
+/// $ athena apply plan.json
+#[test]
+#[serial]
+fn test_apply_plan_file() {
+    setup_env!();
+
+    let dir = tempdir().unwrap();
+    let plan_path = dir.path().join("plan.json");
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("plan")
+        .arg("stg")
+        .arg("-o")
+        .arg(&plan_path)
+        .arg("--workgroup")
+        .arg("etl")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Plan: 6 statement(s)"))
+        .stdout(predicate::str::contains("Saved the plan to"));
+
+    // The saved plan is accepted as is
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(&plan_path)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"5\s+CREATE VIEW\s+db1\s+etl").unwrap());
+
+    // Modified plan is refused
+    let content = read_to_string(&plan_path).unwrap();
+    write(&plan_path, content.replace("CREATE VIEW", "DROP VIEW")).unwrap();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(&plan_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("the plan file was modified"));
+
+    // Expired plan is refused
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("plan")
+        .arg("stg")
+        .arg("-o")
+        .arg(&plan_path)
+        .arg("--expires-in")
+        .arg("0s")
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(&plan_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("the plan expired"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena apply --dry-run <broken template>
+/// Render errors and usage errors have their own exit codes
+#[test]
+#[serial]
+fn test_apply_exit_codes() {
+    let dir = tempdir().unwrap();
+    write(dir.path().join("index.sql"), "SELECT {{ undefined_var }};").unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--dry-run")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("could not render the SQL"));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--on-error")
+        .arg("retry")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--timeout")
+        .arg("soon")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--concurrency")
+        .arg("0")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--retry-max-attempts")
+        .arg("0")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--result-format")
+        .arg("xml")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--max-rows")
+        .arg("0")
+        .assert()
+        .code(2);
+
+    dir.close().unwrap();
+}
+
+/// $ athena apply --dry-run --timeout 90s
+/// The global timeout is shown in the plan, `-- athena: timeout=` overrides it
+#[test]
+#[serial]
+fn test_apply_dry_run_timeout() {
+    let dir = tempdir().unwrap();
+    write(
+        dir.path().join("index.sql"),
+        "SELECT 1;\n-- athena: timeout=10m\nSELECT 2;",
+    )
+    .unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--dry-run")
+        .arg("--timeout")
+        .arg("90s")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"1\s+SELECT.*1m 30s").unwrap())
+        .stdout(predicate::str::is_match(r"2\s+SELECT.*10m").unwrap());
+
+    dir.close().unwrap();
+}
+
+#[test]
+#[serial]
+fn test_apply_dry_run_dependencies() {
+    let dir = tempdir().unwrap();
+    write(
+        dir.path().join("index.sql"),
+        "CREATE VIEW v AS SELECT * FROM t;\nCREATE TABLE t (id int);",
+    )
+    .unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    // The view is created after the table it selects from
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(r"(?s)2\s+CREATE TABLE.*1\s+CREATE VIEW\s.*\s2\s").unwrap(),
+        );
+
+    // Unless it keeps the order of the templates
+    write(
+        dir.path().join("index.sql"),
+        "-- athena: keep_order\nCREATE VIEW v AS SELECT * FROM t;\nCREATE TABLE t (id int);",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::is_match(r"(?s)1\s+CREATE VIEW.*2\s+CREATE TABLE\s.*\s1\s").unwrap(),
+        );
+
+    write(
+        dir.path().join("index.sql"),
+        "CREATE VIEW a AS SELECT * FROM b;\nCREATE VIEW b AS SELECT * FROM a;",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg(".")
+        .arg("--dry-run")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "dependency cycle between statements #1 -> #2 -> #1",
+        ));
+
+    dir.close().unwrap();
+}
+
+/// $ athena apply --resume <run-id>
+/// A run is not resumed when a statement that succeeded changed
+#[test]
+#[serial]
+fn test_apply_resume_changed() {
+    let dir = tempdir().unwrap();
+    write(
+        dir.path().join("index.sql"),
+        "CREATE DATABASE db2;\nSELECT 2;",
+    )
+    .unwrap();
+    create_dir_all(dir.path().join(".athena/runs")).unwrap();
+    write(
+        dir.path().join(".athena/runs/run-1.json"),
+        indoc! { r#"
+            {
+              "version": 1,
+              "run_id": "run-1",
+              "started_at": "2024-01-01T12:00:00Z",
+              "updated_at": "2024-01-01T12:00:10Z",
+              "statements": [
+                {"index": 1, "hash": "0000", "kind": "CREATE DATABASE", "status": "SUCCEEDED", "query_execution_id": "id-1"},
+                {"index": 2, "hash": "0000", "kind": "SELECT", "status": "FAILED", "query_execution_id": "id-2"}
+              ]
+            }
+        "# },
+    )
+    .unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.env("AWS_REGION", "us-east-1")
+        .arg("apply")
+        .arg(".")
+        .arg("--resume")
+        .arg("run-1")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("cannot resume run run-1"))
+        .stderr(predicate::str::contains(
+            "statements that already ran changed: #1 (CREATE DATABASE)",
+        ));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.env("AWS_REGION", "us-east-1")
+        .arg("apply")
+        .arg(".")
+        .arg("--resume")
+        .arg("unknown")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "could not read the journal of run unknown",
+        ));
+
+    dir.close().unwrap();
+}
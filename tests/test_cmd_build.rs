@@ -3,7 +3,7 @@ use indoc::indoc;
 use predicates::prelude::*;
 use serial_test::serial;
 use std::env::set_current_dir;
-use std::fs::File;
+use std::fs::{create_dir_all, File};
 use std::io::{Read, Write};
 use std::process::Command;
 use tempfile::tempdir;
@@ -480,3 +480,159 @@ fn test_render_to_file_permission_denied() {
     // cleanup
     dir.close().unwrap();
 }
+
+#[test]
+#[serial]
+fn test_render_with_var_args() {
+    let template = indoc! { r#"
+        SELECT * FROM {{ env }}_{{ retention + 1 }}
+        {%- if enabled %} WHERE enabled{% endif %}
+        {%- for t in tables %} {{ t }}{% endfor %}
+    "# };
+    let expected = "SELECT * FROM prd_31 WHERE enabled a b";
+
+    // create a temporary directory
+    let dir = tempdir().unwrap();
+
+    // Create a file inside tempdir
+    let file_path = dir.path().join("index.sql");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "{}", &template).expect("could not write to temp file");
+
+    // Set working dir to tempdir
+    assert!(set_current_dir(&dir).is_ok());
+
+    // $ athena build . --var env=prd --var retention=30 ...
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg(".")
+        .arg("--no-pretty")
+        .arg("true")
+        .arg("--var")
+        .arg("env=prd")
+        .arg("--var")
+        .arg("retention=30")
+        .arg("--var")
+        .arg("enabled=true")
+        .arg("--var")
+        .arg(r#"tables=["a", "b"]"#)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(expected));
+
+    // cleanup
+    dir.close().unwrap();
+}
+
+#[test]
+#[serial]
+fn test_render_with_vars_files() {
+    let template = indoc! { r#"
+        {{ env }} {{ aws.account_id }} {{ aws.region }} {{ s3_bucket }}
+    "# };
+
+    // create a temporary directory
+    let dir = tempdir().unwrap();
+
+    let file_path = dir.path().join("index.sql");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "{}", &template).expect("could not write to temp file");
+
+    let file_path = dir.path().join("base.json");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(
+        file,
+        r#"{{"env": "base", "aws": {{"account_id": 111, "region": "us-east-1"}}}}"#
+    )
+    .expect("could not write to temp file");
+
+    let file_path = dir.path().join("prd.yaml");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "env: prd\naws:\n  account_id: 222").expect("could not write to temp file");
+
+    let file_path = dir.path().join("bucket.toml");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "s3_bucket = \"s3://prd\"").expect("could not write to temp file");
+
+    // Set working dir to tempdir
+    assert!(set_current_dir(&dir).is_ok());
+
+    // Later files override earlier ones, --var overrides all files
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg(".")
+        .arg("--no-pretty")
+        .arg("true")
+        .arg("--vars-file")
+        .arg("base.json")
+        .arg("--vars-file")
+        .arg("prd.yaml")
+        .arg("--vars-file")
+        .arg("bucket.toml")
+        .arg("--var")
+        .arg("s3_bucket=s3://override")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("prd 222 us-east-1 s3://override"));
+
+    // Unsupported format
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg(".")
+        .arg("--vars-file")
+        .arg("index.sql")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unsupported vars file"));
+
+    // cleanup
+    dir.close().unwrap();
+}
+
+#[test]
+#[serial]
+fn test_render_with_dir_vars_files() {
+    let template_table = indoc! { r#"
+        CREATE TABLE {{ team }}_table LOCATION '{{ s3_bucket }}/table';
+    "# };
+    let template_index = indoc! { r#"
+        {% include "base/table.sql" %}
+    "# };
+
+    // create a temporary directory
+    let dir = tempdir().unwrap();
+    create_dir_all(dir.path().join("base")).expect("could not create dir");
+    create_dir_all(dir.path().join("prd")).expect("could not create dir");
+
+    let mut file = File::create(dir.path().join("base/table.sql")).unwrap();
+    writeln!(file, "{}", &template_table).expect("could not write to temp file");
+
+    let mut file = File::create(dir.path().join("prd/index.sql")).unwrap();
+    writeln!(file, "{}", &template_index).expect("could not write to temp file");
+
+    // Defaults in the root, overridden by prd/
+    let mut file = File::create(dir.path().join("_vars.toml")).unwrap();
+    writeln!(file, "team = \"data\"\ns3_bucket = \"s3://default\"")
+        .expect("could not write to temp file");
+
+    let mut file = File::create(dir.path().join("prd/_vars.yaml")).unwrap();
+    writeln!(file, "s3_bucket: s3://prd").expect("could not write to temp file");
+
+    // Set working dir to tempdir
+    assert!(set_current_dir(&dir).is_ok());
+
+    // $ athena build prd
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg("prd")
+        .arg("--no-pretty")
+        .arg("true")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "CREATE TABLE data_table LOCATION 's3://prd/table';",
+        ));
+
+    // cleanup
+    dir.close().unwrap();
+}
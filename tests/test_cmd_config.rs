@@ -0,0 +1,187 @@
+use assert_cmd::prelude::*;
+use indoc::indoc;
+use predicates::prelude::*;
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::process::Command;
+use tempfile::tempdir;
+
+const CONFIG: &str = indoc! { r#"
+    region = "us-east-1"
+    workgroup = "primary"
+
+    [vars]
+    team = "data"
+
+    [env.prd]
+    profile = "prd"
+    target = "prd"
+
+    [env.prd.vars]
+    s3_bucket = "s3://prd"
+"# };
+
+/// Create <temp>/athena.toml and <temp>/prd/index.sql
+macro_rules! setup_project {
+    () => {{
+        let dir = tempdir().unwrap();
+
+        let file_path = dir.path().join("athena.toml");
+        let mut file = File::create(file_path).expect("could not create temp file");
+        writeln!(file, "{}", CONFIG).expect("could not write to temp file");
+
+        create_dir_all(dir.path().join("prd")).expect("could not create dir");
+        let file_path = dir.path().join("prd/index.sql");
+        let mut file = File::create(file_path).expect("could not create temp file");
+        writeln!(file, "SELECT '{{{{ team }}}}' FROM '{{{{ s3_bucket }}}}'")
+            .expect("could not write to temp file");
+
+        dir
+    }};
+}
+
+/// $ athena build --env prd
+/// The target path and variables come from athena.toml
+#[test]
+#[serial]
+fn test_build_with_env() {
+    let dir = setup_project!();
+    assert!(set_current_dir(dir.path().join("prd")).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg("--env")
+        .arg("prd")
+        .arg("--context")
+        .arg(dir.path())
+        .arg("--no-pretty")
+        .arg("true")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("SELECT 'data' FROM 's3://prd'"));
+
+    // --var overrides the config
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg("--env")
+        .arg("prd")
+        .arg("--context")
+        .arg(dir.path())
+        .arg("--no-pretty")
+        .arg("true")
+        .arg("--var")
+        .arg("s3_bucket=s3://other")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("FROM 's3://other'"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena build --env prd
+/// The variables of athena.toml override the `_vars` files
+#[test]
+#[serial]
+fn test_build_with_env_and_dir_vars() {
+    let dir = setup_project!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let file_path = dir.path().join("_vars.toml");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "team = \"ops\"\ns3_bucket = \"s3://default\"")
+        .expect("could not write to temp file");
+
+    // The `_vars` files override the top-level `[vars]`, the environment overrides them
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg("--env")
+        .arg("prd")
+        .arg("--no-pretty")
+        .arg("true")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("SELECT 'ops' FROM 's3://prd'"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena build prd
+/// A `_vars` file overrides the root defaults of athena.toml
+#[test]
+#[serial]
+fn test_build_with_root_vars_and_dir_vars() {
+    let dir = setup_project!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let file_path = dir.path().join("athena.toml");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "[vars]\nteam = \"data\"\ns3_bucket = \"s3://root\"")
+        .expect("could not write to temp file");
+
+    let file_path = dir.path().join("prd/_vars.toml");
+    let mut file = File::create(file_path).expect("could not create temp file");
+    writeln!(file, "s3_bucket = \"s3://prd\"").expect("could not write to temp file");
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("build")
+        .arg("prd")
+        .arg("--no-pretty")
+        .arg("true")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("SELECT 'data' FROM 's3://prd'"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena config show --env prd
+#[test]
+#[serial]
+fn test_config_show() {
+    let dir = setup_project!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("config")
+        .arg("show")
+        .arg("--env")
+        .arg("prd")
+        .env_remove("AWS_PROFILE")
+        .env("AWS_REGION", "eu-west-1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("athena.toml [env.prd]"))
+        .stdout(predicate::str::is_match(r"profile\s+= prd").unwrap())
+        .stdout(predicate::str::contains(
+            "eu-west-1  # environment variable AWS_REGION",
+        ))
+        .stdout(predicate::str::contains(r#"s3_bucket = "s3://prd""#));
+
+    // The selected environment wins over an exported AWS_PROFILE, with a warning
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("config")
+        .arg("show")
+        .arg("--env")
+        .arg("prd")
+        .env("AWS_PROFILE", "dev")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"profile\s+= prd").unwrap())
+        .stderr(predicate::str::contains(
+            "profile = dev from environment variable AWS_PROFILE is ignored",
+        ));
+
+    // Unknown environment
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("config")
+        .arg("show")
+        .arg("--env")
+        .arg("dev")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("environment `dev` not found"));
+
+    dir.close().unwrap();
+}
@@ -0,0 +1,159 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs::{read_to_string, write};
+use tempfile::tempdir;
+
+/// Journal of a run, see `athena apply --no-wait`
+const RUN_FILE: &str = r#"{
+  "version": 1,
+  "run_id": "20240101T120000Z-abc123",
+  "started_at": "2024-01-01T12:00:00Z",
+  "updated_at": "2024-01-01T12:00:01Z",
+  "statements": [
+    {
+      "index": 1,
+      "hash": "aaaa",
+      "kind": "CREATE TABLE",
+      "status": "SUCCEEDED",
+      "query_execution_id": "id-1"
+    },
+    {
+      "index": 2,
+      "hash": "bbbb",
+      "kind": "INSERT",
+      "status": "PENDING",
+      "query_execution_id": null
+    }
+  ]
+}
+"#;
+
+/// $ athena status|wait|cancel
+/// The query execution ids are required
+#[test]
+fn test_execution_ids_required() {
+    for command in ["status", "wait", "cancel"] {
+        let mut cmd = Command::cargo_bin("athena").unwrap();
+        cmd.arg(command)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("<QUERY_EXECUTION_ID>"));
+    }
+}
+
+/// $ athena history
+/// Invalid filters are usage errors
+#[test]
+fn test_history_usage() {
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("history")
+        .arg("--state")
+        .arg("succeeded,done")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("done"));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("history")
+        .arg("--since")
+        .arg("yesterday")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("expected a duration"));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("history").arg("--limit").arg("0").assert().code(2);
+}
+
+/// $ athena wait --run <journal>
+/// Nothing is running: the run is summarized without calling AWS
+#[test]
+fn test_wait_run_finished() {
+    let dir = tempdir().unwrap();
+    let run_file = dir.path().join("run.json");
+    write(&run_file, RUN_FILE).unwrap();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("wait")
+        .arg("--run")
+        .arg(&run_file)
+        .arg("--context")
+        .arg(dir.path())
+        .env("AWS_REGION", "us-east-1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "#1  SUCCEEDED    CREATE TABLE  id-1",
+        ))
+        .stdout(predicate::str::contains("#2  NOT STARTED  INSERT        -"))
+        .stderr(predicate::str::contains(
+            "athena apply --resume 20240101T120000Z-abc123",
+        ));
+
+    // Not waited for, the journal is unchanged
+    assert_eq!(read_to_string(&run_file).unwrap(), RUN_FILE);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("wait")
+        .arg("--run")
+        .arg(dir.path().join("missing.json"))
+        .env("AWS_REGION", "us-east-1")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("could not read"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena wait --run <journal> --env <env>
+/// The run is waited for with the environment it was applied with
+#[test]
+fn test_wait_run_env() {
+    let dir = tempdir().unwrap();
+    let run_file = dir.path().join("run.json");
+    write(
+        &run_file,
+        RUN_FILE.replace(
+            r#""run_id": "20240101T120000Z-abc123","#,
+            r#""run_id": "20240101T120000Z-abc123",
+  "env": "prd","#,
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("wait")
+        .arg("--run")
+        .arg(&run_file)
+        .arg("--env")
+        .arg("stg")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "run 20240101T120000Z-abc123 was applied with --env prd, not stg",
+        ));
+
+    dir.close().unwrap();
+}
+
+/// $ athena wait / athena apply --no-wait
+/// Either query execution ids or a run file, `--no-wait` cannot enforce `--timeout`
+#[test]
+fn test_wait_run_usage() {
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("wait")
+        .arg("id-1")
+        .arg("--run")
+        .arg("run.json")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("apply")
+        .arg("prd")
+        .arg("--no-wait")
+        .arg("--timeout")
+        .arg("1m")
+        .assert()
+        .code(2);
+}
@@ -0,0 +1,162 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use serial_test::serial;
+use sha2::{Digest, Sha256};
+use std::env::set_current_dir;
+use std::fs::{create_dir_all, write};
+use std::process::Command;
+use tempfile::tempdir;
+
+const V1: &str = "CREATE DATABASE {{ db }};";
+const V2: &str = "ALTER TABLE {{ db }}.events ADD COLUMNS (country string);";
+
+/// Create <temp>/athena.toml, <temp>/migrations/ and <temp>/.athena/migrations.json
+/// with V1 applied
+macro_rules! setup_migrations {
+    () => {{
+        let dir = tempdir().unwrap();
+
+        write(dir.path().join("athena.toml"), "[vars]\ndb = \"analytics\"\n").unwrap();
+        create_dir_all(dir.path().join("migrations")).unwrap();
+        write(dir.path().join("migrations/V0001__init.sql"), V1).unwrap();
+        write(dir.path().join("migrations/V0002__add_col.sql"), V2).unwrap();
+
+        let checksum = format!("{:x}", Sha256::digest(V1.as_bytes()));
+        create_dir_all(dir.path().join(".athena")).unwrap();
+        write(
+            dir.path().join(".athena/migrations.json"),
+            format!(
+                r#"{{"version": 1, "migrations": [{{"version": "1", "description": "init", "checksum": "{}", "applied_at": "2024-01-01T12:00:00Z"}}]}}"#,
+                checksum
+            ),
+        )
+        .unwrap();
+
+        dir
+    }};
+}
+
+/// $ athena migrate status
+#[test]
+#[serial]
+fn test_migrate_status() {
+    let dir = setup_migrations!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("migrate")
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 applied, 1 pending"))
+        .stdout(predicate::str::is_match(r"1\s+init\s+APPLIED\s+2024-01-01T12:00:00Z").unwrap())
+        .stdout(predicate::str::is_match(r"2\s+add col\s+PENDING\s+-").unwrap());
+
+    dir.close().unwrap();
+}
+
+/// $ athena migrate up --dry-run
+#[test]
+#[serial]
+fn test_migrate_up_dry_run() {
+    let dir = setup_migrations!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("migrate")
+        .arg("up")
+        .arg("--dry-run")
+        .arg("--no-pretty")
+        .env_remove("AWS_PROFILE")
+        .env("AWS_REGION", "us-east-1")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "ALTER TABLE analytics.events ADD COLUMNS",
+        ))
+        .stdout(predicate::str::contains("V2 (add col)"))
+        .stdout(predicate::str::contains("Plan: 1 statement(s)"))
+        .stdout(predicate::str::contains("CREATE DATABASE").count(0))
+        .stdout(predicate::str::contains("Submitting").count(0));
+
+    // Nothing up to V1
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("migrate")
+        .arg("up")
+        .arg("--dry-run")
+        .arg("--to")
+        .arg("1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Nothing to migrate"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena migrate status
+/// An applied migration was edited
+#[test]
+#[serial]
+fn test_migrate_changed() {
+    let dir = setup_migrations!();
+    write(
+        dir.path().join("migrations/V0001__init.sql"),
+        "CREATE DATABASE other;",
+    )
+    .unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("migrate")
+        .arg("status")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_match(r"1\s+init\s+CHANGED").unwrap())
+        .stderr(predicate::str::contains(
+            "1 applied migration(s) changed since: V1 (init)",
+        ));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("migrate")
+        .arg("up")
+        .arg("--dry-run")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("Plan").count(0));
+
+    dir.close().unwrap();
+}
+
+/// $ athena rollback --to 0 --dry-run
+#[test]
+#[serial]
+fn test_rollback_dry_run() {
+    let dir = setup_migrations!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("rollback")
+        .arg("--to")
+        .arg("0")
+        .arg("--dry-run")
+        .arg("--no-pretty")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-- V1 (init)"))
+        .stdout(predicate::str::contains(
+            "DROP DATABASE IF EXISTS `analytics`;",
+        ))
+        .stdout(predicate::str::is_match(r"1\s+DROP DATABASE").unwrap())
+        .stdout(predicate::str::contains("V2").count(0));
+
+    // Nothing newer than V1 is applied
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("rollback")
+        .arg("--to")
+        .arg("1")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Nothing to roll back"));
+
+    dir.close().unwrap();
+}
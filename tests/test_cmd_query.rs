@@ -0,0 +1,70 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs::write;
+use std::process::Command;
+use tempfile::tempdir;
+
+/// $ athena query "SELECT {{ undefined_var }}"
+/// The SQL is rendered before anything is sent to AWS
+#[test]
+#[serial]
+fn test_query_render_errors() {
+    let dir = tempdir().unwrap();
+    write(
+        dir.path().join("athena.toml"),
+        "[vars]\ndb = \"analytics\"\n",
+    )
+    .unwrap();
+    write(dir.path().join("daily.sql"), "SELECT 1;\nSELECT 2;").unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("query")
+        .arg("SELECT * FROM {{ db }}.{{ undefined_var }}")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("undefined_var"));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("query")
+        .arg("--file")
+        .arg("daily.sql")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains(
+            "expected one SQL statement, found 2",
+        ));
+
+    // SQL on stdin
+    let mut cmd = assert_cmd::Command::cargo_bin("athena").unwrap();
+    cmd.arg("query")
+        .write_stdin("-- nothing\n")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("no SQL statement to run"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena query "SELECT 1" --file q.sql
+#[test]
+#[serial]
+fn test_query_usage() {
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("query")
+        .arg("SELECT 1")
+        .arg("--file")
+        .arg("q.sql")
+        .assert()
+        .code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("query")
+        .arg("SELECT 1")
+        .arg("--result-format")
+        .arg("xml")
+        .assert()
+        .code(2);
+}
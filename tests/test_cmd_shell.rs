@@ -0,0 +1,35 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs::read_to_string;
+use tempfile::tempdir;
+
+/// $ athena shell
+/// Meta-commands and render errors do not need AWS, the history is saved on exit
+#[test]
+#[serial]
+fn test_shell_meta_commands() {
+    let dir = tempdir().unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("shell")
+        .arg("--database")
+        .arg("analytics")
+        .env("AWS_REGION", "us-east-1")
+        .write_stdin(
+            "\\use\n\\workgroup etl\n\\workgroup\n\\drop\nSELECT\n  {{ undefined_var }};\n\\q\n",
+        )
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Database: analytics"))
+        .stdout(predicate::str::contains("Workgroup: etl"))
+        .stderr(predicate::str::contains("unknown command \\drop"))
+        .stderr(predicate::str::contains("undefined_var"));
+
+    let history = read_to_string(dir.path().join(".athena/shell_history")).unwrap();
+    assert!(history.contains("{{ undefined_var }};"));
+
+    dir.close().unwrap();
+}
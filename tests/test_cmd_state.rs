@@ -0,0 +1,150 @@
+use assert_cmd::prelude::*;
+use indoc::indoc;
+use predicates::prelude::*;
+use serial_test::serial;
+use std::env::set_current_dir;
+use std::fs::{create_dir_all, read_to_string, rename, write, File};
+use std::io::Write;
+use std::process::Command;
+use tempfile::tempdir;
+
+const STATE: &str = indoc! { r#"
+    {
+      "version": 1,
+      "statements": [
+        {
+          "hash": "3f2a9c0000000000000000000000000000000000000000000000000000000001",
+          "database": null,
+          "kind": "CREATE DATABASE",
+          "source": "index.sql",
+          "query_execution_id": "id-1",
+          "applied_at": "2024-01-01T12:00:00Z"
+        },
+        {
+          "hash": "7b1e450000000000000000000000000000000000000000000000000000000002",
+          "database": "db",
+          "kind": "ALTER TABLE",
+          "query_execution_id": "id-2",
+          "applied_at": "2024-01-01T12:00:05Z"
+        }
+      ]
+    }
+"# };
+
+/// Create <temp>/.athena/state.json
+macro_rules! setup_state {
+    () => {{
+        let dir = tempdir().unwrap();
+
+        create_dir_all(dir.path().join(".athena")).expect("could not create dir");
+        let file_path = dir.path().join(".athena/state.json");
+        let mut file = File::create(file_path).expect("could not create temp file");
+        write!(file, "{}", STATE).expect("could not write to temp file");
+
+        dir
+    }};
+}
+
+/// $ athena state list
+#[test]
+#[serial]
+fn test_state_list() {
+    let dir = setup_state!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state")
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 statement(s)"))
+        .stdout(predicate::str::is_match(r"3f2a9c000000\s+\(default\)\s+-\s+CREATE DATABASE\s+index.sql\s+id-1\s+2024-01-01T12:00:00Z").unwrap())
+        .stdout(predicate::str::is_match(r"7b1e45000000\s+db\s+-\s+ALTER TABLE\s+-\s+id-2").unwrap());
+
+    dir.close().unwrap();
+}
+
+/// $ athena state rm 7b1e
+#[test]
+#[serial]
+fn test_state_rm() {
+    let dir = setup_state!();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state")
+        .arg("rm")
+        .arg("7b1e")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed 1 statement(s)"));
+
+    let state = read_to_string(dir.path().join(".athena/state.json")).unwrap();
+    assert!(state.contains("3f2a9c"));
+    assert!(!state.contains("7b1e45"));
+
+    // Unknown hash
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state")
+        .arg("rm")
+        .arg("ffff")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no statement matches `ffff`"));
+
+    // A hash or --all is required
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state").arg("rm").assert().code(2);
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state").arg("rm").arg("--all").assert().success();
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state")
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No statement applied yet"));
+
+    dir.close().unwrap();
+}
+
+/// $ athena state list --env prd
+/// Each environment has its own ledger: a statement applied on stg is not applied on prd
+#[test]
+#[serial]
+fn test_state_per_env() {
+    let dir = setup_state!();
+    write(
+        dir.path().join("athena.toml"),
+        "[env.stg]\nregion = \"us-east-1\"\n\n[env.prd]\nregion = \"us-east-1\"\n",
+    )
+    .unwrap();
+    rename(
+        dir.path().join(".athena/state.json"),
+        dir.path().join(".athena/state.stg.json"),
+    )
+    .unwrap();
+    assert!(set_current_dir(&dir).is_ok());
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state")
+        .arg("list")
+        .arg("--env")
+        .arg("stg")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("state.stg.json, 2 statement(s)"));
+
+    let mut cmd = Command::cargo_bin("athena").unwrap();
+    cmd.arg("state")
+        .arg("list")
+        .arg("--env")
+        .arg("prd")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No statement applied yet"))
+        .stdout(predicate::str::contains("state.prd.json"));
+
+    dir.close().unwrap();
+}